@@ -0,0 +1,197 @@
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::Arc,
+};
+
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use internal_prelude::library_prelude::*;
+
+use crate::{BusEvent, Publish, TopicPattern};
+
+#[rustfmt::skip]
+pub trait SubscriberFunc: Fn(&BusEvent) -> Result<()>
+    + Send
+    + Sync
+    + 'static
+    {}
+
+impl<F> SubscriberFunc for F where F: Fn(&BusEvent) -> Result<()> + Send + Sync + 'static {}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Debug)]
+pub struct SubscriptionId(u64);
+
+#[derive(Clone)]
+enum Target {
+    Func(Arc<dyn SubscriberFunc>),
+    Channel(Sender<BusEvent>),
+}
+
+struct Subscriber {
+    id:      SubscriptionId,
+    name:    String,
+    pattern: TopicPattern,
+    target:  Target,
+}
+
+#[derive(Default)]
+struct EventBusInner {
+    subscribers: Vec<Subscriber>,
+    last_id:     u64,
+}
+
+/// Daemon wide publish/subscribe of the events of all services.
+///
+/// Functions subscribed to a topic run in the thread publishing the event, in the order they subscribed.
+/// Their errors and panics are logged and never reach the publisher or other subscribers.
+/// Cloning the bus gives another handle to the same subscribers.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    inner: Arc<Mutex<EventBusInner>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run the function for every event whose topic matches the pattern.
+    pub fn subscribe(
+        &self,
+        pattern: &str,
+        name: &str,
+        f: impl SubscriberFunc,
+    ) -> Result<SubscriptionId> {
+        self.add(pattern, name, Target::Func(Arc::new(f)))
+    }
+
+    /// Receive every event whose topic matches the pattern.
+    /// The subscription ends when the Receiver is dropped.
+    pub fn channel(&self, pattern: &str) -> Result<(SubscriptionId, Receiver<BusEvent>)> {
+        let (sender, receiver) = unbounded();
+        let id = self.add(pattern, pattern, Target::Channel(sender))?;
+        Ok((id, receiver))
+    }
+
+    /// Returns false if there was no such subscription.
+    pub fn unsubscribe(&self, id: SubscriptionId) -> bool {
+        let mut inner = self.inner.lock();
+        let subscribers = inner.subscribers.len();
+        inner.subscribers.retain(|subscriber| subscriber.id != id);
+        inner.subscribers.len() != subscribers
+    }
+
+    /// Deliver the event to every matching subscriber, returns how many it was delivered to.
+    pub fn publish(&self, event: impl Publish) -> usize {
+        let event = event.to_bus_event();
+        let targets: Vec<(SubscriptionId, String, Target)> = self
+            .inner
+            .lock()
+            .subscribers
+            .iter()
+            .filter(|subscriber| subscriber.pattern.matches(&event.topic))
+            .map(|subscriber| {
+                (
+                    subscriber.id,
+                    subscriber.name.clone(),
+                    subscriber.target.clone(),
+                )
+            })
+            .collect();
+
+        let mut delivered = 0;
+        for (id, name, target) in targets {
+            match target {
+                Target::Func(f) => {
+                    match catch_unwind(AssertUnwindSafe(|| f(&event))) {
+                        Ok(Ok(())) => {}
+                        Ok(Err(err)) => log::error!(
+                            "Subscriber '{}' failed for {}: {:#}",
+                            name,
+                            event.topic,
+                            err
+                        ),
+                        Err(_) => log::error!("Subscriber '{}' panicked for {}", name, event.topic),
+                    }
+                    delivered += 1;
+                }
+                Target::Channel(sender) => {
+                    if sender.send(event.clone()).is_ok() {
+                        delivered += 1;
+                    } else {
+                        self.unsubscribe(id);
+                    }
+                }
+            }
+        }
+        delivered
+    }
+
+    fn add(&self, pattern: &str, name: &str, target: Target) -> Result<SubscriptionId> {
+        let pattern: TopicPattern = pattern.parse()?;
+        let mut inner = self.inner.lock();
+        inner.last_id += 1;
+        let id = SubscriptionId(inner.last_id);
+        inner.subscribers.push(Subscriber {
+            id,
+            name: name.to_string(),
+            pattern,
+            target,
+        });
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use internal_prelude::application_prelude::anyhow;
+
+    use crate::Category;
+
+    #[test]
+    fn test_publish_and_subscribe() {
+        let bus = EventBus::new();
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let received_clone = Arc::clone(&received);
+        let interfaces = bus
+            .subscribe("network.interface.*.down", "interfaces", move |event| {
+                received_clone.lock().push(event.topic.clone());
+                Ok(())
+            })
+            .unwrap();
+        bus.subscribe("network.**", "failing", |_| Err(anyhow!("smtp down")))
+            .unwrap();
+        let (_, everything) = bus.channel("**").unwrap();
+
+        let down =
+            BusEvent::new(Category::Network, &["interface", "wg0", "down"]).source("server-1");
+        assert_eq!(down.topic, "network.interface.wg0.down");
+        assert_eq!(bus.publish(&down), 3);
+        assert_eq!(
+            bus.publish(BusEvent::new(Category::Monitoring, &["disk", "disk_full"])),
+            1
+        );
+
+        assert!(bus.unsubscribe(interfaces));
+        assert!(!bus.unsubscribe(interfaces));
+        assert_eq!(bus.publish(&down), 2);
+
+        assert_eq!(*received.lock(), vec!["network.interface.wg0.down"]);
+        let topics: Vec<String> = everything.try_iter().map(|event| event.topic).collect();
+        assert_eq!(
+            topics,
+            vec![
+                "network.interface.wg0.down",
+                "monitoring.disk.disk_full",
+                "network.interface.wg0.down"
+            ]
+        );
+
+        drop(everything);
+        assert_eq!(bus.publish(&down), 1);
+        assert!(bus
+            .subscribe("network..down", "invalid", |_| Ok(()))
+            .is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use std::{collections::BTreeMap, fmt, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Which service an event comes from, the first segment of its topic.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Copy, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    Network,
+    Monitoring,
+    Notification,
+    System,
+}
+
+impl Category {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Category::Network => "network",
+            Category::Monitoring => "monitoring",
+            Category::Notification => "notification",
+            Category::System => "system",
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+pub type Values = BTreeMap<String, Value>;
+
+/// An event published on the EventBus, whatever service and type it originally is.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct BusEvent {
+    pub category:  Category,
+    /// Dot separated segments starting with the category, like `network.interface.wg0.down`.
+    pub topic:     String,
+    /// What published the event, like the host or the monitor.
+    pub source:    String,
+    pub timestamp: SystemTime,
+    pub values:    Values,
+}
+
+impl BusEvent {
+    /// An event on the topic made of the category and the segments.
+    /// Dots within a segment are replaced with underscores so each stays a single segment.
+    pub fn new(category: Category, segments: &[&str]) -> Self {
+        let topic = std::iter::once(category.as_str().to_string())
+            .chain(segments.iter().map(|segment| segment.replace('.', "_")))
+            .collect::<Vec<_>>()
+            .join(".");
+
+        BusEvent {
+            category,
+            topic,
+            source: String::new(),
+            timestamp: SystemTime::now(),
+            values: Values::new(),
+        }
+    }
+
+    pub fn source(mut self, source: &str) -> Self {
+        self.source = source.to_string();
+        self
+    }
+
+    pub fn at(mut self, timestamp: SystemTime) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn value(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.values.insert(name.to_string(), value.into());
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.values.get(name)
+    }
+}
+
+/// A typed event of a service that can be published on the EventBus.
+pub trait Publish {
+    fn to_bus_event(&self) -> BusEvent;
+}
+
+impl Publish for BusEvent {
+    fn to_bus_event(&self) -> BusEvent {
+        self.clone()
+    }
+}
+
+impl<T: Publish> Publish for &T {
+    fn to_bus_event(&self) -> BusEvent {
+        (*self).to_bus_event()
+    }
+}
@@ -0,0 +1,18 @@
+//! A daemon wide event bus, so the events of the networking, monitoring and notification services
+//! can be reacted to in one place regardless of their types.
+
+mod bus;
+mod event;
+mod pattern;
+
+use internal_prelude::library_prelude::*;
+
+pub use bus::{EventBus, SubscriberFunc, SubscriptionId};
+pub use event::{BusEvent, Category, Publish, Values};
+pub use pattern::TopicPattern;
+
+#[derive(Error, Debug)]
+pub enum EventBusError {
+    #[error("Invalid topic pattern '{0}'.")]
+    InvalidPattern(String),
+}
@@ -0,0 +1,117 @@
+use std::{fmt, str::FromStr};
+
+use crate::EventBusError;
+
+/// Selects topics by their segments.
+/// `*` within a segment matches any characters of that segment, a `**` segment matches any number of segments.
+/// For example `network.interface.*.down` or `monitoring.**`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct TopicPattern {
+    segments: Vec<String>,
+}
+
+impl TopicPattern {
+    pub fn matches(&self, topic: &str) -> bool {
+        let topic: Vec<&str> = topic.split('.').collect();
+        matches_segments(&self.segments, &topic)
+    }
+}
+
+fn matches_segments(pattern: &[String], topic: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => topic.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=topic.len()).any(|skipped| matches_segments(rest, &topic[skipped..]))
+        }
+        Some((first, rest)) => match topic.split_first() {
+            Some((segment, topic)) => glob(first, segment) && matches_segments(rest, topic),
+            None => false,
+        },
+    }
+}
+
+/// Whether the text matches the pattern, where `*` matches any number of characters.
+fn glob(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match text.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    match parts.split_last() {
+        // No wildcard at all
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(index) => rest = &rest[index + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.len() >= last.len() && rest.ends_with(last)
+        }
+    }
+}
+
+impl FromStr for TopicPattern {
+    type Err = EventBusError;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let segments: Vec<String> = pattern.trim().split('.').map(str::to_string).collect();
+        let invalid = segments
+            .iter()
+            .any(|segment| segment.is_empty() || (segment.contains("**") && segment != "**"));
+        if invalid {
+            return Err(EventBusError::InvalidPattern(pattern.to_string()));
+        }
+        Ok(TopicPattern { segments })
+    }
+}
+
+impl fmt::Display for TopicPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.segments.join("."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patterns() {
+        let matches =
+            |pattern: &str, topic: &str| pattern.parse::<TopicPattern>().unwrap().matches(topic);
+
+        assert!(matches(
+            "network.interface.wg0.down",
+            "network.interface.wg0.down"
+        ));
+        assert!(!matches(
+            "network.interface.wg0",
+            "network.interface.wg0.down"
+        ));
+        assert!(matches(
+            "network.interface.*.down",
+            "network.interface.eth0.down"
+        ));
+        assert!(matches(
+            "network.interface.wg*.down",
+            "network.interface.wg1.down"
+        ));
+        assert!(!matches(
+            "network.interface.wg*.down",
+            "network.interface.eth0.down"
+        ));
+        assert!(matches("monitoring.**", "monitoring.disk.disk_full"));
+        assert!(matches("**.down", "network.interface.wg0.down"));
+        assert!(matches("**", "system.started"));
+        assert!(matches("*.*.disk_*", "monitoring.disk.disk_full"));
+        assert!(!matches("*.disk_*", "monitoring.disk.disk_full"));
+
+        assert!("network..down".parse::<TopicPattern>().is_err());
+        assert!("network.**down".parse::<TopicPattern>().is_err());
+    }
+}
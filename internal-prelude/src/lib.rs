@@ -5,7 +5,7 @@ pub mod library_prelude {
     pub use itertools::Itertools;
     pub use lazy_static::lazy_static;
     pub use log;
-    pub use parking_lot::{Mutex, MutexGuard};
+    pub use parking_lot::{Condvar, Mutex, MutexGuard};
     pub use regex;
     pub use thiserror;
     pub use thiserror::Error;
@@ -35,7 +35,7 @@ mod cli_programs {
 
         fn is_installed(&self) -> bool {
             let output = process::Command::new("which")
-                .arg(&self.name())
+                .arg(self.name())
                 .output()
                 .expect("failed to execute `which` command");
 
@@ -0,0 +1,459 @@
+use std::{
+    any::Any,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::Arc,
+    thread::{sleep, spawn},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crossbeam::channel::{bounded, RecvTimeoutError};
+use internal_prelude::library_prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    throttle::{ThrottleDecision, ThrottleState},
+    Event, FiredEvent, Throttle,
+};
+
+#[rustfmt::skip]
+pub trait ActionFuncInternal<E: Event>: Fn(&FiredEvent<E>) -> Result<()>
+    + Send
+    + Sync
+    + 'static
+    {}
+
+impl<E: Event, F> ActionFuncInternal<E> for F where
+    F: Fn(&FiredEvent<E>) -> Result<()> + Send + Sync + 'static
+{
+}
+
+pub struct ActionFunc<E: Event> {
+    name:     String,
+    func:     Arc<dyn ActionFuncInternal<E>>,
+    policy:   ActionPolicy,
+    throttle: Option<Mutex<ThrottleState>>,
+    group:    Option<String>,
+}
+
+impl<E: Event> ActionFunc<E> {
+    pub fn new(name: &str, f: impl ActionFuncInternal<E>) -> Self {
+        Self::with_policy(name, f, ActionPolicy::default())
+    }
+
+    pub fn with_policy(name: &str, f: impl ActionFuncInternal<E>, policy: ActionPolicy) -> Self {
+        ActionFunc {
+            name: name.to_string(),
+            func: Arc::new(f),
+            policy,
+            throttle: None,
+            group: None,
+        }
+    }
+
+    /// Limit how often this action runs, independently of other actions for the same event.
+    pub fn throttle(&mut self, throttle: Throttle) -> &mut Self {
+        self.throttle = Some(Mutex::new(ThrottleState::new(throttle)));
+        self
+    }
+
+    /// Count the action against the concurrency limit of the group, see ConcurrencyLimits.
+    pub fn group(&mut self, group: &str) -> &mut Self {
+        self.group = Some(group.to_string());
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn group_name(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    /// Run the action for the event according to its ActionPolicy and Throttle.
+    /// Errors, panics and timeouts never escape, they are reported in the returned ActionReport.
+    pub(crate) fn run(&self, event: &FiredEvent<E>, now: Instant) -> ActionReport {
+        let mut event = event.clone();
+        if let Some(throttle) = &self.throttle {
+            match throttle.lock().check(&event, now) {
+                ThrottleDecision::Pass { suppressed } => event.suppressed += suppressed,
+                ThrottleDecision::Suppress => return self.suppressed(&event),
+            }
+        }
+        let event = &event;
+
+        let started = Instant::now();
+        let mut attempts = 0;
+        let mut backoff = self.policy.retry.initial_backoff;
+
+        let outcome = loop {
+            attempts += 1;
+            let outcome = self.attempt(event);
+            if outcome == ActionOutcome::Succeeded || attempts > self.policy.retry.max_retries {
+                break outcome;
+            }
+
+            log::info!(
+                "Action '{}' attempt {} for {:?} failed: {}, retrying in {:?}",
+                self.name,
+                attempts,
+                event.key(),
+                outcome,
+                backoff
+            );
+            sleep(backoff);
+            backoff = self.policy.retry.next_backoff(backoff);
+        };
+
+        let report = ActionReport {
+            action: self.name.clone(),
+            event: format!("{:?}", event.key()),
+            outcome,
+            attempts,
+            duration: started.elapsed(),
+            finished_at: SystemTime::now(),
+        };
+        report.log();
+        report
+    }
+
+    /// The report of the action being held back by a Throttle.
+    pub(crate) fn suppressed(&self, event: &FiredEvent<E>) -> ActionReport {
+        let report = ActionReport {
+            action:      self.name.clone(),
+            event:       format!("{:?}", event.key()),
+            outcome:     ActionOutcome::Suppressed,
+            attempts:    0,
+            duration:    Duration::from_secs(0),
+            finished_at: SystemTime::now(),
+        };
+        report.log();
+        report
+    }
+
+    fn attempt(&self, event: &FiredEvent<E>) -> ActionOutcome {
+        let timeout = match self.policy.timeout {
+            Some(timeout) => timeout,
+            None => {
+                return ActionOutcome::from_result(catch_unwind(AssertUnwindSafe(|| {
+                    (self.func)(event)
+                })))
+            }
+        };
+
+        // The action runs in its own thread so it can be abandoned when it times out.
+        // NOTE: An abandoned action keeps running in the background until it returns on its own.
+        let (sender, receiver) = bounded(1);
+        let func = Arc::clone(&self.func);
+        let event = event.clone();
+        spawn(move || {
+            let _ = sender.send(catch_unwind(AssertUnwindSafe(|| func(&event))));
+        });
+
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => ActionOutcome::from_result(result),
+            Err(RecvTimeoutError::Timeout) => ActionOutcome::TimedOut(timeout),
+            Err(RecvTimeoutError::Disconnected) => {
+                ActionOutcome::Panicked("action thread exited without a result".to_string())
+            }
+        }
+    }
+}
+
+/// Controls how failures of an ActionFunc are handled.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub struct ActionPolicy {
+    // Without a timeout the action runs in the polling thread and delays the following polls
+    timeout: Option<Duration>,
+    retry:   RetryPolicy,
+}
+
+impl ActionPolicy {
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn retry(&mut self, retry: RetryPolicy) -> &mut Self {
+        self.retry = retry;
+        self
+    }
+}
+
+/// Retry a failed action with an exponential backoff between attempts.
+///
+/// The backoff is slept in the thread dispatching the event, usually the polling thread,
+/// so retries delay the following polls of the monitor by up to all of their backoffs together.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct RetryPolicy {
+    max_retries:        u32,
+    initial_backoff:    Duration,
+    backoff_multiplier: u32,
+    max_backoff:        Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries:        0,
+            initial_backoff:    Duration::from_secs(1),
+            backoff_multiplier: 2,
+            max_backoff:        Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn max_retries(&mut self, max_retries: u32) -> &mut Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn initial_backoff(&mut self, initial_backoff: Duration) -> &mut Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    pub fn backoff_multiplier(&mut self, backoff_multiplier: u32) -> &mut Self {
+        self.backoff_multiplier = backoff_multiplier;
+        self
+    }
+
+    pub fn max_backoff(&mut self, max_backoff: Duration) -> &mut Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    fn next_backoff(&self, backoff: Duration) -> Duration {
+        backoff
+            .checked_mul(self.backoff_multiplier)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
+pub enum ActionOutcome {
+    Succeeded,
+    /// The action returned an error, contains the error and all of its causes.
+    Failed(Vec<String>),
+    TimedOut(Duration),
+    Panicked(String),
+    /// The action was held back by a Throttle and did not run.
+    Suppressed,
+}
+
+impl ActionOutcome {
+    fn from_result(result: std::thread::Result<Result<()>>) -> Self {
+        match result {
+            Ok(Ok(())) => ActionOutcome::Succeeded,
+            Ok(Err(err)) => {
+                ActionOutcome::Failed(err.chain().map(|cause| cause.to_string()).collect())
+            }
+            Err(panic) => ActionOutcome::Panicked(panic_message(panic)),
+        }
+    }
+}
+
+impl std::fmt::Display for ActionOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ActionOutcome::Succeeded => write!(f, "succeeded"),
+            ActionOutcome::Failed(error_chain) => write!(f, "failed: {}", error_chain.join(": ")),
+            ActionOutcome::TimedOut(timeout) => write!(f, "timed out after {:?}", timeout),
+            ActionOutcome::Panicked(message) => write!(f, "panicked: {}", message),
+            ActionOutcome::Suppressed => write!(f, "suppressed"),
+        }
+    }
+}
+
+pub(crate) fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+/// The result of running an action for an event, including all retries.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct ActionReport {
+    pub action:      String,
+    /// The EventKey the action was run for.
+    pub event:       String,
+    pub outcome:     ActionOutcome,
+    pub attempts:    u32,
+    pub duration:    Duration,
+    pub finished_at: SystemTime,
+}
+
+impl ActionReport {
+    fn log(&self) {
+        if self.outcome == ActionOutcome::Suppressed {
+            log::debug!("Action '{}' for {} suppressed", self.action, self.event);
+        } else if self.outcome == ActionOutcome::Succeeded {
+            log::debug!(
+                "Action '{}' for {} succeeded after {} attempt(s)",
+                self.action,
+                self.event,
+                self.attempts
+            );
+        } else {
+            log::error!(
+                "Action '{}' for {} {} after {} attempt(s)",
+                self.action,
+                self.event,
+                self.outcome,
+                self.attempts
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use crate::{MonitorEvent, MonitorId, Values};
+
+    use internal_prelude::application_prelude::anyhow;
+
+    type TestEvent = &'static str;
+
+    fn event() -> FiredEvent<TestEvent> {
+        FiredEvent::new(
+            MonitorEvent::Polled("disk_full"),
+            &MonitorId::new("disk"),
+            "server-1",
+            Values::new(),
+        )
+    }
+
+    #[test]
+    fn test_retries_until_success() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let calls_clone = Arc::clone(&calls);
+
+        let action = ActionFunc::with_policy(
+            "flaky",
+            move |_: &FiredEvent<TestEvent>| {
+                if calls_clone.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(anyhow!("not yet"))
+                } else {
+                    Ok(())
+                }
+            },
+            *ActionPolicy::default().retry(
+                *RetryPolicy::default()
+                    .max_retries(3)
+                    .initial_backoff(Duration::from_millis(1)),
+            ),
+        );
+
+        let report = action.run(&event(), Instant::now());
+        assert_eq!(report.outcome, ActionOutcome::Succeeded);
+        assert_eq!(report.attempts, 3);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_gives_up_after_max_retries() {
+        let action = ActionFunc::with_policy(
+            "broken",
+            |_: &FiredEvent<TestEvent>| Err(anyhow!("smtp down").context("sending email failed")),
+            *ActionPolicy::default().retry(
+                *RetryPolicy::default()
+                    .max_retries(1)
+                    .initial_backoff(Duration::from_millis(1)),
+            ),
+        );
+
+        let report = action.run(&event(), Instant::now());
+        assert_eq!(
+            report.outcome,
+            ActionOutcome::Failed(vec![
+                "sending email failed".to_string(),
+                "smtp down".to_string()
+            ])
+        );
+        assert_eq!(report.attempts, 2);
+        assert_eq!(report.action, "broken");
+        assert_eq!(report.event, "Polled(\"disk_full\")");
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let retry = *RetryPolicy::default()
+            .backoff_multiplier(u32::MAX)
+            .max_backoff(Duration::from_secs(60));
+        assert_eq!(
+            retry.next_backoff(Duration::from_secs(1)),
+            Duration::from_secs(60)
+        );
+        assert_eq!(
+            retry.next_backoff(Duration::from_secs(u64::MAX)),
+            Duration::from_secs(60)
+        );
+        assert_eq!(
+            RetryPolicy::default().next_backoff(Duration::from_secs(1)),
+            Duration::from_secs(2)
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let action = ActionFunc::with_policy(
+            "slow",
+            |_: &FiredEvent<TestEvent>| {
+                sleep(Duration::from_millis(500));
+                Ok(())
+            },
+            *ActionPolicy::default().timeout(Duration::from_millis(10)),
+        );
+
+        let report = action.run(&event(), Instant::now());
+        assert_eq!(
+            report.outcome,
+            ActionOutcome::TimedOut(Duration::from_millis(10))
+        );
+        assert!(report.duration < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_throttled() {
+        let mut action = ActionFunc::new("email", |_: &FiredEvent<TestEvent>| Ok(()));
+        action.throttle(
+            Throttle::default()
+                .cooldown(Duration::from_secs(60))
+                .clone(),
+        );
+
+        let now = Instant::now();
+        assert_eq!(action.run(&event(), now).outcome, ActionOutcome::Succeeded);
+        let report = action.run(&event(), now + Duration::from_secs(1));
+        assert_eq!(report.outcome, ActionOutcome::Suppressed);
+        assert_eq!(report.attempts, 0);
+    }
+
+    #[test]
+    fn test_panic_is_contained() {
+        let action = ActionFunc::new("panicky", |_: &FiredEvent<TestEvent>| panic!("boom"));
+        assert_eq!(
+            action.run(&event(), Instant::now()).outcome,
+            ActionOutcome::Panicked("boom".to_string())
+        );
+
+        let action = ActionFunc::with_policy(
+            "panicky_with_timeout",
+            |_: &FiredEvent<TestEvent>| panic!("boom"),
+            *ActionPolicy::default().timeout(Duration::from_secs(1)),
+        );
+        assert_eq!(
+            action.run(&event(), Instant::now()).outcome,
+            ActionOutcome::Panicked("boom".to_string())
+        );
+    }
+}
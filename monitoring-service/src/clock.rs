@@ -0,0 +1,77 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use internal_prelude::library_prelude::*;
+
+/// Where a PollingMonitor gets the current time from, the system clock unless testing.
+pub trait Clock: Send + Sync + 'static {
+    /// Monotonic time used for schedules, firing modes and throttles.
+    fn now(&self) -> Instant;
+
+    /// Wall clock time used for timestamps in payloads and history.
+    fn system_now(&self) -> SystemTime;
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when advanced, clones share the same time.
+#[derive(Clone, Debug)]
+pub struct VirtualClock {
+    start:        Instant,
+    system_start: SystemTime,
+    elapsed:      Arc<Mutex<Duration>>,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        VirtualClock {
+            start:        Instant::now(),
+            system_start: SystemTime::now(),
+            elapsed:      Arc::new(Mutex::new(Duration::from_secs(0))),
+        }
+    }
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How far the clock has been advanced since it was created.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock()
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock() += by;
+    }
+
+    /// Move the clock to the time since it was created, it never goes backwards.
+    pub fn advance_to(&self, elapsed: Duration) {
+        let mut current = self.elapsed.lock();
+        *current = (*current).max(elapsed);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.system_start + self.elapsed()
+    }
+}
@@ -0,0 +1,511 @@
+use std::{
+    collections::BTreeMap,
+    io::Read,
+    path::PathBuf,
+    process::{Command, Stdio},
+    thread::{sleep, spawn, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use internal_prelude::library_prelude::*;
+use serde_json::Value;
+
+use crate::{ActionFunc, Event, FiredEvent, Observation, PollingFunc};
+
+/// How much of stdout and stderr of a command is kept, the rest is read and dropped
+pub const OUTPUT_CAPTURED: usize = 64 * 1024;
+
+#[derive(Error, Debug)]
+pub enum CommandError {
+    #[error("Command `{0}` did not finish within {1:?} and was killed.")]
+    TimedOut(String, Duration),
+    #[error("Command `{command}` exited with {exit_code:?}: {stderr}")]
+    Failed {
+        command:   String,
+        exit_code: Option<i32>,
+        stderr:    String,
+    },
+    #[error("Output of command `{0}` did not match any rule.")]
+    NoMatchingRule(String),
+    #[error("Unknown placeholder '{{{{{0}}}}}' in command arguments.")]
+    UnknownPlaceholder(String),
+}
+
+/// A command to run directly, without a shell, as used by CommandMonitor and CommandAction.
+///
+/// Only PATH and explicitly allowed variables of the daemon's environment are passed on to the command.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CommandSpec {
+    program:     String,
+    args:        Vec<String>,
+    allowed_env: Vec<String>,
+    env:         BTreeMap<String, String>,
+    working_dir: Option<PathBuf>,
+    timeout:     Option<Duration>,
+}
+
+/// What a command did, stdout and stderr are cut off after OUTPUT_CAPTURED bytes.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct CommandOutput {
+    /// None if the command was terminated by a signal.
+    pub exit_code: Option<i32>,
+    pub stdout:    String,
+    pub stderr:    String,
+    pub duration:  Duration,
+}
+
+impl CommandSpec {
+    pub fn new(program: &str) -> Self {
+        CommandSpec {
+            program:     program.to_string(),
+            args:        Vec::new(),
+            allowed_env: vec!["PATH".to_string()],
+            env:         BTreeMap::new(),
+            working_dir: None,
+            timeout:     None,
+        }
+    }
+
+    pub fn arg(&mut self, arg: &str) -> &mut Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    pub fn args(&mut self, args: &[&str]) -> &mut Self {
+        self.args.extend(args.iter().map(|arg| arg.to_string()));
+        self
+    }
+
+    /// Pass on a variable of the daemon's environment, if it is set.
+    pub fn allow_env(&mut self, name: &str) -> &mut Self {
+        self.allowed_env.push(name.to_string());
+        self
+    }
+
+    /// Set a variable in the environment of the command.
+    pub fn env(&mut self, name: &str, value: &str) -> &mut Self {
+        self.env.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn working_dir(&mut self, working_dir: impl Into<PathBuf>) -> &mut Self {
+        self.working_dir = Some(working_dir.into());
+        self
+    }
+
+    /// Kill the command if it runs longer than this, by default it may run forever.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Run the command with the given arguments instead of the configured ones.
+    /// Exiting with a non-zero code is not an error, the caller decides what it means.
+    pub fn run_with_args(&self, args: &[String]) -> Result<CommandOutput> {
+        let mut command = Command::new(&self.program);
+        command
+            .args(args)
+            .env_clear()
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        for name in &self.allowed_env {
+            if let Some(value) = std::env::var_os(name) {
+                command.env(name, value);
+            }
+        }
+        command.envs(&self.env);
+        if let Some(working_dir) = &self.working_dir {
+            command.current_dir(working_dir);
+        }
+
+        let started = Instant::now();
+        let mut child = command.spawn()?;
+        let stdout = capture(child.stdout.take());
+        let stderr = capture(child.stderr.take());
+
+        let status = match self.timeout {
+            None => child.wait()?,
+            Some(timeout) => loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if started.elapsed() >= timeout {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    // The output is abandoned, children of the command could keep the pipes open
+                    return Err(CommandError::TimedOut(self.to_string(), timeout).into());
+                }
+                sleep(Duration::from_millis(10));
+            },
+        };
+
+        Ok(CommandOutput {
+            exit_code: status.code(),
+            stdout:    stdout.join().unwrap_or_default(),
+            stderr:    stderr.join().unwrap_or_default(),
+            duration:  started.elapsed(),
+        })
+    }
+
+    pub fn run(&self) -> Result<CommandOutput> {
+        self.run_with_args(&self.args)
+    }
+}
+
+impl std::fmt::Display for CommandSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+fn capture(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<String> {
+    spawn(move || {
+        let mut captured = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = (&mut pipe)
+                .take(OUTPUT_CAPTURED as u64)
+                .read_to_end(&mut captured);
+            // Keep reading so the command does not block on a full pipe
+            let _ = std::io::copy(&mut pipe, &mut std::io::sink());
+        }
+        String::from_utf8_lossy(&captured).into_owned()
+    })
+}
+
+enum OutputRule {
+    ExitCode(i32),
+    Stdout(regex::Regex),
+    Json { pointer: String, value: Value },
+}
+
+impl OutputRule {
+    fn matches(&self, output: &CommandOutput, json: Option<&Value>) -> bool {
+        match self {
+            OutputRule::ExitCode(exit_code) => output.exit_code == Some(*exit_code),
+            OutputRule::Stdout(regex) => regex.is_match(&output.stdout),
+            OutputRule::Json { pointer, value } => {
+                json.and_then(|json| json.pointer(pointer)) == Some(value)
+            }
+        }
+    }
+}
+
+/// Polls by running a command and turning its exit code or output into an event with the first matching rule.
+///
+/// The exit code, stdout and stderr are attached as values, along with the named groups of a matching
+/// stdout regex and the fields of stdout when it is a JSON object.
+/// A command that times out, or whose output matches no rule, fails the poll.
+pub struct CommandMonitor<E: Event> {
+    command:   CommandSpec,
+    rules:     Vec<(OutputRule, E)>,
+    otherwise: Option<E>,
+}
+
+impl<E: Event> CommandMonitor<E> {
+    pub fn new(command: CommandSpec) -> Self {
+        CommandMonitor {
+            command,
+            rules: Vec::new(),
+            otherwise: None,
+        }
+    }
+
+    pub fn on_exit_code(&mut self, exit_code: i32, event: E) -> &mut Self {
+        self.rules.push((OutputRule::ExitCode(exit_code), event));
+        self
+    }
+
+    /// Fire the event when the regex matches somewhere in stdout.
+    pub fn on_stdout(&mut self, pattern: &str, event: E) -> Result<&mut Self> {
+        self.rules
+            .push((OutputRule::Stdout(regex::Regex::new(pattern)?), event));
+        Ok(self)
+    }
+
+    /// Fire the event when stdout is JSON with the value at the pointer, like `/status`.
+    pub fn on_json(&mut self, pointer: &str, value: impl Into<Value>, event: E) -> &mut Self {
+        self.rules.push((
+            OutputRule::Json {
+                pointer: pointer.to_string(),
+                value:   value.into(),
+            },
+            event,
+        ));
+        self
+    }
+
+    /// The event when no rule matches.
+    pub fn otherwise(&mut self, event: E) -> &mut Self {
+        self.otherwise = Some(event);
+        self
+    }
+
+    pub fn into_polling_func(self) -> PollingFunc<E> {
+        PollingFunc::with_payload(move || {
+            let output = self.command.run()?;
+            self.evaluate(&output)
+        })
+    }
+
+    pub(crate) fn evaluate(&self, output: &CommandOutput) -> Result<Observation<E>> {
+        let json = serde_json::from_str::<Value>(&output.stdout).ok();
+
+        let (rule, event) = self
+            .rules
+            .iter()
+            .find(|(rule, _)| rule.matches(output, json.as_ref()))
+            .map(|(rule, event)| (Some(rule), event))
+            .or_else(|| self.otherwise.as_ref().map(|event| (None, event)))
+            .ok_or_else(|| CommandError::NoMatchingRule(self.command.to_string()))?;
+
+        let mut observation = Observation::new(event.clone());
+        if let Some(Value::Object(fields)) = json {
+            for (name, value) in fields {
+                observation = observation.value(&name, value);
+            }
+        }
+        if let Some(OutputRule::Stdout(regex)) = rule {
+            if let Some(captures) = regex.captures(&output.stdout) {
+                for name in regex.capture_names().flatten() {
+                    if let Some(capture) = captures.name(name) {
+                        observation = observation.value(name, capture.as_str());
+                    }
+                }
+            }
+        }
+
+        Ok(observation
+            .value("exit_code", output.exit_code)
+            .value("stdout", output.stdout.trim())
+            .value("stderr", output.stderr.trim()))
+    }
+}
+
+/// An action that runs a command, its arguments can contain placeholders like `{{interface}}`.
+///
+/// Placeholders are replaced by the payload value of the same name, or by `monitor_id`, `host`, `event`,
+/// `timestamp` (seconds since the epoch) or `suppressed`. Subscribed to the EventBus with PollingMonitor::subscribe,
+/// the values also include the `topic`, `category` and `source` of the event. The command fails the action when it exits
+/// with a non-zero code, with its stderr as the error. On success stdout is logged at the debug level.
+///
+/// Unlike the ActionPolicy timeout, the timeout of the CommandSpec kills the command.
+pub struct CommandAction {
+    command: CommandSpec,
+}
+
+impl CommandAction {
+    pub fn new(command: CommandSpec) -> Self {
+        CommandAction { command }
+    }
+
+    pub fn into_action_func<E: Event>(self, name: &str) -> ActionFunc<E> {
+        ActionFunc::new(name, move |event: &FiredEvent<E>| self.run(event))
+    }
+
+    fn run<E: Event>(&self, event: &FiredEvent<E>) -> Result<()> {
+        self.run_rendered(|template| render(template, event))
+    }
+
+    fn run_rendered(&self, render: impl Fn(&str) -> Result<String>) -> Result<()> {
+        let args = self
+            .command
+            .args
+            .iter()
+            .map(|arg| render(arg))
+            .collect::<Result<Vec<String>>>()?;
+
+        let output = self.command.run_with_args(&args)?;
+        if output.exit_code != Some(0) {
+            return Err(CommandError::Failed {
+                command:   self.command.to_string(),
+                exit_code: output.exit_code,
+                stderr:    output.stderr.trim().to_string(),
+            }
+            .into());
+        }
+
+        log::debug!(
+            "Command `{}` succeeded with output: {}",
+            self.command,
+            output.stdout.trim()
+        );
+        Ok(())
+    }
+}
+
+fn seconds_since_epoch(timestamp: SystemTime) -> String {
+    timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        .to_string()
+}
+
+fn value_to_string(name: &str, value: Option<&Value>) -> Result<String> {
+    match value {
+        Some(Value::String(value)) => Ok(value.clone()),
+        Some(value) => Ok(value.to_string()),
+        None => Err(CommandError::UnknownPlaceholder(name.to_string()).into()),
+    }
+}
+
+/// Replace the placeholders in a templated argument with values of the event.
+fn render<E: Event>(template: &str, event: &FiredEvent<E>) -> Result<String> {
+    render_with(template, |name| match name {
+        "monitor_id" => Ok(event.payload.monitor_id.to_string()),
+        "host" => Ok(event.payload.host.clone()),
+        "event" => Ok(format!("{:?}", event.key())),
+        "timestamp" => Ok(seconds_since_epoch(event.payload.timestamp)),
+        "suppressed" => Ok(event.suppressed.to_string()),
+        _ => value_to_string(name, event.value(name)),
+    })
+}
+
+fn render_with(template: &str, value: impl Fn(&str) -> Result<String>) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut last_end = 0;
+    for captures in PLACEHOLDER_RE.captures_iter(template) {
+        let placeholder = captures.get(0).unwrap();
+        rendered.push_str(&template[last_end..placeholder.start()]);
+        rendered.push_str(&value(&captures[1])?);
+        last_end = placeholder.end();
+    }
+    rendered.push_str(&template[last_end..]);
+    Ok(rendered)
+}
+
+lazy_static! {
+    static ref PLACEHOLDER_RE: regex::Regex =
+        regex::Regex::new(r"\{\{\s*([A-Za-z0-9_.-]+)\s*\}\}").unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MonitorEvent, MonitorId, Values};
+    use event_bus::BusEvent;
+
+    fn sh(script: &str) -> CommandSpec {
+        CommandSpec::new("sh").args(&["-c", script]).clone()
+    }
+
+    fn event_with(values: &[(&str, &str)]) -> FiredEvent<&'static str> {
+        let mut payload = Values::new();
+        for (name, value) in values {
+            payload.insert(name.to_string(), Value::from(*value));
+        }
+        FiredEvent::new(
+            MonitorEvent::Polled("down"),
+            &MonitorId::new("wg0"),
+            "server-1",
+            payload,
+        )
+    }
+
+    #[test]
+    fn test_command_monitor_rules() {
+        let mut monitor = CommandMonitor::new(sh("true"));
+        monitor
+            .on_json("/status", "degraded", "degraded")
+            .on_stdout(r"(?P<interface>\w+) is down", "down")
+            .unwrap()
+            .on_exit_code(0, "up")
+            .otherwise("unknown");
+
+        let evaluate = |script: &str| monitor.evaluate(&sh(script).run().unwrap()).unwrap();
+
+        let observation = evaluate("echo wg0 is down; exit 1");
+        assert_eq!(observation.event, "down");
+        assert_eq!(observation.values["interface"], Value::from("wg0"));
+        assert_eq!(observation.values["exit_code"], Value::from(1));
+
+        let observation = evaluate(r#"echo '{"status": "degraded", "peers": 2}'"#);
+        assert_eq!(observation.event, "degraded");
+        assert_eq!(observation.values["peers"], Value::from(2));
+
+        assert_eq!(evaluate("echo all good").event, "up");
+        assert_eq!(
+            evaluate("echo oops >&2; exit 3").values["stderr"],
+            Value::from("oops")
+        );
+        assert_eq!(evaluate("exit 3").event, "unknown");
+
+        let mut strict = CommandMonitor::new(sh("exit 3"));
+        strict.on_exit_code(0, "up");
+        assert!(strict.evaluate(&sh("exit 3").run().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_command_timeout() {
+        let started = Instant::now();
+        let err = sh("sleep 5")
+            .timeout(Duration::from_millis(50))
+            .run()
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CommandError>(),
+            Some(CommandError::TimedOut(..))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_command_action() {
+        let action = |script: &str| {
+            CommandAction::new(
+                CommandSpec::new("sh")
+                    .args(&["-c", script, "sh", "{{ interface }}", "{{monitor_id}}"])
+                    .env("GREETING", "hello")
+                    .working_dir("/")
+                    .clone(),
+            )
+        };
+        let event = event_with(&[("interface", "wg0")]);
+
+        assert!(action(r#"test "$1 $2" = "wg0 wg0""#).run(&event).is_ok());
+        assert!(action(r#"test "$GREETING" = hello && test -z "$HOME""#)
+            .run(&event)
+            .is_ok());
+        assert!(action(r#"test "$(pwd)" = /"#).run(&event).is_ok());
+
+        let err = action("echo broken >&2; exit 2").run(&event).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Command `sh -c echo broken >&2; exit 2 sh {{ interface }} {{monitor_id}}` exited with Some(2): broken"
+        );
+
+        let err = action("true").run(&event_with(&[])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown placeholder '{{interface}}' in command arguments."
+        );
+    }
+
+    #[test]
+    fn test_bus_event_placeholders() {
+        let file = std::env::temp_dir().join(format!("command-bus-event-{}", std::process::id()));
+        let action = CommandAction::new(
+            sh(r#"echo "$1 $2 $3" > "$4""#)
+                .args(&["sh", "{{topic}}", "{{source}}", "{{interface}}"])
+                .arg(file.to_str().unwrap())
+                .clone(),
+        );
+
+        let event = FiredEvent::of_bus_event(
+            &BusEvent::new(event_bus::Category::Network, &["interface", "wg0", "down"])
+                .source("server-1")
+                .value("interface", "wg0"),
+        );
+        action.run(&event).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&file).unwrap(),
+            "network.interface.wg0.down server-1 wg0\n"
+        );
+        std::fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,375 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::{Event, EventKey, MonitorId, Values};
+
+/// A condition on the events of one or more polling functions of the same PollingMonitor.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Condition<E: Event> {
+    /// Without a window the latest event polled by the monitor, in any of its series, is the event.
+    /// With a window the monitor polled the event at some point within it.
+    Observed {
+        monitor: MonitorId,
+        event:   EventKey<E>,
+        within:  Option<Duration>,
+    },
+    All(Vec<Condition<E>>),
+    Any(Vec<Condition<E>>),
+    /// At least N of the conditions are met.
+    AtLeast(usize, Vec<Condition<E>>),
+}
+
+impl<E: Event> Condition<E> {
+    pub fn observed(monitor: impl Into<MonitorId>, event: impl Into<EventKey<E>>) -> Self {
+        Condition::Observed {
+            monitor: monitor.into(),
+            event:   event.into(),
+            within:  None,
+        }
+    }
+
+    pub fn all(conditions: Vec<Condition<E>>) -> Self {
+        Condition::All(conditions)
+    }
+
+    pub fn any(conditions: Vec<Condition<E>>) -> Self {
+        Condition::Any(conditions)
+    }
+
+    pub fn at_least(count: usize, conditions: Vec<Condition<E>>) -> Self {
+        Condition::AtLeast(count, conditions)
+    }
+
+    /// Set the time window of every Observed condition within this one that has none yet.
+    pub fn within(self, window: Duration) -> Self {
+        match self {
+            Condition::Observed {
+                monitor,
+                event,
+                within,
+            } => Condition::Observed {
+                monitor,
+                event,
+                within: within.or(Some(window)),
+            },
+            Condition::All(conditions) => Condition::All(Self::all_within(conditions, window)),
+            Condition::Any(conditions) => Condition::Any(Self::all_within(conditions, window)),
+            Condition::AtLeast(count, conditions) => {
+                Condition::AtLeast(count, Self::all_within(conditions, window))
+            }
+        }
+    }
+
+    fn all_within(conditions: Vec<Condition<E>>, window: Duration) -> Vec<Condition<E>> {
+        conditions
+            .into_iter()
+            .map(|condition| condition.within(window))
+            .collect()
+    }
+
+    fn is_met(&self, observations: &Observations<E>, now: Instant) -> bool {
+        let met = |conditions: &[Condition<E>]| {
+            conditions
+                .iter()
+                .filter(|condition| condition.is_met(observations, now))
+                .count()
+        };
+
+        match self {
+            Condition::Observed {
+                monitor,
+                event,
+                within: None,
+            } => observations.latest_is(monitor, event),
+            Condition::Observed {
+                monitor,
+                event,
+                within: Some(window),
+            } => observations
+                .last_seen
+                .get(&(monitor.clone(), event.clone()))
+                .is_some_and(|seen| now.duration_since(*seen) <= *window),
+            Condition::All(conditions) => met(conditions) == conditions.len(),
+            Condition::Any(conditions) => met(conditions) > 0,
+            Condition::AtLeast(count, conditions) => met(conditions) >= *count,
+        }
+    }
+}
+
+impl<E: Event> fmt::Display for Condition<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter<'_>, conditions: &[Condition<E>], separator: &str| {
+            write!(f, "(")?;
+            for (index, condition) in conditions.iter().enumerate() {
+                if index > 0 {
+                    write!(f, "{}", separator)?;
+                }
+                write!(f, "{}", condition)?;
+            }
+            write!(f, ")")
+        };
+
+        match self {
+            Condition::Observed {
+                monitor,
+                event,
+                within,
+            } => {
+                write!(f, "{}: {:?}", monitor, event)?;
+                if let Some(within) = within {
+                    write!(f, " within {:?}", within)?;
+                }
+                Ok(())
+            }
+            Condition::All(conditions) => join(f, conditions, " AND "),
+            Condition::Any(conditions) => join(f, conditions, " OR "),
+            Condition::AtLeast(count, conditions) => {
+                write!(f, "{} OF ", count)?;
+                join(f, conditions, ", ")
+            }
+        }
+    }
+}
+
+/// An event of its own that is emitted whenever its Condition starts being met,
+/// and optionally a clear event that is emitted when the condition stops being met.
+///
+/// Conditions are evaluated after every poll of the PollingMonitor, so a time window running out
+/// is noticed at the next poll of any of its polling functions.
+#[derive(PartialEq, Clone, Debug)]
+pub struct CompositeEvent<E: Event> {
+    event:       E,
+    clear_event: Option<E>,
+    condition:   Condition<E>,
+}
+
+impl<E: Event> CompositeEvent<E> {
+    pub fn new(event: E, condition: Condition<E>) -> Self {
+        CompositeEvent {
+            event,
+            clear_event: None,
+            condition,
+        }
+    }
+
+    pub fn clear_event(&mut self, clear_event: E) -> &mut Self {
+        self.clear_event = Some(clear_event);
+        self
+    }
+}
+
+/// What the polling functions of a PollingMonitor observed, as needed to evaluate Conditions.
+pub(crate) struct Observations<E: Event> {
+    // The latest event of each series of each monitor
+    latest:    HashMap<(MonitorId, String), EventKey<E>>,
+    last_seen: HashMap<(MonitorId, EventKey<E>), Instant>,
+}
+
+impl<E: Event> Observations<E> {
+    /// Whether the latest event of the monitor, in any of its series, is the event.
+    pub(crate) fn latest_is(&self, monitor: &MonitorId, event: &EventKey<E>) -> bool {
+        self.latest
+            .iter()
+            .any(|((id, _), latest)| id == monitor && latest == event)
+    }
+}
+
+/// The CompositeEvents of a PollingMonitor along with the observations they are evaluated on.
+pub(crate) struct Composites<E: Event> {
+    composites:   Vec<(MonitorId, CompositeEvent<E>, bool)>,
+    observations: Observations<E>,
+}
+
+impl<E: Event> Default for Composites<E> {
+    fn default() -> Self {
+        Composites {
+            composites:   Vec::new(),
+            observations: Observations {
+                latest:    HashMap::new(),
+                last_seen: HashMap::new(),
+            },
+        }
+    }
+}
+
+impl<E: Event> Composites<E> {
+    pub(crate) fn add(&mut self, id: MonitorId, composite: CompositeEvent<E>) {
+        self.composites.retain(|(existing, _, _)| *existing != id);
+        self.composites.push((id, composite, false));
+    }
+
+    pub(crate) fn observations(&self) -> &Observations<E> {
+        &self.observations
+    }
+
+    pub(crate) fn observe(
+        &mut self,
+        monitor: &MonitorId,
+        series: &str,
+        event: EventKey<E>,
+        now: Instant,
+    ) {
+        self.observations
+            .last_seen
+            .insert((monitor.clone(), event.clone()), now);
+        self.observations
+            .latest
+            .insert((monitor.clone(), series.to_string()), event);
+    }
+
+    /// The composite events whose conditions started or stopped being met since the last evaluation.
+    ///
+    /// Composites are evaluated in the order they were added and what they emit is observed right away,
+    /// so a condition can use composites added before it.
+    pub(crate) fn evaluate(&mut self, now: Instant) -> Vec<(MonitorId, E, Values)> {
+        let mut emitted = Vec::new();
+        for index in 0..self.composites.len() {
+            let (id, composite, was_met) = &self.composites[index];
+            let is_met = composite.condition.is_met(&self.observations, now);
+            if is_met == *was_met {
+                continue;
+            }
+
+            let event = if is_met {
+                Some(composite.event.clone())
+            } else {
+                composite.clear_event.clone()
+            };
+            let id = id.clone();
+            let condition = composite.condition.to_string();
+            self.composites[index].2 = is_met;
+
+            if let Some(event) = event {
+                self.observe(&id, "", EventKey::Polled(event.clone()), now);
+                let mut values = Values::new();
+                values.insert("condition".to_string(), condition.into());
+                emitted.push((id, event, values));
+            }
+        }
+        emitted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_support::{ActionRecorder, MonitorHarness, ScriptedPolling},
+        PollingMonitor, PollingSchedule, VirtualClock,
+    };
+
+    #[test]
+    fn test_at_least() {
+        let mut composites = Composites::default();
+        composites.add(
+            MonitorId::new("health"),
+            CompositeEvent::new(
+                "degraded",
+                Condition::at_least(
+                    2,
+                    vec![
+                        Condition::observed("a", EventKey::PollFailed),
+                        Condition::observed("b", EventKey::PollFailed),
+                        Condition::observed("c", EventKey::PollFailed),
+                    ],
+                ),
+            )
+            .clear_event("healthy")
+            .clone(),
+        );
+
+        let now = Instant::now();
+        let mut poll = |monitor: &str, event: EventKey<&'static str>| {
+            composites.observe(&MonitorId::new(monitor), "", event, now);
+            composites
+                .evaluate(now)
+                .into_iter()
+                .map(|(_, event, _)| event)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(poll("a", EventKey::PollFailed).is_empty());
+        assert!(poll("b", EventKey::Polled("ok")).is_empty());
+        assert_eq!(poll("c", EventKey::PollFailed), vec!["degraded"]);
+        assert!(poll("b", EventKey::PollFailed).is_empty());
+        assert!(poll("a", EventKey::Polled("ok")).is_empty());
+        assert_eq!(poll("c", EventKey::PollRecovered), vec!["healthy"]);
+    }
+
+    #[test]
+    fn test_display() {
+        let condition: Condition<&'static str> = Condition::all(vec![
+            Condition::observed("public_ip", "changed"),
+            Condition::any(vec![
+                Condition::observed("wg0", "down"),
+                Condition::observed("wg1", "down").within(Duration::from_secs(60)),
+            ]),
+        ])
+        .within(Duration::from_secs(300));
+        assert_eq!(
+            condition.to_string(),
+            "(public_ip: Polled(\"changed\") within 300s AND (wg0: Polled(\"down\") within 300s OR wg1: Polled(\"down\") within 60s))"
+        );
+    }
+
+    #[test]
+    fn test_composite_with_window() {
+        let clock = VirtualClock::new();
+        let recorder = ActionRecorder::new(&clock);
+        let every_10s = *PollingSchedule::default().interval(Duration::from_secs(10));
+
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .schedule_named_polling(
+                "public_ip",
+                every_10s,
+                ScriptedPolling::new()
+                    .event("unchanged")
+                    .event("changed")
+                    .event("unchanged")
+                    .into_polling_func(),
+            )
+            .schedule_named_polling(
+                "wg0",
+                every_10s,
+                ScriptedPolling::new()
+                    .events("up", 3)
+                    .event("down")
+                    .into_polling_func(),
+            )
+            .composite(
+                "vpn",
+                CompositeEvent::new(
+                    "vpn_broken",
+                    Condition::all(vec![
+                        Condition::observed("public_ip", "changed").within(Duration::from_secs(30)),
+                        Condition::observed("wg0", "down"),
+                    ]),
+                )
+                .clear_event("vpn_ok")
+                .clone(),
+            )
+            .register_action("vpn_broken", recorder.action("page"))
+            .register_action("vpn_ok", recorder.action("page"));
+
+        let mut harness = MonitorHarness::new(monitor, &clock);
+        harness.advance(Duration::from_secs(60));
+
+        assert_eq!(
+            recorder.timeline(),
+            vec![
+                (30, "page".to_string(), EventKey::Polled("vpn_broken")),
+                (50, "page".to_string(), EventKey::Polled("vpn_ok")),
+            ]
+        );
+        let invocation = &recorder.invocations()[0];
+        assert_eq!(invocation.event.payload.monitor_id, MonitorId::new("vpn"));
+        assert_eq!(
+            invocation.event.value("condition"),
+            Some(&"(public_ip: Polled(\"changed\") within 30s AND wg0: Polled(\"down\"))".into())
+        );
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use internal_prelude::library_prelude::*;
+use serde::Deserialize;
+
+use crate::{composite::Observations, Event, EventKey, MonitorId};
+
+/// A polling function whose failure makes the events of a dependent polling function meaningless,
+/// like every HTTP check failing while the uplink interface is down.
+///
+/// The parent is failing while its latest poll failed, or while its latest event is one of the failing events.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Dependency<E: Event> {
+    parent:     MonitorId,
+    failing_on: Vec<EventKey<E>>,
+}
+
+impl<E: Event> Dependency<E> {
+    pub fn new(parent: impl Into<MonitorId>) -> Self {
+        Dependency {
+            parent:     parent.into(),
+            failing_on: vec![EventKey::PollFailed],
+        }
+    }
+
+    /// Also consider the parent failing while its latest event is this one.
+    pub fn failing_on(mut self, event: impl Into<EventKey<E>>) -> Self {
+        self.failing_on.push(event.into());
+        self
+    }
+
+    pub fn parent(&self) -> &MonitorId {
+        &self.parent
+    }
+
+    fn is_failing(&self, observations: &Observations<E>) -> bool {
+        self.failing_on
+            .iter()
+            .any(|event| observations.latest_is(&self.parent, event))
+    }
+}
+
+/// What happens to the events of a polling function while one of its parents is failing.
+#[derive(Deserialize, PartialEq, Eq, Copy, Clone, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WhenParentFailing {
+    /// The events are recorded in the history but no actions run for them.
+    #[default]
+    Suppress,
+    /// Actions run as usual, with the failing parent as the `parent_failing` value.
+    Annotate,
+}
+
+#[derive(Error, Debug)]
+pub enum DependencyError {
+    #[error("Monitor dependencies form a cycle: {0}.")]
+    Cycle(String),
+    #[error("Monitor {0} depends on {1} which does not exist.")]
+    UnknownParent(MonitorId, MonitorId),
+}
+
+/// Which monitors depend on which, used to make sure dependencies form no cycles.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct DependencyGraph {
+    parents: BTreeMap<MonitorId, BTreeSet<MonitorId>>,
+}
+
+impl DependencyGraph {
+    pub fn add(&mut self, child: impl Into<MonitorId>, parent: impl Into<MonitorId>) -> &mut Self {
+        self.parents
+            .entry(child.into())
+            .or_default()
+            .insert(parent.into());
+        self
+    }
+
+    pub fn remove(&mut self, child: &MonitorId) -> &mut Self {
+        self.parents.remove(child);
+        self
+    }
+
+    /// Every parent must be one of the known monitors.
+    pub fn validate_parents(&self, known: &BTreeSet<MonitorId>) -> Result<(), DependencyError> {
+        for (child, parents) in &self.parents {
+            if let Some(parent) = parents.iter().find(|parent| !known.contains(*parent)) {
+                return Err(DependencyError::UnknownParent(
+                    child.clone(),
+                    parent.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), DependencyError> {
+        let mut done = BTreeSet::new();
+        for child in self.parents.keys() {
+            let mut path = Vec::new();
+            if self.find_cycle(child, &mut path, &mut done) {
+                return Err(DependencyError::Cycle(path.iter().join(" -> ")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Depth first search, on finding a cycle the path ends with the monitor that closes it.
+    fn find_cycle<'a>(
+        &'a self,
+        id: &'a MonitorId,
+        path: &mut Vec<&'a MonitorId>,
+        done: &mut BTreeSet<&'a MonitorId>,
+    ) -> bool {
+        if let Some(start) = path.iter().position(|on_path| *on_path == id) {
+            path.drain(..start);
+            path.push(id);
+            return true;
+        }
+        if done.contains(id) {
+            return false;
+        }
+
+        path.push(id);
+        for parent in self.parents.get(id).into_iter().flatten() {
+            if self.find_cycle(parent, path, done) {
+                return true;
+            }
+        }
+        path.pop();
+        done.insert(id);
+        false
+    }
+}
+
+/// The dependencies of the polling functions of a running PollingMonitor.
+pub(crate) struct Dependencies<E: Event> {
+    graph:        DependencyGraph,
+    dependencies: HashMap<MonitorId, (Vec<Dependency<E>>, WhenParentFailing)>,
+}
+
+impl<E: Event> Default for Dependencies<E> {
+    fn default() -> Self {
+        Dependencies {
+            graph:        DependencyGraph::default(),
+            dependencies: HashMap::new(),
+        }
+    }
+}
+
+impl<E: Event> Dependencies<E> {
+    /// Add the dependencies of a polling function, unless they would form a cycle.
+    pub(crate) fn add(
+        &mut self,
+        id: &MonitorId,
+        dependencies: Vec<Dependency<E>>,
+        when_parent_failing: WhenParentFailing,
+    ) -> Result<(), DependencyError> {
+        if dependencies.is_empty() {
+            return Ok(());
+        }
+
+        let mut graph = self.graph.clone();
+        for dependency in &dependencies {
+            graph.add(id.clone(), dependency.parent.clone());
+        }
+        graph.validate()?;
+
+        self.graph = graph;
+        self.dependencies
+            .insert(id.clone(), (dependencies, when_parent_failing));
+        Ok(())
+    }
+
+    pub(crate) fn remove(&mut self, id: &MonitorId) {
+        self.graph.remove(id);
+        self.dependencies.remove(id);
+    }
+
+    /// The nearest failing ancestor of the polling function, if any, and what to do about it.
+    pub(crate) fn failing_parent(
+        &self,
+        id: &MonitorId,
+        observations: &Observations<E>,
+    ) -> Option<(MonitorId, WhenParentFailing)> {
+        let (dependencies, when_parent_failing) = self.dependencies.get(id)?;
+        dependencies
+            .iter()
+            .find(|dependency| dependency.is_failing(observations))
+            .map(|dependency| dependency.parent.clone())
+            .or_else(|| {
+                dependencies.iter().find_map(|dependency| {
+                    self.failing_parent(&dependency.parent, observations)
+                        .map(|(ancestor, _)| ancestor)
+                })
+            })
+            .map(|parent| (parent, *when_parent_failing))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_support::{ActionRecorder, MonitorHarness, ScriptedPolling},
+        PollingMonitor, PollingSchedule, VirtualClock,
+    };
+    use serde_json::Value;
+    use std::time::Duration;
+
+    #[test]
+    fn test_cycles() {
+        let mut graph = DependencyGraph::default();
+        graph
+            .add("http", "public_ip")
+            .add("dns", "public_ip")
+            .add("public_ip", "uplink");
+        assert!(graph.validate().is_ok());
+
+        let known = ["http", "dns", "public_ip"]
+            .iter()
+            .map(|id| MonitorId::new(id))
+            .collect();
+        assert_eq!(
+            graph.validate_parents(&known).unwrap_err().to_string(),
+            "Monitor public_ip depends on uplink which does not exist."
+        );
+
+        graph.add("uplink", "dns");
+        assert_eq!(
+            graph.validate().unwrap_err().to_string(),
+            "Monitor dependencies form a cycle: dns -> public_ip -> uplink -> dns."
+        );
+    }
+
+    #[test]
+    fn test_start_with_cycle() {
+        let every_10s = *PollingSchedule::default().interval(Duration::from_secs(10));
+        let mut monitor = PollingMonitor::<&'static str>::new();
+        monitor
+            .schedule_named_polling(
+                "uplink",
+                every_10s,
+                ScriptedPolling::new().into_polling_func(),
+            )
+            .schedule_named_polling(
+                "wan_ip",
+                every_10s,
+                ScriptedPolling::new().into_polling_func(),
+            )
+            .depends_on("wan_ip", Dependency::new("uplink"))
+            .unwrap()
+            .depends_on("uplink", Dependency::new("wan_ip"))
+            .unwrap();
+
+        assert_eq!(
+            monitor.start().err().unwrap().to_string(),
+            "Monitor dependencies form a cycle: uplink -> wan_ip -> uplink."
+        );
+    }
+
+    #[test]
+    fn test_suppression() {
+        let clock = VirtualClock::new();
+        let recorder = ActionRecorder::new(&clock);
+        let every_10s = *PollingSchedule::default().interval(Duration::from_secs(10));
+
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .schedule_named_polling(
+                "uplink",
+                every_10s,
+                ScriptedPolling::new()
+                    .event("up")
+                    .events("down", 2)
+                    .event("up")
+                    .into_polling_func(),
+            )
+            .schedule_named_polling(
+                "wan_ip",
+                every_10s,
+                ScriptedPolling::new()
+                    .event("unchanged")
+                    .failure("dig timed out")
+                    .failure("dig timed out")
+                    .event("unchanged")
+                    .into_polling_func(),
+            )
+            .schedule_named_polling(
+                "wg0",
+                every_10s,
+                ScriptedPolling::new()
+                    .event("up")
+                    .events("down", 2)
+                    .event("up")
+                    .into_polling_func(),
+            )
+            .depends_on("wan_ip", Dependency::new("uplink").failing_on("down"))
+            .unwrap()
+            .depends_on("wg0", Dependency::new("wan_ip"))
+            .unwrap()
+            .when_parent_failing("wg0", WhenParentFailing::Annotate)
+            .unwrap()
+            .register_action(EventKey::PollFailed, recorder.action("page"))
+            .register_action("down", recorder.action("page"));
+
+        let mut harness = MonitorHarness::new(monitor, &clock);
+        harness.advance(Duration::from_secs(35));
+
+        // The wan_ip failures are suppressed, wg0 is annotated with its failing parent
+        assert_eq!(
+            recorder.timeline(),
+            vec![
+                (10, "page".to_string(), EventKey::Polled("down")),
+                (10, "page".to_string(), EventKey::Polled("down")),
+                (20, "page".to_string(), EventKey::Polled("down")),
+                (20, "page".to_string(), EventKey::Polled("down")),
+            ]
+        );
+        let invocations = recorder.invocations();
+        assert_eq!(
+            invocations[0].event.payload.monitor_id,
+            MonitorId::new("uplink")
+        );
+        assert_eq!(
+            invocations[1].event.payload.monitor_id,
+            MonitorId::new("wg0")
+        );
+        assert_eq!(
+            invocations[1].event.value("parent_failing"),
+            Some(&Value::from("wan_ip"))
+        );
+        assert_eq!(
+            harness.fired_timeline()[3..],
+            [
+                (10, EventKey::Polled("down")),
+                (10, EventKey::Polled("down")),
+                (20, EventKey::Polled("down")),
+                (20, EventKey::Polled("down")),
+                (30, EventKey::Polled("up")),
+                (30, EventKey::PollRecovered),
+                (30, EventKey::Polled("unchanged")),
+                (30, EventKey::Polled("up")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use std::{collections::HashMap, time::Instant};
+
+use internal_prelude::library_prelude::*;
+
+use crate::{
+    limit::Limiter,
+    throttle::{ThrottleDecision, ThrottleState},
+    ActionFunc, ActionReport, Event, EventKey, FiredEvent, Throttle,
+};
+
+/// Routes FiredEvents to the actions registered for them.
+pub(crate) struct Dispatcher<E: Event> {
+    event_to_actions: HashMap<EventKey<E>, Vec<ActionFunc<E>>>,
+    event_throttles:  HashMap<EventKey<E>, Mutex<ThrottleState>>,
+}
+
+impl<E: Event> Default for Dispatcher<E> {
+    fn default() -> Self {
+        Dispatcher {
+            event_to_actions: HashMap::new(),
+            event_throttles:  HashMap::new(),
+        }
+    }
+}
+
+impl<E: Event> Dispatcher<E> {
+    pub(crate) fn register_action(&mut self, event: EventKey<E>, action: ActionFunc<E>) {
+        self.event_to_actions.entry(event).or_default().push(action);
+    }
+
+    pub(crate) fn throttle_event(&mut self, event: EventKey<E>, throttle: Throttle) {
+        self.event_throttles
+            .insert(event, Mutex::new(ThrottleState::new(throttle)));
+    }
+
+    /// Run all actions registered for the event, every action runs regardless of how the others fared.
+    pub(crate) fn dispatch(
+        &self,
+        event: &FiredEvent<E>,
+        now: Instant,
+        limiter: &Limiter,
+    ) -> Vec<ActionReport> {
+        let key = event.key();
+        let actions = match self.event_to_actions.get(&key) {
+            Some(actions) => actions,
+            None => return vec![],
+        };
+
+        let mut event = event.clone();
+        if let Some(throttle) = self.event_throttles.get(&key) {
+            match throttle.lock().check(&event, now) {
+                ThrottleDecision::Pass { suppressed } => event.suppressed += suppressed,
+                ThrottleDecision::Suppress => {
+                    return actions
+                        .iter()
+                        .map(|action| action.suppressed(&event))
+                        .collect()
+                }
+            }
+        }
+
+        actions
+            .iter()
+            .map(|action| limiter.run_action(action, &event, now))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use internal_prelude::application_prelude::anyhow;
+
+    use crate::{ActionOutcome, MonitorEvent, MonitorId, Values};
+
+    fn fired(event: &'static str) -> FiredEvent<&'static str> {
+        FiredEvent::new(
+            MonitorEvent::Polled(event),
+            &MonitorId::new("disk"),
+            "server-1",
+            Values::new(),
+        )
+    }
+
+    #[test]
+    fn test_dispatch_throttled() {
+        let suppressed_seen = Arc::new(AtomicU32::new(0));
+        let suppressed_seen_clone = Arc::clone(&suppressed_seen);
+
+        let mut dispatcher = Dispatcher::default();
+        dispatcher.register_action(
+            EventKey::Polled("disk_full"),
+            ActionFunc::new("failing", |_: &FiredEvent<&'static str>| {
+                Err(anyhow!("smtp down"))
+            }),
+        );
+        dispatcher.register_action(
+            EventKey::Polled("disk_full"),
+            ActionFunc::new("email", move |event: &FiredEvent<&'static str>| {
+                suppressed_seen_clone.store(event.suppressed, Ordering::SeqCst);
+                Ok(())
+            }),
+        );
+        dispatcher.throttle_event(
+            EventKey::Polled("disk_full"),
+            Throttle::default()
+                .cooldown(Duration::from_secs(60))
+                .clone(),
+        );
+
+        let start = Instant::now();
+        let limiter = Limiter::default();
+        let outcomes = |secs: u64| -> Vec<ActionOutcome> {
+            dispatcher
+                .dispatch(
+                    &fired("disk_full"),
+                    start + Duration::from_secs(secs),
+                    &limiter,
+                )
+                .into_iter()
+                .map(|report| report.outcome)
+                .collect()
+        };
+
+        assert_eq!(
+            outcomes(0),
+            vec![
+                ActionOutcome::Failed(vec!["smtp down".to_string()]),
+                ActionOutcome::Succeeded
+            ]
+        );
+        for secs in 1..13 {
+            assert_eq!(
+                outcomes(secs),
+                vec![ActionOutcome::Suppressed, ActionOutcome::Suppressed]
+            );
+        }
+        assert_eq!(outcomes(60)[1], ActionOutcome::Succeeded);
+        assert_eq!(suppressed_seen.load(Ordering::SeqCst), 12);
+
+        assert!(dispatcher
+            .dispatch(&fired("disk_ok"), start, &limiter)
+            .is_empty());
+    }
+}
@@ -0,0 +1,391 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::Sender;
+
+use crate::{
+    incident::Incidents, runner::Command, ActionFunc, Event, EventKey, FiredEvent, IncidentId,
+    IncidentState, MonitorId,
+};
+
+/// What stops a step of an EscalationPolicy from running again.
+#[derive(PartialEq, Clone, Debug)]
+pub enum StopCondition<E: Event> {
+    /// The incident of the event that started the escalation is acknowledged.
+    Acknowledged,
+    /// The polling function fires the event in the series of the event that started the escalation.
+    Event(EventKey<E>),
+    /// The step ran this many times.
+    Runs(u32),
+}
+
+/// Actions to run some time into an escalation, optionally repeating until a StopCondition is met.
+pub struct EscalationStep<E: Event> {
+    after:        Duration,
+    actions:      Vec<ActionFunc<E>>,
+    repeat_every: Option<Duration>,
+    until:        Vec<StopCondition<E>>,
+}
+
+impl<E: Event> EscalationStep<E> {
+    /// A step first running this long after the escalation started.
+    pub fn after(after: Duration) -> Self {
+        EscalationStep {
+            after,
+            actions: Vec::new(),
+            repeat_every: None,
+            until: Vec::new(),
+        }
+    }
+
+    /// A step running right when the escalation starts.
+    pub fn immediately() -> Self {
+        Self::after(Duration::from_secs(0))
+    }
+
+    pub fn notify(mut self, action: ActionFunc<E>) -> Self {
+        self.actions.push(action);
+        self
+    }
+
+    pub fn repeat_every(mut self, interval: Duration) -> Self {
+        self.repeat_every = Some(interval);
+        self
+    }
+
+    /// Stop running the step once any of its conditions is met.
+    pub fn until(mut self, condition: StopCondition<E>) -> Self {
+        self.until.push(condition);
+        self
+    }
+}
+
+/// Steps of actions run over time for an event, like notifying a chat channel every 10 minutes
+/// until the incident is acknowledged and paging someone if it still is not after 30 minutes.
+///
+/// An escalation starts when the event fires and runs its actions, repeats of the event in the same series
+/// do not start another one while it is going on. It ends when its incident resolves,
+/// or when no step is left to run if the event is not tracked as an Incident.
+pub struct EscalationPolicy<E: Event> {
+    name:  String,
+    steps: Vec<EscalationStep<E>>,
+}
+
+impl<E: Event> EscalationPolicy<E> {
+    pub fn new(name: &str) -> Self {
+        EscalationPolicy {
+            name:  name.to_string(),
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn step(mut self, step: EscalationStep<E>) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+struct Escalation<E: Event> {
+    policy:    Arc<EscalationPolicy<E>>,
+    event:     FiredEvent<E>,
+    series:    String,
+    incident:  Option<IncidentId>,
+    started:   Instant,
+    // The next run and the number of runs so far of each step, None once a step is done
+    next_runs: Vec<(Option<Instant>, u32)>,
+}
+
+impl<E: Event> Escalation<E> {
+    fn is_about(&self, monitor_id: &MonitorId, series: &str) -> bool {
+        self.event.payload.monitor_id == *monitor_id && self.series == series
+    }
+}
+
+/// A step of an escalation that is due to run.
+pub(crate) struct DueStep<E: Event> {
+    policy: Arc<EscalationPolicy<E>>,
+    step:   usize,
+    event:  FiredEvent<E>,
+}
+
+impl<E: Event> DueStep<E> {
+    pub(crate) fn actions(&self) -> &[ActionFunc<E>] {
+        &self.policy.steps[self.step].actions
+    }
+
+    pub(crate) fn event(&self) -> &FiredEvent<E> {
+        &self.event
+    }
+}
+
+/// The EscalationPolicies of a PollingMonitor along with the escalations going on.
+pub(crate) struct Escalations<E: Event> {
+    policies:    Vec<(EventKey<E>, Arc<EscalationPolicy<E>>)>,
+    escalations: Vec<Escalation<E>>,
+    // Wakes up the process running the escalations when one starts
+    wakeup:      Option<Sender<Command>>,
+}
+
+impl<E: Event> Default for Escalations<E> {
+    fn default() -> Self {
+        Escalations {
+            policies:    Vec::new(),
+            escalations: Vec::new(),
+            wakeup:      None,
+        }
+    }
+}
+
+impl<E: Event> Escalations<E> {
+    pub(crate) fn add(&mut self, event: EventKey<E>, policy: EscalationPolicy<E>) {
+        self.policies.push((event, Arc::new(policy)));
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.policies.is_empty()
+    }
+
+    pub(crate) fn set_wakeup(&mut self, wakeup: Sender<Command>) {
+        self.wakeup = Some(wakeup);
+    }
+
+    /// Stop the steps waiting for the event in its series.
+    pub(crate) fn observe(&mut self, event: &FiredEvent<E>, series: &str) {
+        let key = event.key();
+        for escalation in &mut self.escalations {
+            if !escalation.is_about(&event.payload.monitor_id, series) {
+                continue;
+            }
+            for (step, next_run) in escalation
+                .policy
+                .steps
+                .iter()
+                .zip(&mut escalation.next_runs)
+            {
+                if step.until.contains(&StopCondition::Event(key.clone())) {
+                    next_run.0 = None;
+                }
+            }
+        }
+    }
+
+    /// Start the escalations of a dispatched event, unless they are already going on for its series.
+    pub(crate) fn start(&mut self, event: &FiredEvent<E>, series: &str, now: Instant) {
+        let key = event.key();
+        let mut started = false;
+        for (_, policy) in self.policies.iter().filter(|(on, _)| *on == key) {
+            let going_on = self.escalations.iter().any(|escalation| {
+                Arc::ptr_eq(&escalation.policy, policy)
+                    && escalation.is_about(&event.payload.monitor_id, series)
+            });
+            if going_on {
+                continue;
+            }
+
+            self.escalations.push(Escalation {
+                policy:    Arc::clone(policy),
+                event:     event.clone(),
+                series:    series.to_string(),
+                incident:  event
+                    .value("incident_id")
+                    .and_then(|id| id.as_u64())
+                    .map(IncidentId),
+                started:   now,
+                next_runs: policy
+                    .steps
+                    .iter()
+                    .map(|step| (Some(now + step.after), 0))
+                    .collect(),
+            });
+            started = true;
+        }
+
+        if let (true, Some(wakeup)) = (started, &self.wakeup) {
+            let _ = wakeup.send(Command::CheckNow);
+        }
+    }
+
+    /// When the next step is due, if any escalation is going on.
+    pub(crate) fn next_due(&self) -> Option<Instant> {
+        self.escalations
+            .iter()
+            .flat_map(|escalation| escalation.next_runs.iter().filter_map(|(at, _)| *at))
+            .min()
+    }
+
+    /// The steps due to run, in the order of their escalations and steps.
+    /// Ends the escalations whose incident resolved, and those without an incident once no step is left to run.
+    pub(crate) fn due(&mut self, now: Instant, incidents: &Incidents<E>) -> Vec<DueStep<E>> {
+        let mut due = Vec::new();
+        self.escalations.retain_mut(|escalation| {
+            let incident_state = escalation
+                .incident
+                .map(|id| incidents.get(id).map(|incident| incident.state));
+            if let Some(None | Some(IncidentState::Resolved)) = incident_state {
+                return false;
+            }
+
+            for (index, step) in escalation.policy.steps.iter().enumerate() {
+                let (next_run, runs) = &mut escalation.next_runs[index];
+                let stopped = step.until.iter().any(|condition| match condition {
+                    StopCondition::Acknowledged => {
+                        incident_state == Some(Some(IncidentState::Acknowledged))
+                    }
+                    StopCondition::Event(_) => false,
+                    StopCondition::Runs(max_runs) => *runs >= *max_runs,
+                });
+                if stopped {
+                    *next_run = None;
+                }
+                let at = match next_run {
+                    Some(at) if *at <= now => *at,
+                    _ => continue,
+                };
+
+                *runs += 1;
+                *next_run = step.repeat_every.map(|interval| at + interval);
+                let mut event = escalation.event.clone();
+                let values = &mut event.payload.values;
+                values.insert(
+                    "escalation".to_string(),
+                    escalation.policy.name.clone().into(),
+                );
+                values.insert("escalation_step".to_string(), (index + 1).into());
+                values.insert("escalation_run".to_string(), (*runs).into());
+                values.insert(
+                    "escalation_elapsed".to_string(),
+                    at.duration_since(escalation.started).as_secs().into(),
+                );
+                due.push(DueStep {
+                    policy: Arc::clone(&escalation.policy),
+                    step: index,
+                    event,
+                });
+            }
+
+            // Kept until its incident resolves so repeats of the event do not start it again
+            escalation.incident.is_some() || escalation.next_runs.iter().any(|(at, _)| at.is_some())
+        });
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_support::{ActionRecorder, MonitorHarness, ScriptedPolling},
+        IncidentRule, PollingMonitor, PollingSchedule, VirtualClock,
+    };
+    use serde_json::Value;
+
+    const MINUTE: u64 = 60;
+
+    #[test]
+    fn test_escalation() {
+        let clock = VirtualClock::new();
+        let recorder = ActionRecorder::new(&clock);
+        let every_minute = *PollingSchedule::default().interval(Duration::from_secs(MINUTE));
+
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .schedule_named_polling(
+                "disk",
+                every_minute,
+                ScriptedPolling::new()
+                    .events("full", 50)
+                    .event("ok")
+                    .into_polling_func(),
+            )
+            .incidents("disk", IncidentRule::new("full", "ok"))
+            .unwrap()
+            .register_escalation(
+                "full",
+                EscalationPolicy::new("critical")
+                    .step(
+                        EscalationStep::immediately()
+                            .notify(recorder.action("chat"))
+                            .repeat_every(Duration::from_secs(10 * MINUTE))
+                            .until(StopCondition::Acknowledged),
+                    )
+                    .step(
+                        EscalationStep::after(Duration::from_secs(30 * MINUTE))
+                            .notify(recorder.action("pager"))
+                            .until(StopCondition::Acknowledged),
+                    ),
+            )
+            .register_action("ok", recorder.action("chat"));
+
+        let mut harness = MonitorHarness::new(monitor, &clock);
+        harness.advance(Duration::from_secs(35 * MINUTE));
+        harness.acknowledge(IncidentId(1), "on-call").unwrap();
+        harness.advance(Duration::from_secs(15 * MINUTE));
+
+        // Nothing runs between the acknowledgement at 35m and the recovery at 50m
+        assert_eq!(
+            recorder.timeline(),
+            vec![
+                (0, "chat".to_string(), EventKey::Polled("full")),
+                (10 * MINUTE, "chat".to_string(), EventKey::Polled("full")),
+                (20 * MINUTE, "chat".to_string(), EventKey::Polled("full")),
+                (30 * MINUTE, "chat".to_string(), EventKey::Polled("full")),
+                (30 * MINUTE, "pager".to_string(), EventKey::Polled("full")),
+                (50 * MINUTE, "chat".to_string(), EventKey::Polled("ok")),
+            ]
+        );
+        let pager = &recorder.invocations()[4].event;
+        assert_eq!(pager.value("escalation_step"), Some(&Value::from(2)));
+        assert_eq!(
+            pager.value("escalation_elapsed"),
+            Some(&Value::from(30 * MINUTE))
+        );
+        assert_eq!(pager.value("incident_id"), Some(&Value::from(1)));
+    }
+
+    #[test]
+    fn test_stop_on_event() {
+        let clock = VirtualClock::new();
+        let recorder = ActionRecorder::new(&clock);
+        let every_minute = *PollingSchedule::default().interval(Duration::from_secs(MINUTE));
+
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .schedule_named_polling(
+                "wg0",
+                every_minute,
+                ScriptedPolling::new()
+                    .events("down", 4)
+                    .event("up")
+                    .into_polling_func(),
+            )
+            .register_escalation(
+                "down",
+                EscalationPolicy::new("vpn").step(
+                    EscalationStep::immediately()
+                        .notify(recorder.action("chat"))
+                        .repeat_every(Duration::from_secs(MINUTE + 30))
+                        .until(StopCondition::Event(EventKey::Polled("up")))
+                        .until(StopCondition::Runs(5)),
+                ),
+            );
+
+        let mut harness = MonitorHarness::new(monitor, &clock);
+        harness.advance(Duration::from_secs(10 * MINUTE));
+
+        // Repeats of down do not restart the escalation, up at 4m stops it
+        assert_eq!(
+            recorder.timeline(),
+            vec![
+                (0, "chat".to_string(), EventKey::Polled("down")),
+                (90, "chat".to_string(), EventKey::Polled("down")),
+                (180, "chat".to_string(), EventKey::Polled("down")),
+            ]
+        );
+    }
+}
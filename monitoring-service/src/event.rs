@@ -0,0 +1,461 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug, Display},
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use event_bus::{BusEvent, Category, Publish};
+use internal_prelude::library_prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// A significant event that should be reacted to with an action (or multiple)
+#[rustfmt::skip]
+pub trait Event: Eq
+    + Hash
+    + Clone
+    + Debug
+    + Send
+    + Sync
+    + 'static // TODO
+    {}
+
+/// Events named at runtime, like those of the monitors of the daemon config.
+impl Event for String {}
+
+/// Everything a PollingMonitor can react to.
+/// Besides the events returned by the polling functions themselves,
+/// the monitor emits its own events about the health of the polling functions.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum MonitorEvent<E: Event> {
+    /// An event returned by a polling function.
+    Polled(E),
+    /// The polling function returned an error.
+    PollFailed(PollFailure),
+    /// The polling function succeeded again after one or more failures.
+    PollRecovered(PollRecovery),
+    /// The polling loop has not come around for too long, as noticed by the Watchdog.
+    Stalled(Stall),
+}
+
+impl<E: Event> MonitorEvent<E> {
+    /// The key actions are registered under for this event.
+    pub fn key(&self) -> EventKey<E> {
+        match self {
+            MonitorEvent::Polled(event) => EventKey::Polled(event.clone()),
+            MonitorEvent::PollFailed(_) => EventKey::PollFailed,
+            MonitorEvent::PollRecovered(_) => EventKey::PollRecovered,
+            MonitorEvent::Stalled(_) => EventKey::Stalled,
+        }
+    }
+}
+
+/// Identifies a MonitorEvent when registering actions.
+/// Unlike MonitorEvent it carries no data about the specific occurrence.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub enum EventKey<E: Event> {
+    Polled(E),
+    PollFailed,
+    PollRecovered,
+    Stalled,
+}
+
+impl<E: Event> EventKey<E> {
+    /// The event without the quotes of its Debug output, like `interface_down` or `PollFailed`.
+    pub fn name(&self) -> String {
+        match self {
+            EventKey::Polled(event) => format!("{:?}", event).trim_matches('"').to_string(),
+            EventKey::PollFailed => "PollFailed".to_string(),
+            EventKey::PollRecovered => "PollRecovered".to_string(),
+            EventKey::Stalled => "Stalled".to_string(),
+        }
+    }
+}
+
+impl<E: Event> From<E> for EventKey<E> {
+    fn from(event: E) -> Self {
+        EventKey::Polled(event)
+    }
+}
+
+/// Named values describing an occurrence of an event, like the name of the interface that went down.
+pub type Values = BTreeMap<String, Value>;
+
+/// Values too costly to compute for every poll, like a snapshot of the top processes,
+/// computed when an event they are deferred to fires and only once however many of them share it.
+#[derive(Clone)]
+pub struct DeferredValues(Arc<Mutex<Deferred>>);
+
+struct Deferred {
+    compute: Option<Box<dyn FnOnce() -> Values + Send>>,
+    values:  Values,
+}
+
+impl DeferredValues {
+    pub fn new(compute: impl FnOnce() -> Values + Send + 'static) -> Self {
+        DeferredValues(Arc::new(Mutex::new(Deferred {
+            compute: Some(Box::new(compute)),
+            values:  Values::new(),
+        })))
+    }
+
+    pub fn get(&self) -> Values {
+        let mut deferred = self.0.lock();
+        if let Some(compute) = deferred.compute.take() {
+            deferred.values = compute();
+        }
+        deferred.values.clone()
+    }
+
+    fn is_computed(&self) -> bool {
+        self.0.lock().compute.is_none()
+    }
+}
+
+impl PartialEq for DeferredValues {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Debug for DeferredValues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DeferredValues")
+            .field(&self.is_computed())
+            .finish()
+    }
+}
+
+/// The result of a successful poll, an event along with values describing it.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Observation<E: Event> {
+    pub event:    E,
+    /// Identifies what the event is about when a poll observes several things at once,
+    /// like one observation per mount point. Each series has its own state for the FiringMode.
+    pub series:   String,
+    pub values:   Values,
+    /// Added to the values only if the event fires and runs actions.
+    pub deferred: Vec<DeferredValues>,
+}
+
+impl<E: Event> Observation<E> {
+    pub fn new(event: E) -> Self {
+        Observation {
+            event,
+            series: String::new(),
+            values: Values::new(),
+            deferred: Vec::new(),
+        }
+    }
+
+    pub fn series(mut self, series: &str) -> Self {
+        self.series = series.to_string();
+        self
+    }
+
+    pub fn value(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.values.insert(name.to_string(), value.into());
+        self
+    }
+
+    pub fn defer(mut self, deferred: DeferredValues) -> Self {
+        self.deferred.push(deferred);
+        self
+    }
+}
+
+impl<E: Event> From<E> for Observation<E> {
+    fn from(event: E) -> Self {
+        Observation::new(event)
+    }
+}
+
+/// Identifies a polling function scheduled in a PollingMonitor.
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Debug)]
+pub struct MonitorId(String);
+
+impl MonitorId {
+    pub fn new(id: &str) -> Self {
+        MonitorId(id.to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for MonitorId {
+    fn from(id: &str) -> Self {
+        MonitorId::new(id)
+    }
+}
+
+impl Display for MonitorId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Context about an occurrence of an event that is not used for routing it to actions.
+#[derive(PartialEq, Clone, Debug)]
+pub struct EventPayload {
+    pub monitor_id: MonitorId,
+    pub host:       String,
+    pub timestamp:  SystemTime,
+    pub values:     Values,
+}
+
+/// An occurrence of a MonitorEvent as handed to actions.
+/// The event is used to find the actions to run, the payload gives them context.
+#[derive(PartialEq, Clone, Debug)]
+pub struct FiredEvent<E: Event> {
+    pub event:      MonitorEvent<E>,
+    pub payload:    EventPayload,
+    /// How many similar occurrences were held back by Throttles since this action last ran.
+    pub suppressed: u32,
+}
+
+impl<E: Event> FiredEvent<E> {
+    pub fn new(event: MonitorEvent<E>, monitor_id: &MonitorId, host: &str, values: Values) -> Self {
+        FiredEvent {
+            event,
+            payload: EventPayload {
+                monitor_id: monitor_id.clone(),
+                host: host.to_string(),
+                timestamp: SystemTime::now(),
+                values,
+            },
+            suppressed: 0,
+        }
+    }
+
+    pub fn key(&self) -> EventKey<E> {
+        self.event.key()
+    }
+
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.payload.values.get(name)
+    }
+}
+
+impl FiredEvent<String> {
+    /// An event of the EventBus as handed to the actions subscribed to it with PollingMonitor::subscribe.
+    /// The topic is the event, the category the MonitorId and the source the host,
+    /// the values are those of the event along with its `topic`, `category` and `source`.
+    pub fn of_bus_event(event: &BusEvent) -> Self {
+        let mut values = event.values.clone();
+        values.insert("topic".to_string(), event.topic.as_str().into());
+        values.insert("category".to_string(), event.category.as_str().into());
+        values.insert("source".to_string(), event.source.as_str().into());
+        FiredEvent {
+            event:      MonitorEvent::Polled(event.topic.clone()),
+            payload:    EventPayload {
+                monitor_id: MonitorId::new(event.category.as_str()),
+                host: event.source.clone(),
+                timestamp: event.timestamp,
+                values,
+            },
+            suppressed: 0,
+        }
+    }
+}
+
+/// Published on the EventBus as `monitoring.<monitor id>.<event name>` with the values of the payload,
+/// along with the error of failed polls.
+impl<E: Event> Publish for FiredEvent<E> {
+    fn to_bus_event(&self) -> BusEvent {
+        let mut bus_event = BusEvent::new(
+            Category::Monitoring,
+            &[self.payload.monitor_id.as_str(), &self.key().name()],
+        )
+        .source(&self.payload.host)
+        .at(self.payload.timestamp);
+        bus_event.values = self.payload.values.clone();
+        match &self.event {
+            MonitorEvent::Polled(_) => bus_event,
+            MonitorEvent::PollFailed(failure) => bus_event
+                .value("error", failure.error_chain.join(": "))
+                .value("consecutive_failures", failure.consecutive_failures),
+            MonitorEvent::PollRecovered(recovery) => {
+                bus_event.value("failed_polls", recovery.failed_polls)
+            }
+            MonitorEvent::Stalled(stall) => {
+                bus_event.value("stalled_for", stall.stalled_for.as_secs_f64())
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct PollFailure {
+    /// The error and all of its causes, outermost first.
+    pub error_chain:          Vec<String>,
+    /// How many polls in a row have failed, including this one.
+    pub consecutive_failures: u32,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct PollRecovery {
+    /// How many polls in a row failed before the recovery.
+    pub failed_polls: u32,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Stall {
+    /// How long ago the polling loop last came around.
+    pub stalled_for: Duration,
+    pub interval:    Duration,
+}
+
+/// A MonitorEvent resulting from a poll along with the series and values of the Observation.
+#[derive(PartialEq, Clone, Debug)]
+pub(crate) struct Observed<E: Event> {
+    pub(crate) event:    MonitorEvent<E>,
+    pub(crate) series:   String,
+    pub(crate) values:   Values,
+    pub(crate) deferred: Vec<DeferredValues>,
+}
+
+impl<E: Event> Observed<E> {
+    fn new(event: MonitorEvent<E>) -> Self {
+        Observed {
+            event,
+            series: String::new(),
+            values: Values::new(),
+            deferred: Vec::new(),
+        }
+    }
+}
+
+/// Turns the results of consecutive polls into MonitorEvents,
+/// keeping track of failures so recoveries can be detected.
+#[derive(Default)]
+pub(crate) struct PollTracker {
+    consecutive_failures: u32,
+}
+
+impl PollTracker {
+    pub(crate) fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    pub(crate) fn observe<E: Event>(
+        &mut self,
+        poll_result: Result<Vec<Observation<E>>>,
+    ) -> Vec<Observed<E>> {
+        match poll_result {
+            Ok(observations) => {
+                let mut events = Vec::with_capacity(observations.len() + 1);
+                if self.consecutive_failures > 0 {
+                    events.push(Observed::new(MonitorEvent::PollRecovered(PollRecovery {
+                        failed_polls: self.consecutive_failures,
+                    })));
+                    self.consecutive_failures = 0;
+                }
+                events.extend(observations.into_iter().map(|observation| Observed {
+                    event:    MonitorEvent::Polled(observation.event),
+                    series:   observation.series,
+                    values:   observation.values,
+                    deferred: observation.deferred,
+                }));
+                events
+            }
+            Err(err) => {
+                self.consecutive_failures += 1;
+                log::warn!(
+                    "Polling failed {} time(s) in a row with error: {:#}",
+                    self.consecutive_failures,
+                    err
+                );
+                vec![Observed::new(MonitorEvent::PollFailed(PollFailure {
+                    error_chain:          err.chain().map(|cause| cause.to_string()).collect(),
+                    consecutive_failures: self.consecutive_failures,
+                }))]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use internal_prelude::application_prelude::anyhow;
+
+    fn events(observed: Vec<Observed<&'static str>>) -> Vec<MonitorEvent<&'static str>> {
+        observed
+            .into_iter()
+            .map(|observed| observed.event)
+            .collect()
+    }
+
+    #[test]
+    fn test_failures_and_recovery() {
+        let mut tracker = PollTracker::default();
+
+        assert_eq!(
+            events(tracker.observe(Ok(vec!["up".into()]))),
+            vec![MonitorEvent::Polled("up")]
+        );
+
+        let err = anyhow!("`dig` not found").context("calling dig failed");
+        assert_eq!(
+            events(tracker.observe(Err(err))),
+            vec![MonitorEvent::PollFailed(PollFailure {
+                error_chain:          vec![
+                    "calling dig failed".to_string(),
+                    "`dig` not found".to_string()
+                ],
+                consecutive_failures: 1,
+            })]
+        );
+
+        let observed = events(tracker.observe(Err(anyhow!("still broken"))));
+        assert_eq!(observed[0].key(), EventKey::PollFailed);
+        assert!(matches!(
+            &observed[0],
+            MonitorEvent::PollFailed(PollFailure {
+                consecutive_failures: 2,
+                ..
+            })
+        ));
+
+        assert_eq!(
+            events(tracker.observe(Ok(vec!["up".into()]))),
+            vec![
+                MonitorEvent::PollRecovered(PollRecovery { failed_polls: 2 }),
+                MonitorEvent::Polled("up"),
+            ]
+        );
+        assert_eq!(
+            events(tracker.observe(Ok(vec!["up".into()]))),
+            vec![MonitorEvent::Polled("up")]
+        );
+    }
+
+    #[test]
+    fn test_observation_values_are_kept() {
+        let mut tracker = PollTracker::default();
+
+        let observed = tracker.observe(Ok(vec![Observation::new("interface_down")
+            .series("wg0")
+            .value("interface", "wg0")
+            .value("down_for_polls", 3)]));
+        assert_eq!(observed.len(), 1);
+        assert_eq!(observed[0].series, "wg0");
+
+        let fired = FiredEvent::new(
+            observed[0].event.clone(),
+            &MonitorId::new("interfaces"),
+            "server-1",
+            observed[0].values.clone(),
+        );
+        assert_eq!(fired.key(), EventKey::Polled("interface_down"));
+        assert_eq!(fired.payload.monitor_id.as_str(), "interfaces");
+        assert_eq!(fired.payload.host, "server-1");
+        assert_eq!(fired.value("interface"), Some(&Value::from("wg0")));
+        assert_eq!(fired.value("down_for_polls"), Some(&Value::from(3)));
+    }
+}
@@ -0,0 +1,341 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{threshold::Comparison, Event};
+
+/// Decides which of the polled events fire their actions.
+/// Only applies to events returned by polling functions, PollFailed and PollRecovered always fire.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub enum FiringMode<E: Event> {
+    /// Fire on every poll that returns the event.
+    #[default]
+    Level,
+    /// Fire only when the polled event differs from the one returned by the previous poll.
+    Edge,
+    /// Like Edge but a new event must first be returned by consecutive polls for its Hold.
+    Debounced(Debounce<E>),
+}
+
+/// How long an event must keep being polled before it is considered the new state.
+/// Both the number of polls and the duration must be reached.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct Hold {
+    polls:    u32,
+    duration: Duration,
+}
+
+impl Default for Hold {
+    fn default() -> Self {
+        Hold {
+            polls:    1,
+            duration: Duration::from_secs(0),
+        }
+    }
+}
+
+impl Hold {
+    pub fn polls(&mut self, polls: u32) -> &mut Self {
+        self.polls = polls;
+        self
+    }
+
+    pub fn duration(&mut self, duration: Duration) -> &mut Self {
+        self.duration = duration;
+        self
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct Debounce<E: Event> {
+    hold:           Hold,
+    hold_per_event: HashMap<E, Hold>,
+}
+
+impl<E: Event> Debounce<E> {
+    pub fn new(hold: Hold) -> Self {
+        Debounce {
+            hold,
+            hold_per_event: HashMap::new(),
+        }
+    }
+
+    /// Use a different Hold for a specific event.
+    ///
+    /// Giving the "healthy" event a longer hold than the "unhealthy" one
+    /// makes an alert quick to raise but slow to clear, which keeps it from flapping.
+    pub fn hold_event(&mut self, event: E, hold: Hold) -> &mut Self {
+        self.hold_per_event.insert(event, hold);
+        self
+    }
+
+    fn hold(&self, event: &E) -> Hold {
+        *self.hold_per_event.get(event).unwrap_or(&self.hold)
+    }
+}
+
+/// Keeps the state of a single polling function needed to apply its FiringMode.
+pub(crate) struct FiringTracker<E: Event> {
+    mode:   FiringMode<E>,
+    series: HashMap<String, SeriesState<E>>,
+}
+
+struct SeriesState<E: Event> {
+    // The event last considered the state of the polled system
+    settled:   Option<E>,
+    // A new event that is not yet held long enough to become settled
+    candidate: Option<Candidate<E>>,
+}
+
+struct Candidate<E: Event> {
+    event: E,
+    since: Instant,
+    polls: u32,
+}
+
+impl<E: Event> FiringTracker<E> {
+    pub(crate) fn new(mode: FiringMode<E>) -> Self {
+        FiringTracker {
+            mode,
+            series: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn should_fire(&mut self, series: &str, event: &E, now: Instant) -> bool {
+        let debounce = match &self.mode {
+            FiringMode::Level => return true,
+            FiringMode::Edge => None,
+            FiringMode::Debounced(debounce) => Some(debounce),
+        };
+
+        let state = self
+            .series
+            .entry(series.to_string())
+            .or_insert_with(|| SeriesState {
+                settled:   None,
+                candidate: None,
+            });
+
+        let debounce = match debounce {
+            Some(debounce) => debounce,
+            None => {
+                let changed = state.settled.as_ref() != Some(event);
+                state.settled = Some(event.clone());
+                return changed;
+            }
+        };
+
+        if state.settled.as_ref() == Some(event) {
+            state.candidate = None;
+            return false;
+        }
+
+        let candidate = match &mut state.candidate {
+            Some(candidate) if candidate.event == *event => {
+                candidate.polls += 1;
+                candidate
+            }
+            candidate => candidate.insert(Candidate {
+                event: event.clone(),
+                since: now,
+                polls: 1,
+            }),
+        };
+
+        let hold = debounce.hold(event);
+        if candidate.polls >= hold.polls && now.duration_since(candidate.since) >= hold.duration {
+            state.settled = Some(event.clone());
+            state.candidate = None;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Decides whether a value is past a threshold using separate thresholds for raising and clearing.
+/// A value hovering around a single threshold would otherwise flip between states on every poll.
+///
+/// When raise_at is above clear_at the value is raised when it is too high, otherwise when it is too low.
+/// Used by ThresholdRules with a clear condition, like `> 90 clear < 85`, and in polling functions
+/// to decide which event to return.
+pub struct Hysteresis {
+    raise:  (Comparison, f64),
+    clear:  (Comparison, f64),
+    raised: AtomicBool,
+}
+
+impl Hysteresis {
+    pub fn new(raise_at: f64, clear_at: f64) -> Self {
+        if raise_at >= clear_at {
+            Hysteresis::between(
+                (Comparison::AtLeast, raise_at),
+                (Comparison::AtMost, clear_at),
+            )
+        } else {
+            Hysteresis::between(
+                (Comparison::AtMost, raise_at),
+                (Comparison::AtLeast, clear_at),
+            )
+        }
+    }
+
+    /// Raised once the raise condition holds, until the clear condition does, like `> 90` and `< 85`.
+    pub fn between(raise: (Comparison, f64), clear: (Comparison, f64)) -> Self {
+        Hysteresis {
+            raise,
+            clear,
+            raised: AtomicBool::new(false),
+        }
+    }
+
+    /// Update the state with the latest value and return if it is raised.
+    pub fn check(&self, value: f64) -> bool {
+        let raised = if self.raised.load(Ordering::SeqCst) {
+            !self.clear.0.holds(value, self.clear.1)
+        } else {
+            self.raise.0.holds(value, self.raise.1)
+        };
+
+        self.raised.store(raised, Ordering::SeqCst);
+        raised
+    }
+
+    pub fn is_raised(&self) -> bool {
+        self.raised.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fired(
+        tracker: &mut FiringTracker<&'static str>,
+        polls: &[(&'static str, u64)],
+    ) -> Vec<&'static str> {
+        let start = Instant::now();
+        polls
+            .iter()
+            .filter(|(event, secs)| {
+                tracker.should_fire("", event, start + Duration::from_secs(*secs))
+            })
+            .map(|(event, _)| *event)
+            .collect()
+    }
+
+    #[test]
+    fn test_level() {
+        let mut tracker = FiringTracker::new(FiringMode::Level);
+        assert_eq!(
+            fired(&mut tracker, &[("full", 0), ("full", 1), ("ok", 2)]),
+            vec!["full", "full", "ok"]
+        );
+    }
+
+    #[test]
+    fn test_edge() {
+        let mut tracker = FiringTracker::new(FiringMode::Edge);
+        assert_eq!(
+            fired(
+                &mut tracker,
+                &[
+                    ("ok", 0),
+                    ("full", 1),
+                    ("full", 2),
+                    ("full", 3),
+                    ("ok", 4),
+                    ("ok", 5)
+                ]
+            ),
+            vec!["ok", "full", "ok"]
+        );
+    }
+
+    #[test]
+    fn test_debounced_by_polls() {
+        let mut tracker = FiringTracker::new(FiringMode::Debounced(Debounce::new(
+            *Hold::default().polls(3),
+        )));
+        assert_eq!(
+            fired(
+                &mut tracker,
+                &[
+                    ("ok", 0),
+                    ("ok", 1),
+                    ("ok", 2),
+                    ("full", 3),
+                    ("ok", 4),
+                    ("full", 5),
+                    ("full", 6),
+                    ("full", 7),
+                    ("full", 8),
+                ]
+            ),
+            vec!["ok", "full"]
+        );
+    }
+
+    #[test]
+    fn test_debounced_by_duration() {
+        let mut tracker = FiringTracker::new(FiringMode::Debounced(Debounce::new(
+            *Hold::default().duration(Duration::from_secs(10)),
+        )));
+        assert_eq!(
+            fired(
+                &mut tracker,
+                &[("full", 0), ("full", 5), ("full", 10), ("full", 11)]
+            ),
+            vec!["full"]
+        );
+    }
+
+    #[test]
+    fn test_debounced_hold_per_event() {
+        let mut debounce = Debounce::new(*Hold::default().polls(1));
+        debounce.hold_event("ok", *Hold::default().polls(3));
+        let mut tracker = FiringTracker::new(FiringMode::Debounced(debounce));
+
+        assert_eq!(
+            fired(
+                &mut tracker,
+                &[
+                    ("full", 0),
+                    ("ok", 1),
+                    ("full", 2),
+                    ("ok", 3),
+                    ("ok", 4),
+                    ("ok", 5)
+                ]
+            ),
+            vec!["full", "ok"]
+        );
+    }
+
+    #[test]
+    fn test_edge_per_series() {
+        let mut tracker = FiringTracker::new(FiringMode::Edge);
+        let now = Instant::now();
+
+        assert!(tracker.should_fire("/", &"ok", now));
+        assert!(tracker.should_fire("/home", &"full", now));
+        assert!(!tracker.should_fire("/", &"ok", now));
+        assert!(!tracker.should_fire("/home", &"full", now));
+        assert!(tracker.should_fire("/", &"full", now));
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let too_high = Hysteresis::new(90.0, 80.0);
+        let values = [85.0, 90.0, 85.0, 81.0, 80.0, 89.0];
+        let raised: Vec<bool> = values.iter().map(|v| too_high.check(*v)).collect();
+        assert_eq!(raised, vec![false, true, true, true, false, false]);
+
+        let too_low = Hysteresis::new(10.0, 20.0);
+        let values = [15.0, 10.0, 15.0, 20.0, 15.0];
+        let raised: Vec<bool> = values.iter().map(|v| too_low.check(*v)).collect();
+        assert_eq!(raised, vec![false, true, true, false, false]);
+    }
+}
@@ -0,0 +1,699 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::Arc,
+    thread::{JoinHandle, Result as ThreadResult},
+    time::{Duration, SystemTime},
+};
+
+use crossbeam::channel::Sender;
+use internal_prelude::library_prelude::*;
+use serde::Serialize;
+
+use crate::{
+    history::{History, HistoryEntry, HistoryQuery},
+    runner::{
+        Command, EscalationProcess, Poller, PollingContext, PollingProcess, SubscriptionProcess,
+        WatchdogProcess,
+    },
+    stats::ExecutionStats,
+    watchdog::Watchdog,
+    ActionFunc, ActionReport, ConcurrencyStats, Event, Incident, IncidentId, MonitorId,
+    MonitorOptions, PollingFunc, PollingSchedule, Silence, SilenceId,
+};
+
+#[derive(Error, Debug)]
+pub enum MonitorControlError {
+    #[error("No monitor with id {0}.")]
+    UnknownMonitor(MonitorId),
+    #[error("A monitor with id {0} already exists.")]
+    DuplicateMonitor(MonitorId),
+    #[error("The polling process of monitor {0} is no longer running.")]
+    MonitorStopped(MonitorId),
+}
+
+#[derive(Serialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub enum MonitorState {
+    Running,
+    Paused,
+}
+
+/// A snapshot of the state of a single polling function of a running PollingMonitor.
+#[derive(Serialize, PartialEq, Clone, Debug)]
+pub struct MonitorStatus {
+    pub id:                   MonitorId,
+    pub state:                MonitorState,
+    pub schedule:             PollingSchedule,
+    pub polls:                u64,
+    pub consecutive_failures: u32,
+    pub last_poll:            Option<SystemTime>,
+    pub last_poll_duration:   Option<Duration>,
+    /// How long the last poll waited for a free slot of its concurrency limits.
+    pub last_queue_wait:      Option<Duration>,
+    pub total_queue_wait:     Duration,
+    /// Polls skipped because their WhenBusy is Skip and they could not start right away.
+    pub skipped_ticks:        u64,
+    /// When the polling loop last came around, whether it polled or skipped, or when the monitor was added.
+    pub last_tick:            Option<SystemTime>,
+    /// Every poll that ran, a poll returning an error is a failure.
+    pub stats:                ExecutionStats,
+}
+
+impl MonitorStatus {
+    pub(crate) fn new(id: MonitorId, schedule: PollingSchedule) -> Self {
+        MonitorStatus {
+            id,
+            state: MonitorState::Running,
+            schedule,
+            polls: 0,
+            consecutive_failures: 0,
+            last_poll: None,
+            last_poll_duration: None,
+            last_queue_wait: None,
+            total_queue_wait: Duration::from_secs(0),
+            skipped_ticks: 0,
+            last_tick: None,
+            stats: ExecutionStats::default(),
+        }
+    }
+}
+
+struct ProcessHandle {
+    commands:    Sender<Command>,
+    join_handle: JoinHandle<()>,
+}
+
+pub struct PollingMonitorHandle<E: Event> {
+    inner:              Arc<Mutex<PollingMonitorHandleInner>>,
+    context:            Arc<PollingContext<E>>,
+    processes:          Mutex<HashMap<MonitorId, ProcessHandle>>,
+    escalation_process: Option<ProcessHandle>,
+    watchdog_process:   Option<ProcessHandle>,
+    subscriptions:      Vec<ProcessHandle>,
+}
+
+impl<E: Event> PollingMonitorHandle<E> {
+    pub(crate) fn new(context: Arc<PollingContext<E>>) -> Self {
+        PollingMonitorHandle {
+            inner: Arc::clone(&context.inner),
+            context,
+            processes: Mutex::new(HashMap::new()),
+            escalation_process: None,
+            watchdog_process: None,
+            subscriptions: Vec::new(),
+        }
+    }
+
+    /// Start running the steps of escalations as they become due.
+    pub(crate) fn spawn_escalation_process(&mut self) {
+        let (commands, join_handle) = EscalationProcess::spawn(Arc::clone(&self.context));
+        self.escalation_process = Some(ProcessHandle {
+            commands,
+            join_handle,
+        });
+    }
+
+    /// Start watching the polling loops and sending heartbeats.
+    pub(crate) fn spawn_watchdog_process(&mut self, watchdog: Watchdog) {
+        let (commands, join_handle) = WatchdogProcess::spawn(Arc::clone(&self.context), watchdog);
+        self.watchdog_process = Some(ProcessHandle {
+            commands,
+            join_handle,
+        });
+    }
+
+    /// Start running the action for the events on the EventBus matching the pattern.
+    /// Without an EventBus there is nothing to subscribe to and the action never runs.
+    pub(crate) fn spawn_subscription_process(&mut self, pattern: &str, action: ActionFunc<String>) {
+        let events = match &self.context.event_bus {
+            Some(event_bus) => match event_bus.channel(pattern) {
+                Ok((_, events)) => events,
+                Err(err) => {
+                    log::error!("Unable to subscribe '{}': {:#}", action.name(), err);
+                    return;
+                }
+            },
+            None => {
+                log::warn!(
+                    "Action '{}' subscribed to {} but there is no event bus",
+                    action.name(),
+                    pattern
+                );
+                return;
+            }
+        };
+        let (commands, join_handle) =
+            SubscriptionProcess::spawn(Arc::clone(&self.context), action, events);
+        self.subscriptions.push(ProcessHandle {
+            commands,
+            join_handle,
+        });
+    }
+
+    /// Returns if the Watcher thread is semantically running.
+    /// Even if this returns false in practice it may still be running it's final loop before termination.
+    /// To make sure the logic loop has stopped use join() or stop_and_join().
+    pub fn is_running(&self) -> bool {
+        self.inner.lock().is_running
+    }
+
+    /// Signals the Watcher to stop.
+    /// In practice the logic loop might still finish up it's final loop before termination.
+    /// To make sure the logic loop has stopped use join() or stop_and_join().
+    pub fn stop(&self) {
+        self.inner.lock().is_running = false;
+        for process in self.processes.lock().values() {
+            let _ = process.commands.send(Command::Stop);
+        }
+        let others = self
+            .escalation_process
+            .iter()
+            .chain(&self.watchdog_process)
+            .chain(&self.subscriptions);
+        for process in others {
+            let _ = process.commands.send(Command::Stop);
+        }
+    }
+
+    /// Reports of the most recently run actions, oldest first.
+    /// At most ACTION_REPORTS_KEPT reports are kept.
+    pub fn action_reports(&self) -> Vec<ActionReport> {
+        self.inner.lock().action_reports()
+    }
+
+    /// Join the threads running the polling processes.
+    pub fn join(self) -> ThreadResult<()> {
+        let mut result = Ok(());
+        let processes = self.processes.into_inner().into_values();
+        let others = self
+            .escalation_process
+            .into_iter()
+            .chain(self.watchdog_process)
+            .chain(self.subscriptions);
+        for process in processes.chain(others) {
+            if let Err(err) = process.join_handle.join() {
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    /// Signal the Watcher to stop and wait for it's thread to terminate.
+    pub fn stop_and_join(self) -> ThreadResult<()> {
+        self.stop();
+        self.join()
+    }
+
+    /// Start polling a new function while the PollingMonitor is running.
+    /// Fails if the id is taken or the dependencies of the function would form a cycle.
+    pub fn add_monitor(
+        &self,
+        id: impl Into<MonitorId>,
+        schedule: PollingSchedule,
+        polling_func: PollingFunc<E>,
+        options: MonitorOptions<E>,
+    ) -> Result<()> {
+        let id = id.into();
+        let mut processes = self.processes.lock();
+        if processes.contains_key(&id) {
+            return Err(MonitorControlError::DuplicateMonitor(id).into());
+        }
+
+        let poller = Poller::new(
+            id.clone(),
+            schedule,
+            polling_func,
+            options,
+            Arc::clone(&self.context),
+        )?;
+        let (commands, join_handle) = PollingProcess::spawn(poller, schedule);
+        processes.insert(
+            id,
+            ProcessHandle {
+                commands,
+                join_handle,
+            },
+        );
+        Ok(())
+    }
+
+    /// Stop polling a function and forget about it.
+    /// A poll in progress is finished in the background.
+    pub fn remove_monitor(&self, id: impl Into<MonitorId>) -> Result<()> {
+        let id = id.into();
+        let process = self
+            .processes
+            .lock()
+            .remove(&id)
+            .ok_or_else(|| MonitorControlError::UnknownMonitor(id.clone()))?;
+        self.inner.lock().monitors.remove(&id);
+        self.context.dependencies.lock().remove(&id);
+        self.context.incidents.lock().set_rule(&id, None);
+
+        // The thread is left to finish on its own, a poll in progress could take a while
+        let _ = process.commands.send(Command::Stop);
+        Ok(())
+    }
+
+    /// Stop polling a function until it is resumed, check_now() still polls a paused function.
+    /// The status shows the function as paused once a poll in progress has finished.
+    pub fn pause(&self, id: impl Into<MonitorId>) -> Result<()> {
+        self.send(&id.into(), Command::Pause)
+    }
+
+    /// Continue polling a paused function on its schedule.
+    /// If a poll became due while paused the function is polled immediately.
+    pub fn resume(&self, id: impl Into<MonitorId>) -> Result<()> {
+        self.send(&id.into(), Command::Resume)
+    }
+
+    /// Change the schedule of a function, the next poll is due the new interval after the last one.
+    pub fn reschedule(&self, id: impl Into<MonitorId>, schedule: PollingSchedule) -> Result<()> {
+        let id = id.into();
+        self.send(&id, Command::Reschedule(schedule))?;
+        self.update_status(&id, |status| status.schedule = schedule);
+        Ok(())
+    }
+
+    /// Poll a function right away, regardless of its schedule.
+    pub fn check_now(&self, id: impl Into<MonitorId>) -> Result<()> {
+        self.send(&id.into(), Command::CheckNow)
+    }
+
+    /// The status of all polling functions, ordered by their id.
+    pub fn status(&self) -> Vec<MonitorStatus> {
+        self.inner.lock().monitors.values().cloned().collect()
+    }
+
+    pub fn monitor_status(&self, id: impl Into<MonitorId>) -> Option<MonitorStatus> {
+        self.inner.lock().monitors.get(&id.into()).cloned()
+    }
+
+    /// Polls, events and action outcomes matching the query, oldest first.
+    /// Entries of removed polling functions are kept until the PollingMonitor stops.
+    pub fn history(&self, query: &HistoryQuery) -> Vec<HistoryEntry> {
+        self.inner.lock().history.query(query)
+    }
+
+    /// Unresolved incidents and the most recently resolved ones, ordered by their id.
+    pub fn incidents(&self) -> Vec<Incident> {
+        self.context.incidents.lock().incidents()
+    }
+
+    pub fn incident(&self, id: IncidentId) -> Option<Incident> {
+        self.context.incidents.lock().get(id).cloned()
+    }
+
+    /// Acknowledge an incident, its repeats no longer run actions until it is resolved.
+    pub fn acknowledge(&self, id: IncidentId, by: &str) -> Result<Incident> {
+        let at = self.context.clock.system_now();
+        Ok(self.context.incidents.lock().acknowledge(id, by, at)?)
+    }
+
+    /// Silence the matching events for a time range, returns the id to lift the silence with.
+    pub fn silence(&self, silence: Silence) -> SilenceId {
+        let now = self.context.clock.system_now();
+        self.context.silences.lock().add(silence, now)
+    }
+
+    pub fn unsilence(&self, id: SilenceId) -> Result<Silence> {
+        Ok(self.context.silences.lock().remove(id)?)
+    }
+
+    /// The silences that have not ended yet, ordered by their id.
+    pub fn silences(&self) -> Vec<Silence> {
+        let now = self.context.clock.system_now();
+        self.context.silences.lock().silences(now)
+    }
+
+    /// Runs, failures and durations of each action by name, including the actions of escalations.
+    pub fn action_stats(&self) -> BTreeMap<String, ExecutionStats> {
+        self.inner.lock().action_stats()
+    }
+
+    /// How busy the concurrency limits are and how long polls and actions waited for them.
+    pub fn concurrency(&self) -> ConcurrencyStats {
+        self.context.limiter.stats()
+    }
+
+    fn send(&self, id: &MonitorId, command: Command) -> Result<()> {
+        let processes = self.processes.lock();
+        let process = processes
+            .get(id)
+            .ok_or_else(|| MonitorControlError::UnknownMonitor(id.clone()))?;
+        process
+            .commands
+            .send(command)
+            .map_err(|_| MonitorControlError::MonitorStopped(id.clone()).into())
+    }
+
+    fn update_status(&self, id: &MonitorId, update: impl FnOnce(&mut MonitorStatus)) {
+        if let Some(status) = self.inner.lock().monitors.get_mut(id) {
+            update(status);
+        }
+    }
+}
+
+/// The key the history of a polling function is kept under in the StateStore.
+pub(crate) fn history_key(id: &MonitorId) -> String {
+    format!("history-{}", id)
+}
+
+/// How many ActionReports a PollingMonitorHandle keeps
+pub const ACTION_REPORTS_KEPT: usize = 100;
+
+/// Inner representation of WatcherHandle
+pub(crate) struct PollingMonitorHandleInner {
+    pub(crate) is_running: bool,
+    action_reports:        VecDeque<ActionReport>,
+    action_stats:          BTreeMap<String, ExecutionStats>,
+    pub(crate) monitors:   BTreeMap<MonitorId, MonitorStatus>,
+    pub(crate) history:    History,
+}
+
+impl PollingMonitorHandleInner {
+    pub(crate) fn new(history_size: usize) -> Self {
+        PollingMonitorHandleInner {
+            is_running:     true,
+            action_reports: VecDeque::with_capacity(ACTION_REPORTS_KEPT),
+            action_stats:   BTreeMap::new(),
+            monitors:       BTreeMap::new(),
+            history:        History::new(history_size),
+        }
+    }
+
+    pub(crate) fn action_reports(&self) -> Vec<ActionReport> {
+        self.action_reports.iter().cloned().collect()
+    }
+
+    pub(crate) fn action_stats(&self) -> BTreeMap<String, ExecutionStats> {
+        self.action_stats.clone()
+    }
+
+    /// Record the reports of actions that ran and the history of what led to them.
+    pub(crate) fn record(&mut self, reports: Vec<ActionReport>, history: Vec<HistoryEntry>) {
+        for report in reports {
+            self.action_stats
+                .entry(report.action.clone())
+                .or_default()
+                .record_action(&report);
+            if self.action_reports.len() == ACTION_REPORTS_KEPT {
+                self.action_reports.pop_front();
+            }
+            self.action_reports.push_back(report);
+        }
+        for entry in history {
+            self.history.record(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicU32, Ordering},
+        thread::sleep,
+        time::Instant,
+    };
+
+    use crate::{
+        state::tests::temp_state_store, ActionFunc, ConcurrencyLimits, HistoryRecord,
+        PollingMonitor, WhenBusy,
+    };
+
+    fn wait_until(what: &str, condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(
+                Instant::now() < deadline,
+                "timed out waiting until {}",
+                what
+            );
+            sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn counting_polling_func(polls: &Arc<AtomicU32>) -> PollingFunc<&'static str> {
+        let polls = Arc::clone(polls);
+        PollingFunc::new(move || {
+            polls.fetch_add(1, Ordering::SeqCst);
+            Ok("ok")
+        })
+    }
+
+    #[test]
+    fn test_runtime_control() {
+        let hourly = *PollingSchedule::default().interval(Duration::from_secs(3600));
+        let polls = Arc::new(AtomicU32::new(0));
+
+        let mut monitor = PollingMonitor::new();
+        monitor.schedule_named_polling("first", hourly, counting_polling_func(&polls));
+        let handle = monitor.start().unwrap();
+
+        // Polled once right after starting
+        wait_until("first poll", || polls.load(Ordering::SeqCst) == 1);
+        wait_until("status updated", || {
+            handle.monitor_status("first").unwrap().polls == 1
+        });
+
+        handle.pause("first").unwrap();
+        wait_until("paused", || {
+            handle.monitor_status("first").unwrap().state == MonitorState::Paused
+        });
+
+        handle.check_now("first").unwrap();
+        wait_until("checked now", || polls.load(Ordering::SeqCst) == 2);
+
+        handle.resume("first").unwrap();
+        handle
+            .reschedule(
+                "first",
+                *PollingSchedule::default().interval(Duration::from_millis(1)),
+            )
+            .unwrap();
+        wait_until("polling on new schedule", || {
+            polls.load(Ordering::SeqCst) > 5
+        });
+        assert_eq!(
+            handle.monitor_status("first").unwrap().state,
+            MonitorState::Running
+        );
+
+        let second_polls = Arc::new(AtomicU32::new(0));
+        handle
+            .add_monitor(
+                "second",
+                hourly,
+                counting_polling_func(&second_polls),
+                MonitorOptions::default(),
+            )
+            .unwrap();
+        wait_until("second monitor polled", || {
+            second_polls.load(Ordering::SeqCst) == 1
+        });
+        assert!(handle
+            .add_monitor(
+                "second",
+                hourly,
+                counting_polling_func(&second_polls),
+                MonitorOptions::default(),
+            )
+            .is_err());
+
+        let ids: Vec<String> = handle
+            .status()
+            .into_iter()
+            .map(|status| status.id.to_string())
+            .collect();
+        assert_eq!(ids, vec!["first", "second"]);
+
+        handle.remove_monitor("first").unwrap();
+        assert_eq!(handle.status().len(), 1);
+        assert!(handle.pause("first").is_err());
+        assert!(handle.check_now("unknown").is_err());
+
+        handle.stop_and_join().unwrap();
+    }
+
+    #[test]
+    fn test_pause_during_poll() {
+        let hourly = *PollingSchedule::default().interval(Duration::from_secs(3600));
+        let (started, started_receiver) = crossbeam::channel::bounded(1);
+        let (release, release_receiver) = crossbeam::channel::bounded::<()>(1);
+
+        let mut monitor = PollingMonitor::new();
+        monitor.schedule_named_polling(
+            "slow",
+            hourly,
+            PollingFunc::new(move || {
+                let _ = started.try_send(());
+                let _ = release_receiver.recv();
+                Ok("ok")
+            }),
+        );
+        let handle = monitor.start().unwrap();
+
+        // Paused while polling, the poll finishing must not report it as running again
+        started_receiver.recv().unwrap();
+        handle.pause("slow").unwrap();
+        release.send(()).unwrap();
+        wait_until("paused", || {
+            handle.monitor_status("slow").unwrap().state == MonitorState::Paused
+        });
+        sleep(Duration::from_millis(20));
+        let status = handle.monitor_status("slow").unwrap();
+        assert_eq!(status.state, MonitorState::Paused);
+        assert_eq!(status.polls, 1);
+
+        handle.resume("slow").unwrap();
+        wait_until("resumed", || {
+            handle.monitor_status("slow").unwrap().state == MonitorState::Running
+        });
+        handle.stop_and_join().unwrap();
+    }
+
+    #[test]
+    fn test_control_while_queued() {
+        let hourly = *PollingSchedule::default().interval(Duration::from_secs(3600));
+        let (started, started_receiver) = crossbeam::channel::bounded(1);
+        let (release, release_receiver) = crossbeam::channel::bounded::<()>(1);
+        let polls = Arc::new(AtomicU32::new(0));
+
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .concurrency_limits(ConcurrencyLimits::default().group("commands", 1))
+            .schedule_named_polling(
+                "hung",
+                hourly,
+                PollingFunc::new(move || {
+                    let _ = started.try_send(());
+                    let _ = release_receiver.recv();
+                    Ok("ok")
+                }),
+            )
+            .group("hung", "commands")
+            .unwrap();
+        let handle = monitor.start().unwrap();
+        started_receiver.recv().unwrap();
+        handle
+            .add_monitor(
+                "queued",
+                hourly,
+                counting_polling_func(&polls),
+                MonitorOptions::default().group("commands"),
+            )
+            .unwrap();
+        let queued = || handle.concurrency().groups["commands"].queued;
+
+        // Waiting for the slot the hung poll holds doesn't keep the queued monitor from pausing or stopping
+        wait_until("queued", || queued() == 1);
+        handle.pause("queued").unwrap();
+        wait_until("paused", || {
+            handle.monitor_status("queued").unwrap().state == MonitorState::Paused
+        });
+        assert_eq!(queued(), 0);
+        handle.resume("queued").unwrap();
+        wait_until("queued again", || queued() == 1);
+        handle.stop();
+        wait_until("stopped waiting", || queued() == 0);
+        assert_eq!(polls.load(Ordering::SeqCst), 0);
+
+        release.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_history() {
+        let hourly = *PollingSchedule::default().interval(Duration::from_secs(3600));
+        let state_store = temp_state_store();
+        let start_monitor = || {
+            let mut monitor = PollingMonitor::new();
+            monitor
+                .state_store(state_store.clone())
+                .schedule_named_polling("wg0", hourly, PollingFunc::new(|| Ok("down")))
+                .register_action("down", ActionFunc::new("restart", |_| Ok(())));
+            monitor.start().unwrap()
+        };
+
+        let handle = start_monitor();
+        wait_until("first poll recorded", || {
+            handle.history(&HistoryQuery::default()).len() == 3
+        });
+        let history = handle.history(&HistoryQuery::default());
+        assert!(matches!(
+            history[0].record,
+            HistoryRecord::Poll {
+                observations: 1,
+                error_chain: None,
+                ..
+            }
+        ));
+        assert_eq!(history[1].record.event(), Some("Polled(\"down\")"));
+        assert!(
+            matches!(&history[2].record, HistoryRecord::Action(report) if report.action == "restart")
+        );
+        assert_eq!(
+            handle.history(HistoryQuery::default().event("down").last(1)),
+            history[2..].to_vec()
+        );
+        handle.stop_and_join().unwrap();
+
+        // The history of the previous run is restored from the StateStore
+        let handle = start_monitor();
+        wait_until("second poll recorded", || {
+            handle.history(&HistoryQuery::default()).len() == 6
+        });
+        assert_eq!(handle.history(&HistoryQuery::default())[..3], history[..]);
+        handle.stop_and_join().unwrap();
+
+        std::fs::remove_dir_all(state_store.dir()).unwrap();
+    }
+
+    #[test]
+    fn test_concurrency_limits() {
+        let every_ms = *PollingSchedule::default().interval(Duration::from_millis(1));
+        let running = Arc::new(AtomicU32::new(0));
+        let overlapped = Arc::new(AtomicU32::new(0));
+        let slow_polling_func = || {
+            let running = Arc::clone(&running);
+            let overlapped = Arc::clone(&overlapped);
+            PollingFunc::new(move || {
+                if running.fetch_add(1, Ordering::SeqCst) > 0 {
+                    overlapped.fetch_add(1, Ordering::SeqCst);
+                }
+                sleep(Duration::from_millis(10));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok("ok")
+            })
+        };
+
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .concurrency_limits(ConcurrencyLimits::default().group("commands", 1))
+            .schedule_named_polling("queued", every_ms, slow_polling_func())
+            .group("queued", "commands")
+            .unwrap()
+            .schedule_named_polling("skipping", every_ms, slow_polling_func())
+            .group("skipping", "commands")
+            .unwrap()
+            .when_busy("skipping", WhenBusy::Skip)
+            .unwrap();
+        let handle = monitor.start().unwrap();
+
+        wait_until("polls queued and skipped", || {
+            let queued = handle.monitor_status("queued").unwrap();
+            let skipping = handle.monitor_status("skipping").unwrap();
+            queued.polls > 3
+                && queued.total_queue_wait > Duration::from_secs(0)
+                && skipping.skipped_ticks > 3
+        });
+        let stats = handle.concurrency();
+        handle.stop_and_join().unwrap();
+
+        assert_eq!(overlapped.load(Ordering::SeqCst), 0);
+        let commands = &stats.groups["commands"];
+        assert_eq!(commands.limit, 1);
+        assert!(commands.skipped > 3 && commands.max_wait > Duration::from_secs(0));
+        assert_eq!(stats.polls, None);
+    }
+}
@@ -0,0 +1,346 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ActionReport, Event, EventKey, MonitorId, Values};
+
+/// How many HistoryEntries a PollingMonitor keeps per polling function by default
+pub const HISTORY_KEPT: usize = 500;
+
+/// How often the new HistoryEntries of a polling function are appended to the StateStore at most,
+/// they are also appended when the polling function stops
+pub const HISTORY_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Something that happened to a polling function of a PollingMonitor.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct HistoryEntry {
+    pub monitor_id: MonitorId,
+    pub at:         SystemTime,
+    pub record:     HistoryRecord,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum HistoryRecord {
+    /// The polling function ran, returning either observations or an error.
+    Poll {
+        duration:     Duration,
+        observations: usize,
+        /// The error and all of its causes when the poll failed.
+        error_chain:  Option<Vec<String>>,
+    },
+    /// An event fired, after its FiringMode and before any Throttles.
+    Event {
+        /// The EventKey of the event.
+        event:  String,
+        series: String,
+        values: Values,
+    },
+    /// An action ran, or was suppressed, for an event.
+    Action(ActionReport),
+}
+
+impl HistoryRecord {
+    /// The EventKey the record is about, if any.
+    pub fn event(&self) -> Option<&str> {
+        match self {
+            HistoryRecord::Poll { .. } => None,
+            HistoryRecord::Event { event, .. } => Some(event),
+            HistoryRecord::Action(report) => Some(&report.event),
+        }
+    }
+}
+
+/// Selects HistoryEntries, by default all entries of all polling functions.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct HistoryQuery {
+    monitor_id: Option<MonitorId>,
+    since:      Option<SystemTime>,
+    event:      Option<String>,
+    last:       Option<usize>,
+}
+
+impl HistoryQuery {
+    /// Only entries of a single polling function.
+    pub fn monitor(&mut self, id: impl Into<MonitorId>) -> &mut Self {
+        self.monitor_id = Some(id.into());
+        self
+    }
+
+    /// Only entries at or after the time.
+    pub fn since(&mut self, since: SystemTime) -> &mut Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// Only occurrences of the event and the actions run for it.
+    pub fn event<E: Event>(&mut self, event: impl Into<EventKey<E>>) -> &mut Self {
+        self.event = Some(format!("{:?}", event.into()));
+        self
+    }
+
+    /// Only the most recent entries matching the rest of the query.
+    pub fn last(&mut self, count: usize) -> &mut Self {
+        self.last = Some(count);
+        self
+    }
+
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.since.is_none_or(|since| entry.at >= since)
+            && self
+                .event
+                .as_ref()
+                .is_none_or(|event| entry.record.event() == Some(event.as_str()))
+    }
+}
+
+/// Bounded history of each polling function, oldest entries are dropped first.
+pub(crate) struct History {
+    size:     usize,
+    entries:  HashMap<MonitorId, VecDeque<HistoryEntry>>,
+    // How many entries were ever recorded per polling function, to tell which ones are new
+    recorded: HashMap<MonitorId, u64>,
+}
+
+impl History {
+    pub(crate) fn new(size: usize) -> Self {
+        History {
+            size,
+            entries: HashMap::new(),
+            recorded: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    pub(crate) fn record(&mut self, entry: HistoryEntry) {
+        *self.recorded.entry(entry.monitor_id.clone()).or_default() += 1;
+        let size = self.size;
+        let entries = self
+            .entries
+            .entry(entry.monitor_id.clone())
+            .or_insert_with(|| VecDeque::with_capacity(size));
+        if entries.len() == size {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Put back entries of a polling function from a previous run, like from a StateStore.
+    pub(crate) fn restore(&mut self, id: &MonitorId, restored: Vec<HistoryEntry>) {
+        let skip = restored.len().saturating_sub(self.size);
+        self.entries
+            .insert(id.clone(), restored.into_iter().skip(skip).collect());
+    }
+
+    /// How many entries of a polling function were recorded so far, including the ones no longer kept.
+    pub(crate) fn recorded(&self, id: &MonitorId) -> u64 {
+        self.recorded.get(id).copied().unwrap_or_default()
+    }
+
+    /// The entries of a polling function recorded after the first `since` of them, as far as they are still kept.
+    pub(crate) fn recorded_since(&self, id: &MonitorId, since: u64) -> Vec<HistoryEntry> {
+        let new = self.recorded(id).saturating_sub(since) as usize;
+        self.entries
+            .get(id)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .skip(entries.len().saturating_sub(new))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn entries(&self, id: &MonitorId) -> Vec<HistoryEntry> {
+        self.entries
+            .get(id)
+            .map(|entries| entries.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The matching entries, oldest first.
+    pub(crate) fn query(&self, query: &HistoryQuery) -> Vec<HistoryEntry> {
+        let mut matching: Vec<HistoryEntry> = self
+            .entries
+            .iter()
+            .filter(|(id, _)| {
+                query
+                    .monitor_id
+                    .as_ref()
+                    .is_none_or(|monitor_id| monitor_id == *id)
+            })
+            .flat_map(|(_, entries)| entries.iter())
+            .filter(|entry| query.matches(entry))
+            .cloned()
+            .collect();
+        matching.sort_by_key(|entry| entry.at);
+
+        if let Some(last) = query.last {
+            matching.drain(..matching.len().saturating_sub(last));
+        }
+        matching
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        state::tests::temp_state_store,
+        test_support::{MonitorHarness, ScriptedPolling},
+        PollingMonitor, PollingSchedule, VirtualClock,
+    };
+
+    fn poll(id: &str, secs: u64) -> HistoryEntry {
+        HistoryEntry {
+            monitor_id: MonitorId::new(id),
+            at:         SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            record:     HistoryRecord::Poll {
+                duration:     Duration::from_millis(5),
+                observations: 1,
+                error_chain:  None,
+            },
+        }
+    }
+
+    fn event(id: &str, secs: u64, event: &'static str) -> HistoryEntry {
+        HistoryEntry {
+            record: HistoryRecord::Event {
+                event:  format!("{:?}", EventKey::Polled(event)),
+                series: String::new(),
+                values: Values::new(),
+            },
+            ..poll(id, secs)
+        }
+    }
+
+    fn times(entries: &[HistoryEntry]) -> Vec<u64> {
+        entries
+            .iter()
+            .map(|entry| {
+                entry
+                    .at
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bounded() {
+        let mut history = History::new(3);
+        for secs in 0..5 {
+            history.record(poll("wg0", secs));
+        }
+        history.record(poll("eth0", 10));
+        assert_eq!(
+            times(&history.entries(&MonitorId::new("wg0"))),
+            vec![2, 3, 4]
+        );
+        assert_eq!(history.recorded(&MonitorId::new("wg0")), 5);
+        assert_eq!(
+            times(&history.recorded_since(&MonitorId::new("wg0"), 3)),
+            vec![3, 4]
+        );
+        assert_eq!(
+            times(&history.recorded_since(&MonitorId::new("wg0"), 0)),
+            vec![2, 3, 4]
+        );
+
+        history.restore(
+            &MonitorId::new("eth0"),
+            (20..25).map(|secs| poll("eth0", secs)).collect(),
+        );
+        assert_eq!(
+            times(&history.entries(&MonitorId::new("eth0"))),
+            vec![22, 23, 24]
+        );
+    }
+
+    #[test]
+    fn test_query() {
+        let mut history = History::new(HISTORY_KEPT);
+        history.record(poll("wg0", 0));
+        history.record(event("wg0", 1, "down"));
+        history.record(poll("eth0", 2));
+        history.record(event("eth0", 3, "up"));
+        history.record(event("wg0", 4, "down"));
+
+        assert_eq!(
+            times(&history.query(&HistoryQuery::default())),
+            vec![0, 1, 2, 3, 4]
+        );
+        assert_eq!(
+            times(&history.query(HistoryQuery::default().monitor("wg0"))),
+            vec![0, 1, 4]
+        );
+        assert_eq!(
+            times(&history.query(HistoryQuery::default().event("down"))),
+            vec![1, 4]
+        );
+        assert_eq!(
+            times(&history.query(
+                HistoryQuery::default().since(SystemTime::UNIX_EPOCH + Duration::from_secs(2))
+            )),
+            vec![2, 3, 4]
+        );
+        assert_eq!(
+            times(&history.query(HistoryQuery::default().monitor("eth0").last(1))),
+            vec![3]
+        );
+    }
+
+    #[test]
+    fn test_saved_to_state_store() {
+        let state_store = temp_state_store();
+        let log = state_store.dir().join("history-wg0.jsonl");
+        let lines = || std::fs::read_to_string(&log).map_or(0, |log| log.lines().count());
+        let start_harness = || {
+            let mut monitor = PollingMonitor::new();
+            monitor
+                .history_size(10)
+                .state_store(state_store.clone())
+                .schedule_named_polling(
+                    "wg0",
+                    *PollingSchedule::default().interval(Duration::from_secs(10)),
+                    ScriptedPolling::new()
+                        .events("down", 20)
+                        .into_polling_func(),
+                );
+            MonitorHarness::new(monitor, &VirtualClock::new())
+        };
+
+        // Each poll records the poll and its event, saved every HISTORY_SAVE_INTERVAL
+        let mut harness = start_harness();
+        harness.advance(Duration::from_secs(5));
+        assert_eq!(lines(), 0);
+        harness.advance(Duration::from_secs(10));
+        assert_eq!(lines(), 4);
+        harness.advance(Duration::from_secs(80));
+        assert_eq!(lines(), 20);
+        // Rewritten with the kept entries instead of growing past twice their number
+        harness.advance(Duration::from_secs(10));
+        assert_eq!(lines(), 10);
+        // And saved when the polling function stops
+        harness.advance(Duration::from_secs(10));
+        assert_eq!(lines(), 12);
+        harness.check_now("wg0");
+        drop(harness);
+        assert_eq!(lines(), 14);
+
+        // The restored entries and those of the first poll, as many as are kept
+        let harness = start_harness();
+        let history = harness.history(&HistoryQuery::default());
+        assert_eq!(history.len(), 10);
+        drop(harness);
+
+        std::fs::remove_dir_all(state_store.dir()).unwrap();
+    }
+}
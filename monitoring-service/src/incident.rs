@@ -0,0 +1,393 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+use internal_prelude::library_prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{Event, EventKey, FiredEvent, MonitorId, StateStore};
+
+/// How many resolved Incidents are kept, open ones are always kept.
+pub const RESOLVED_INCIDENTS_KEPT: usize = 100;
+
+/// The key the incidents are kept under in the StateStore.
+const INCIDENTS_KEY: &str = "incidents";
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Debug)]
+pub struct IncidentId(pub u64);
+
+impl fmt::Display for IncidentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub enum IncidentState {
+    Open,
+    /// Someone is on it, repeats of the event no longer run actions.
+    Acknowledged,
+    Resolved,
+}
+
+/// A failing condition of a series of a polling function, from the event that opened it until it recovered.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct Incident {
+    pub id:              IncidentId,
+    pub monitor_id:      MonitorId,
+    pub series:          String,
+    /// The event that opened the incident, as in HistoryRecord::Event.
+    pub event:           String,
+    pub state:           IncidentState,
+    pub opened_at:       SystemTime,
+    pub last_seen:       SystemTime,
+    /// How many times an opening event fired while the incident was not resolved, including the first.
+    pub occurrences:     u32,
+    pub acknowledged_by: Option<String>,
+    pub acknowledged_at: Option<SystemTime>,
+    pub resolved_at:     Option<SystemTime>,
+}
+
+impl Incident {
+    /// How long the incident has been going on, or went on if it is resolved.
+    pub fn duration(&self) -> Duration {
+        self.resolved_at
+            .unwrap_or(self.last_seen)
+            .duration_since(self.opened_at)
+            .unwrap_or_default()
+    }
+}
+
+/// Which events of a polling function open an incident and which resolve it.
+/// Each series of the polling function has at most one unresolved incident at a time.
+#[derive(PartialEq, Clone, Debug)]
+pub struct IncidentRule<E: Event> {
+    opened_by:   Vec<EventKey<E>>,
+    resolved_by: Vec<EventKey<E>>,
+}
+
+impl<E: Event> IncidentRule<E> {
+    pub fn new(opened_by: impl Into<EventKey<E>>, resolved_by: impl Into<EventKey<E>>) -> Self {
+        IncidentRule {
+            opened_by:   vec![opened_by.into()],
+            resolved_by: vec![resolved_by.into()],
+        }
+    }
+
+    pub fn or_opened_by(mut self, event: impl Into<EventKey<E>>) -> Self {
+        self.opened_by.push(event.into());
+        self
+    }
+
+    pub fn or_resolved_by(mut self, event: impl Into<EventKey<E>>) -> Self {
+        self.resolved_by.push(event.into());
+        self
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum IncidentError {
+    #[error("No incident with id {0}.")]
+    UnknownIncident(IncidentId),
+    #[error("Incident {0} is already resolved.")]
+    AlreadyResolved(IncidentId),
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct IncidentLog {
+    last_id:   u64,
+    // Ordered by id
+    incidents: Vec<Incident>,
+}
+
+/// The incidents of a running PollingMonitor along with the rules of its polling functions.
+pub(crate) struct Incidents<E: Event> {
+    rules:       HashMap<MonitorId, IncidentRule<E>>,
+    log:         IncidentLog,
+    state_store: Option<StateStore>,
+}
+
+impl<E: Event> Incidents<E> {
+    /// Restore the incidents from the StateStore, if any.
+    pub(crate) fn new(state_store: Option<StateStore>) -> Self {
+        let log = match state_store.as_ref().map(|store| store.load(INCIDENTS_KEY)) {
+            Some(Ok(Some(log))) => log,
+            Some(Err(err)) => {
+                log::warn!("Failed to restore the incidents: {:#}", err);
+                IncidentLog::default()
+            }
+            _ => IncidentLog::default(),
+        };
+
+        Incidents {
+            rules: HashMap::new(),
+            log,
+            state_store,
+        }
+    }
+
+    pub(crate) fn set_rule(&mut self, id: &MonitorId, rule: Option<IncidentRule<E>>) {
+        match rule {
+            Some(rule) => self.rules.insert(id.clone(), rule),
+            None => self.rules.remove(id),
+        };
+    }
+
+    pub(crate) fn incidents(&self) -> Vec<Incident> {
+        self.log.incidents.clone()
+    }
+
+    pub(crate) fn get(&self, id: IncidentId) -> Option<&Incident> {
+        self.log.incidents.iter().find(|incident| incident.id == id)
+    }
+
+    /// Open, attach to or resolve an incident for the event and annotate it with the incident values.
+    /// Returns false for repeats of acknowledged incidents, their actions are held back.
+    ///
+    /// Suppressed events can only resolve incidents.
+    pub(crate) fn update(
+        &mut self,
+        event: &mut FiredEvent<E>,
+        series: &str,
+        suppressed: bool,
+    ) -> bool {
+        let rule = match self.rules.get(&event.payload.monitor_id) {
+            Some(rule) => rule,
+            None => return true,
+        };
+        let key = event.key();
+        let opens = rule.opened_by.contains(&key);
+        let resolves = rule.resolved_by.contains(&key);
+        if !resolves && (!opens || suppressed) {
+            return true;
+        }
+
+        let at = event.payload.timestamp;
+        let unresolved = self.log.incidents.iter_mut().find(|incident| {
+            incident.state != IncidentState::Resolved
+                && incident.monitor_id == event.payload.monitor_id
+                && incident.series == series
+        });
+
+        let values = &mut event.payload.values;
+        let dispatch = match (unresolved, opens) {
+            (Some(incident), true) => {
+                incident.occurrences += 1;
+                incident.last_seen = at;
+                values.insert("incident_id".to_string(), incident.id.0.into());
+                values.insert("incident_state".to_string(), "repeated".into());
+                incident.state == IncidentState::Open
+            }
+            (None, true) => {
+                self.log.last_id += 1;
+                let id = IncidentId(self.log.last_id);
+                self.log.incidents.push(Incident {
+                    id,
+                    monitor_id: event.payload.monitor_id.clone(),
+                    series: series.to_string(),
+                    event: format!("{:?}", key),
+                    state: IncidentState::Open,
+                    opened_at: at,
+                    last_seen: at,
+                    occurrences: 1,
+                    acknowledged_by: None,
+                    acknowledged_at: None,
+                    resolved_at: None,
+                });
+                values.insert("incident_id".to_string(), id.0.into());
+                values.insert("incident_state".to_string(), "opened".into());
+                true
+            }
+            (Some(incident), false) => {
+                incident.state = IncidentState::Resolved;
+                incident.resolved_at = Some(at);
+                let duration = incident.duration();
+                values.insert("incident_id".to_string(), incident.id.0.into());
+                values.insert("incident_state".to_string(), "resolved".into());
+                values.insert("incident_duration".to_string(), duration.as_secs().into());
+                values.insert(
+                    "incident_summary".to_string(),
+                    format!("resolved after {}", format_duration(duration)).into(),
+                );
+                self.forget_resolved();
+                true
+            }
+            (None, false) => return true,
+        };
+
+        self.save();
+        dispatch
+    }
+
+    /// Stop the repeats of the incident from running actions until it is resolved.
+    pub(crate) fn acknowledge(
+        &mut self,
+        id: IncidentId,
+        by: &str,
+        at: SystemTime,
+    ) -> Result<Incident, IncidentError> {
+        let incident = self
+            .log
+            .incidents
+            .iter_mut()
+            .find(|incident| incident.id == id)
+            .ok_or(IncidentError::UnknownIncident(id))?;
+        if incident.state == IncidentState::Resolved {
+            return Err(IncidentError::AlreadyResolved(id));
+        }
+
+        incident.state = IncidentState::Acknowledged;
+        incident.acknowledged_by = Some(by.to_string());
+        incident.acknowledged_at = Some(at);
+        let incident = incident.clone();
+        self.save();
+        Ok(incident)
+    }
+
+    fn forget_resolved(&mut self) {
+        let resolved = self
+            .log
+            .incidents
+            .iter()
+            .filter(|incident| incident.state == IncidentState::Resolved)
+            .count();
+        let mut to_forget = resolved.saturating_sub(RESOLVED_INCIDENTS_KEPT);
+        self.log.incidents.retain(|incident| {
+            let forget = to_forget > 0 && incident.state == IncidentState::Resolved;
+            if forget {
+                to_forget -= 1;
+            }
+            !forget
+        });
+    }
+
+    fn save(&self) {
+        if let Some(state_store) = &self.state_store {
+            if let Err(err) = state_store.save(INCIDENTS_KEY, &self.log) {
+                log::warn!("Failed to persist the incidents: {:#}", err);
+            }
+        }
+    }
+}
+
+/// Durations like `45s`, `14m`, `1h 5m` or `2d 3h`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (major, major_unit, minor, minor_unit) = match secs {
+        0..=59 => return format!("{}s", secs),
+        60..=3599 => return format!("{}m", secs / 60),
+        3600..=86399 => (secs / 3600, "h", secs % 3600 / 60, "m"),
+        _ => (secs / 86400, "d", secs % 86400 / 3600, "h"),
+    };
+    if minor == 0 {
+        format!("{}{}", major, major_unit)
+    } else {
+        format!("{}{} {}{}", major, major_unit, minor, minor_unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        state::tests::temp_state_store,
+        test_support::{ActionRecorder, MonitorHarness, ScriptedPolling},
+        PollingMonitor, PollingSchedule, VirtualClock,
+    };
+    use serde_json::Value;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(format_duration(Duration::from_secs(14 * 60 + 20)), "14m");
+        assert_eq!(format_duration(Duration::from_secs(3600)), "1h");
+        assert_eq!(format_duration(Duration::from_secs(3900)), "1h 5m");
+        assert_eq!(
+            format_duration(Duration::from_secs(2 * 86400 + 3 * 3600)),
+            "2d 3h"
+        );
+    }
+
+    #[test]
+    fn test_incident_lifecycle() {
+        let clock = VirtualClock::new();
+        let recorder = ActionRecorder::new(&clock);
+        let state_store = temp_state_store();
+        let every_10s = *PollingSchedule::default().interval(Duration::from_secs(10));
+
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .schedule_named_polling(
+                "wg0",
+                every_10s,
+                ScriptedPolling::new()
+                    .event("up")
+                    .events("down", 4)
+                    .event("up")
+                    .into_polling_func(),
+            )
+            .incidents("wg0", IncidentRule::new("down", "up"))
+            .unwrap()
+            .state_store(state_store.clone())
+            .register_action("down", recorder.action("page"))
+            .register_action("up", recorder.action("notify"));
+
+        let mut harness = MonitorHarness::new(monitor, &clock);
+        harness.advance(Duration::from_secs(25));
+        let incident = harness.acknowledge(IncidentId(1), "on-call").unwrap();
+        assert_eq!(incident.occurrences, 2);
+        harness.advance(Duration::from_secs(30));
+
+        // Acknowledged repeats at 30 and 40 run no actions, the recovery at 50 resolves the incident
+        assert_eq!(
+            recorder.timeline(),
+            vec![
+                (0, "notify".to_string(), EventKey::Polled("up")),
+                (10, "page".to_string(), EventKey::Polled("down")),
+                (20, "page".to_string(), EventKey::Polled("down")),
+                (50, "notify".to_string(), EventKey::Polled("up")),
+            ]
+        );
+        let invocations = recorder.invocations();
+        assert_eq!(invocations[0].event.value("incident_id"), None);
+        assert_eq!(
+            invocations[2].event.value("incident_state"),
+            Some(&Value::from("repeated"))
+        );
+        assert_eq!(
+            invocations[3].event.value("incident_summary"),
+            Some(&Value::from("resolved after 40s"))
+        );
+
+        let incidents = harness.incidents();
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(incidents[0].state, IncidentState::Resolved);
+        assert_eq!(incidents[0].occurrences, 4);
+        assert_eq!(incidents[0].acknowledged_by.as_deref(), Some("on-call"));
+        assert!(matches!(
+            harness.acknowledge(IncidentId(1), "on-call"),
+            Err(IncidentError::AlreadyResolved(_))
+        ));
+
+        // After a restart the incidents are restored and new ones continue the ids
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .schedule_named_polling(
+                "wg0",
+                every_10s,
+                ScriptedPolling::new().event("down").into_polling_func(),
+            )
+            .incidents("wg0", IncidentRule::new("down", "up"))
+            .unwrap()
+            .state_store(state_store);
+        let mut harness = MonitorHarness::new(monitor, &VirtualClock::new());
+        harness.advance(Duration::from_secs(0));
+        let incidents = harness.incidents();
+        assert_eq!(incidents.len(), 2);
+        assert_eq!(incidents[0].state, IncidentState::Resolved);
+        assert_eq!(incidents[1].id, IncidentId(2));
+        assert_eq!(incidents[1].state, IncidentState::Open);
+    }
+}
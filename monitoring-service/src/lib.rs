@@ -1,22 +1,75 @@
+mod action;
+mod clock;
+mod command;
+mod composite;
+mod dependency;
+mod dispatch;
+mod escalation;
+mod event;
+mod firing;
+mod handle;
+mod history;
+mod incident;
+mod limit;
+mod metric;
+mod runner;
+mod silence;
+mod state;
+mod stats;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+mod threshold;
+mod throttle;
+mod watchdog;
+
 use std::{
-    collections::HashMap,
-    hash::Hash,
+    collections::{BTreeSet, HashMap},
     sync::Arc,
-    thread::{sleep, spawn, JoinHandle, Result as ThreadResult},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use event_bus::{EventBus, EventBusError, TopicPattern};
 use internal_prelude::library_prelude::*;
+use serde::Serialize;
 
-// A significant event that should be reacted to with an action (or multiple)
-#[rustfmt::skip]
-pub trait Event: Eq
-    + Hash
-    + Send
-    + 'static // TODO
-    {}
+pub use action::{
+    ActionFunc, ActionFuncInternal, ActionOutcome, ActionPolicy, ActionReport, RetryPolicy,
+};
+pub use clock::{Clock, SystemClock, VirtualClock};
+pub use command::{
+    CommandAction, CommandError, CommandMonitor, CommandOutput, CommandSpec, OUTPUT_CAPTURED,
+};
+pub use composite::{CompositeEvent, Condition};
+pub use dependency::{Dependency, DependencyError, DependencyGraph, WhenParentFailing};
+pub use escalation::{EscalationPolicy, EscalationStep, StopCondition};
+pub use event::{
+    DeferredValues, Event, EventKey, EventPayload, FiredEvent, MonitorEvent, MonitorId,
+    Observation, PollFailure, PollRecovery, Stall, Values,
+};
 
-#[derive(PartialEq, Eq, Hash, Copy, Clone)]
+pub use firing::{Debounce, FiringMode, Hold, Hysteresis};
+pub use handle::{
+    MonitorControlError, MonitorState, MonitorStatus, PollingMonitorHandle, ACTION_REPORTS_KEPT,
+};
+pub use history::{HistoryEntry, HistoryQuery, HistoryRecord, HISTORY_KEPT, HISTORY_SAVE_INTERVAL};
+pub use incident::{
+    Incident, IncidentError, IncidentId, IncidentRule, IncidentState, RESOLVED_INCIDENTS_KEPT,
+};
+pub use limit::{ConcurrencyLimits, ConcurrencyStats, LimitStats, WhenBusy};
+pub use metric::{MetricMonitor, Sample, Severity, ThresholdEvent};
+pub use silence::{MaintenanceWindow, Silence, SilenceError, SilenceId, SilenceMatcher, Weekday};
+pub use state::StateStore;
+pub use stats::{DurationHistogram, ExecutionStats, DURATION_BUCKETS_MS};
+pub use threshold::{parse_duration, Comparison, Measure, ThresholdRule, ThresholdRuleError};
+pub use throttle::{RateLimit, Throttle};
+pub use watchdog::{Heartbeat, Watchdog};
+
+use composite::Composites;
+use dispatch::Dispatcher;
+use escalation::Escalations;
+use runner::PollingContext;
+
+#[derive(Serialize, PartialEq, Eq, Hash, Copy, Clone, Debug)]
 pub struct PollingSchedule {
     // This interval starts ticking down after finishing the current poll
     //
@@ -47,152 +100,413 @@ pub trait PollingFuncInternal<E: Event>: Fn() -> Result<E>
     + 'static // TODO
     {}
 
-pub struct PollingFunc<E: Event>(Box<dyn PollingFuncInternal<E>>);
+impl<E: Event, F> PollingFuncInternal<E> for F where F: Fn() -> Result<E> + Send + 'static {}
+
+/// Called with the time of the poll according to the Clock of the PollingMonitor.
+pub struct PollingFunc<E: Event>(Box<dyn Fn(Instant) -> Result<Vec<Observation<E>>> + Send>);
 
 impl<E: Event> PollingFunc<E> {
-    // fn new(f: impl PollingFuncInternal<E>) -> Self {
-    //     PollingFunc(Box::new(f))
-    // }
+    pub fn new(f: impl PollingFuncInternal<E>) -> Self {
+        PollingFunc(Box::new(move |_| {
+            f().map(|event| vec![Observation::new(event)])
+        }))
+    }
+
+    /// A polling function that attaches values to the events it returns.
+    /// The values are passed on to the actions in the EventPayload.
+    pub fn with_payload(f: impl Fn() -> Result<Observation<E>> + Send + 'static) -> Self {
+        PollingFunc(Box::new(move |_| f().map(|observation| vec![observation])))
+    }
+
+    /// A polling function that observes several things per poll, each in its own series.
+    pub fn with_observations(f: impl Fn() -> Result<Vec<Observation<E>>> + Send + 'static) -> Self {
+        PollingFunc(Box::new(move |_| f()))
+    }
+
+    /// Like with_observations, for polling functions with timing logic of their own, like holds and rates,
+    /// which should use the time of the poll so they follow a VirtualClock in tests.
+    pub fn with_clock(f: impl Fn(Instant) -> Result<Vec<Observation<E>>> + Send + 'static) -> Self {
+        PollingFunc(Box::new(f))
+    }
 }
 
-#[rustfmt::skip]
-pub trait ActionFuncInternal: Fn() -> Result<()>
-    + Send
-    {}
+/// Per polling function settings besides its schedule.
+pub struct MonitorOptions<E: Event> {
+    firing_mode:         FiringMode<E>,
+    dependencies:        Vec<Dependency<E>>,
+    when_parent_failing: WhenParentFailing,
+    incident_rule:       Option<IncidentRule<E>>,
+    group:               Option<String>,
+    when_busy:           WhenBusy,
+}
+
+impl<E: Event> Default for MonitorOptions<E> {
+    fn default() -> Self {
+        MonitorOptions {
+            firing_mode:         FiringMode::default(),
+            dependencies:        Vec::new(),
+            when_parent_failing: WhenParentFailing::default(),
+            incident_rule:       None,
+            group:               None,
+            when_busy:           WhenBusy::default(),
+        }
+    }
+}
+
+impl<E: Event> MonitorOptions<E> {
+    /// Set which polled events fire their actions, defaults to FiringMode::Level.
+    pub fn firing_mode(mut self, firing_mode: FiringMode<E>) -> Self {
+        self.firing_mode = firing_mode;
+        self
+    }
+
+    pub fn depends_on(mut self, dependency: Dependency<E>) -> Self {
+        self.dependencies.push(dependency);
+        self
+    }
+
+    /// What happens to events while a parent is failing, defaults to WhenParentFailing::Suppress.
+    pub fn when_parent_failing(mut self, when_parent_failing: WhenParentFailing) -> Self {
+        self.when_parent_failing = when_parent_failing;
+        self
+    }
 
-pub struct ActionFunc(Box<dyn ActionFuncInternal>);
+    /// Track the failures of the polling function as Incidents.
+    pub fn incidents(mut self, rule: IncidentRule<E>) -> Self {
+        self.incident_rule = Some(rule);
+        self
+    }
+
+    /// Count the polls against the concurrency limit of the group, see ConcurrencyLimits.
+    pub fn group(mut self, group: &str) -> Self {
+        self.group = Some(group.to_string());
+        self
+    }
+
+    /// What a poll does when it can't start right away, defaults to WhenBusy::Queue.
+    pub fn when_busy(mut self, when_busy: WhenBusy) -> Self {
+        self.when_busy = when_busy;
+        self
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum PollingMonitorError {
+    /// The options of a polling function were set before it was scheduled, or under a different id.
+    #[error("No polling function scheduled with id {0}.")]
+    UnknownMonitor(MonitorId),
+}
+
+struct ScheduledPolling<E: Event> {
+    schedule:     PollingSchedule,
+    polling_func: PollingFunc<E>,
+    options:      MonitorOptions<E>,
+}
 
 pub struct PollingMonitor<E: Event> {
-    polling_schedule: HashMap<PollingSchedule, PollingFunc<E>>,
-    event_to_actions: HashMap<E, Vec<ActionFunc>>,
+    host:             String,
+    polling_schedule: HashMap<MonitorId, ScheduledPolling<E>>,
+    dispatcher:       Dispatcher<E>,
+    history_size:     usize,
+    state_store:      Option<StateStore>,
+    composites:       Composites<E>,
+    escalations:      Escalations<E>,
+    windows:          Vec<MaintenanceWindow>,
+    event_bus:        Option<EventBus>,
+    limits:           ConcurrencyLimits,
+    watchdog:         Option<Watchdog>,
+    subscriptions:    Vec<(String, ActionFunc<String>)>,
 }
 
 impl<E: Event> Default for PollingMonitor<E> {
     fn default() -> Self {
         PollingMonitor {
+            host:             local_hostname(),
             polling_schedule: HashMap::new(),
-            event_to_actions: HashMap::new(),
+            dispatcher:       Dispatcher::default(),
+            history_size:     HISTORY_KEPT,
+            state_store:      None,
+            composites:       Composites::default(),
+            escalations:      Escalations::default(),
+            windows:          Vec::new(),
+            event_bus:        None,
+            limits:           ConcurrencyLimits::default(),
+            watchdog:         None,
+            subscriptions:    Vec::new(),
         }
     }
 }
 
+fn local_hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|hostname| hostname.trim().to_string())
+        .unwrap_or_else(|_| "localhost".to_string())
+}
+
 impl<E: Event> PollingMonitor<E> {
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// The host reported in the EventPayload, defaults to the hostname of the machine.
+    pub fn host(&mut self, host: &str) -> &mut Self {
+        self.host = host.to_string();
+        self
+    }
+
+    /// How many HistoryEntries to keep per polling function, defaults to HISTORY_KEPT.
+    pub fn history_size(&mut self, history_size: usize) -> &mut Self {
+        self.history_size = history_size;
+        self
+    }
+
+    /// Persist the history of each polling function, the incidents and the silences so they survive restarts.
+    /// The history is saved every HISTORY_SAVE_INTERVAL, so a crash loses the entries since.
+    pub fn state_store(&mut self, state_store: StateStore) -> &mut Self {
+        self.state_store = Some(state_store);
+        self
+    }
+
+    /// Publish the events whose actions are dispatched on the EventBus, see FiredEvent for their topics.
+    pub fn event_bus(&mut self, event_bus: EventBus) -> &mut Self {
+        self.event_bus = Some(event_bus);
+        self
+    }
+
+    /// Run the action for every event on the EventBus whose topic matches the pattern, see FiredEvent::of_bus_event.
+    /// Like the actions of events it runs within the concurrency limits and with its ActionPolicy and Throttle,
+    /// and its runs count towards the action stats. It runs in a thread of the subscription, never in the publishing one.
+    /// Fails if the pattern is invalid.
+    pub fn subscribe(
+        &mut self,
+        pattern: &str,
+        action: ActionFunc<String>,
+    ) -> Result<&mut Self, EventBusError> {
+        pattern.parse::<TopicPattern>()?;
+        self.subscriptions.push((pattern.to_string(), action));
+        Ok(self)
+    }
+
+    /// Bound how many polling functions and actions run at once, by default there is no limit.
+    pub fn concurrency_limits(&mut self, limits: ConcurrencyLimits) -> &mut Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Watch that the polling loops keep ticking and send heartbeats while they do.
+    pub fn watchdog(&mut self, watchdog: Watchdog) -> &mut Self {
+        self.watchdog = Some(watchdog);
+        self
+    }
+
+    /// Schedule a polling function under a generated MonitorId.
     pub fn schedule_polling(
         &mut self,
         schedule: PollingSchedule,
         polling_func: PollingFunc<E>,
     ) -> &mut Self {
-        self.polling_schedule.insert(schedule, polling_func);
-        self
+        let id = MonitorId::new(&format!("monitor-{}", self.polling_schedule.len()));
+        self.schedule_named_polling(id, schedule, polling_func)
     }
 
-    pub fn register_action(&mut self, event: E, action: ActionFunc) -> &mut Self {
-        if let Some(actions) = self.event_to_actions.get_mut(&event) {
-            actions.push(action);
-        } else {
-            self.event_to_actions.insert(event, vec![action]);
-        }
+    /// Schedule a polling function, replacing any polling function already scheduled under the same id.
+    pub fn schedule_named_polling(
+        &mut self,
+        id: impl Into<MonitorId>,
+        schedule: PollingSchedule,
+        polling_func: PollingFunc<E>,
+    ) -> &mut Self {
+        self.polling_schedule.insert(
+            id.into(),
+            ScheduledPolling {
+                schedule,
+                polling_func,
+                options: MonitorOptions::default(),
+            },
+        );
         self
     }
 
-    /// Start the PollingMonitor in a separate thread and return a PollingMonitorHandle to manipulate its thread.
-    pub fn start(self) -> PollingMonitorHandle {
-        // This is shared state between all the polling processes and the PollingMonitorHandle
-        let polling_monitor_handle = Arc::new(Mutex::new(PollingMonitorHandleInner::default()));
-
-        // NOTE: Is the mutext needed despite it being used as readonly?
-        let event_to_actions = Arc::new(Mutex::new(self.event_to_actions));
-
-        let mut polling_processes = Vec::new();
-        for (schedule, polling_func) in self.polling_schedule.into_iter() {
-            // Clone for this polling process
-            let schedules_event_to_actions_clone = Arc::clone(&event_to_actions);
-            let schedules_polling_monitor_handle_clone = Arc::clone(&polling_monitor_handle);
+    /// Set which polled events fire their actions, defaults to FiringMode::Level.
+    pub fn firing_mode(
+        &mut self,
+        id: impl Into<MonitorId>,
+        firing_mode: FiringMode<E>,
+    ) -> Result<&mut Self, PollingMonitorError> {
+        self.options(id.into())?.firing_mode = firing_mode;
+        Ok(self)
+    }
 
-            let polling_process = move || -> Result<i32> {
-                loop {
-                    if let Ok(event) = polling_func.0() {
-                        if let Some(actions) = schedules_event_to_actions_clone.lock().get(&event) {
-                            for action in actions {
-                                (*action.0)()?;
-                            }
-                        }
-                    }
+    /// Declare that the events of a polling function are meaningless while the parent is failing.
+    pub fn depends_on(
+        &mut self,
+        id: impl Into<MonitorId>,
+        dependency: Dependency<E>,
+    ) -> Result<&mut Self, PollingMonitorError> {
+        self.options(id.into())?.dependencies.push(dependency);
+        Ok(self)
+    }
 
-                    if !(schedules_polling_monitor_handle_clone).lock().is_running {
-                        return Ok(0);
-                    }
+    /// What happens to the events of a polling function while a parent is failing,
+    /// defaults to WhenParentFailing::Suppress.
+    pub fn when_parent_failing(
+        &mut self,
+        id: impl Into<MonitorId>,
+        when_parent_failing: WhenParentFailing,
+    ) -> Result<&mut Self, PollingMonitorError> {
+        self.options(id.into())?.when_parent_failing = when_parent_failing;
+        Ok(self)
+    }
 
-                    sleep(schedule.interval);
-                }
-            };
+    /// Track the failures of a polling function as Incidents.
+    /// Actions of its events get the `incident_id` and `incident_state` values, a resolving event also
+    /// gets `incident_duration` in seconds and an `incident_summary` like "resolved after 14m".
+    pub fn incidents(
+        &mut self,
+        id: impl Into<MonitorId>,
+        rule: IncidentRule<E>,
+    ) -> Result<&mut Self, PollingMonitorError> {
+        self.options(id.into())?.incident_rule = Some(rule);
+        Ok(self)
+    }
 
-            polling_processes.push(polling_process);
-        }
+    /// Count the polls of a polling function against the concurrency limit of the group.
+    pub fn group(
+        &mut self,
+        id: impl Into<MonitorId>,
+        group: &str,
+    ) -> Result<&mut Self, PollingMonitorError> {
+        self.options(id.into())?.group = Some(group.to_string());
+        Ok(self)
+    }
 
-        for process in polling_processes {
-            tokio::spawn(async move { process() });
-        }
+    /// What a poll of a polling function does when it can't start right away because its concurrency
+    /// limits are busy or its previous poll is still running, defaults to WhenBusy::Queue.
+    pub fn when_busy(
+        &mut self,
+        id: impl Into<MonitorId>,
+        when_busy: WhenBusy,
+    ) -> Result<&mut Self, PollingMonitorError> {
+        self.options(id.into())?.when_busy = when_busy;
+        Ok(self)
+    }
 
-        PollingMonitorHandle::new(polling_monitor_handle, spawn(|| {}))
+    fn options(&mut self, id: MonitorId) -> Result<&mut MonitorOptions<E>, PollingMonitorError> {
+        self.polling_schedule
+            .get_mut(&id)
+            .map(|scheduled| &mut scheduled.options)
+            .ok_or(PollingMonitorError::UnknownMonitor(id))
     }
-}
 
-pub struct PollingMonitorHandle {
-    inner:       Arc<Mutex<PollingMonitorHandleInner>>,
-    join_handle: JoinHandle<()>,
-}
+    /// Check that every dependency is on a scheduled polling function and that they form no cycles.
+    pub fn validate(&self) -> Result<()> {
+        let graph = self.dependency_graph();
+        let known: BTreeSet<MonitorId> = self.polling_schedule.keys().cloned().collect();
+        graph.validate_parents(&known)?;
+        graph.validate()?;
+        Ok(())
+    }
 
-impl PollingMonitorHandle {
-    fn new(inner: Arc<Mutex<PollingMonitorHandleInner>>, join_handle: JoinHandle<()>) -> Self {
-        PollingMonitorHandle { inner, join_handle }
+    fn dependency_graph(&self) -> DependencyGraph {
+        let mut graph = DependencyGraph::default();
+        for (id, scheduled) in &self.polling_schedule {
+            for dependency in &scheduled.options.dependencies {
+                graph.add(id.clone(), dependency.parent().clone());
+            }
+        }
+        graph
     }
 
-    /// Returns if the Watcher thread is semantically running.
-    /// Even if this returns false in practice it may still be running it's final loop before termination.
-    /// To make sure the logic loop has stopped use join() or stop_and_join().
-    pub fn is_running(&self) -> bool {
-        self.inner.lock().is_running
+    /// Emit an event of its own when a condition on the events of several polling functions is met.
+    /// Actions are registered for the event like for any other, its payload has the MonitorId of the composite.
+    pub fn composite(
+        &mut self,
+        id: impl Into<MonitorId>,
+        composite: CompositeEvent<E>,
+    ) -> &mut Self {
+        self.composites.add(id.into(), composite);
+        self
     }
 
-    /// Signals the Watcher to stop.
-    /// In practice the logic loop might still finish up it's final loop before termination.
-    /// To make sure the logic loop has stopped use join() or stop_and_join().
-    pub fn stop(&mut self) {
-        self.inner.lock().is_running = false;
+    /// Register an action to be run each time the event occurs.
+    /// Besides the events returned by polling functions, actions can be registered for
+    /// EventKey::PollFailed and EventKey::PollRecovered to react to broken polling functions,
+    /// and for EventKey::Stalled to react to stalled polling loops noticed by the Watchdog.
+    pub fn register_action(
+        &mut self,
+        event: impl Into<EventKey<E>>,
+        action: ActionFunc<E>,
+    ) -> &mut Self {
+        self.dispatcher.register_action(event.into(), action);
+        self
     }
 
-    /// Join the thread running the watcher logic loop.
-    pub fn join(self) -> ThreadResult<()> {
-        self.join_handle.join()
+    /// Run the steps of an escalation policy for the event, besides the actions registered for it.
+    /// Escalations are not started for events suppressed because of a failing parent
+    /// or for repeats of acknowledged incidents.
+    pub fn register_escalation(
+        &mut self,
+        event: impl Into<EventKey<E>>,
+        policy: EscalationPolicy<E>,
+    ) -> &mut Self {
+        self.escalations.add(event.into(), policy);
+        self
     }
 
-    /// Signal the Watcher to stop and wait for it's thread to terminate.
-    pub fn stop_and_join(mut self) -> ThreadResult<()> {
-        self.stop();
-        self.join()
+    /// Silence the matching events every week, silences for a single time range are added to the running
+    /// PollingMonitor with PollingMonitorHandle::silence().
+    pub fn maintenance_window(&mut self, window: MaintenanceWindow) -> &mut Self {
+        self.windows.push(window);
+        self
     }
-}
 
-/// Inner representation of WatcherHandle
-struct PollingMonitorHandleInner {
-    is_running: bool,
-}
+    /// Limit how often the actions of an event run, on top of the Throttles of the actions themselves.
+    pub fn throttle_event(
+        &mut self,
+        event: impl Into<EventKey<E>>,
+        throttle: Throttle,
+    ) -> &mut Self {
+        self.dispatcher.throttle_event(event.into(), throttle);
+        self
+    }
 
-impl Default for PollingMonitorHandleInner {
-    fn default() -> Self {
-        PollingMonitorHandleInner { is_running: true }
+    /// Start each polling function in a separate thread and return a PollingMonitorHandle to control them.
+    ///
+    /// Fails without starting anything if the dependencies form a cycle. Parents that aren't scheduled yet
+    /// may still be added to the running PollingMonitor, use validate() to rule them out as well.
+    pub fn start(mut self) -> Result<PollingMonitorHandle<E>> {
+        self.dependency_graph().validate()?;
+        let context = Arc::new(PollingContext::new(&mut self, Arc::new(SystemClock)));
+
+        let has_escalations = !context.escalations.lock().is_empty();
+        let mut handle = PollingMonitorHandle::new(context);
+        if has_escalations {
+            handle.spawn_escalation_process();
+        }
+        if let Some(watchdog) = self.watchdog {
+            handle.spawn_watchdog_process(watchdog);
+        }
+        for (pattern, action) in self.subscriptions.drain(..) {
+            handle.spawn_subscription_process(&pattern, action);
+        }
+        for (id, scheduled) in self.polling_schedule.into_iter() {
+            handle
+                .add_monitor(
+                    id,
+                    scheduled.schedule,
+                    scheduled.polling_func,
+                    scheduled.options,
+                )
+                .expect("polling functions with unique ids and no dependency cycles");
+        }
+        Ok(handle)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    // use std::{process::Command, thread::sleep};
+    use test_support::{ActionRecorder, MonitorHarness, ScriptedPolling};
 
     type MyEvent = &'static str;
 
@@ -202,51 +516,183 @@ mod tests {
 
     impl PollingFuncInternal<MyEvent> for MyPollingFunc {}
 
-    // struct BasePollingFunc(dyn Fn() -> Result<String>);
-    // impl PollingFuncInternal<String> for BasePollingFunc {}
+    #[test]
+    fn test_actual_watcher() {
+        let clock = VirtualClock::new();
+        let recorder = ActionRecorder::new(&clock);
+
+        let polling_func = ScriptedPolling::new()
+            .event("up")
+            .events("down", 3)
+            .failure("timed out")
+            .failure("timed out")
+            .event("down")
+            .event("up")
+            .into_polling_func();
+        let debounce = Debounce::new(Hold::default())
+            .hold_event("down", *Hold::default().duration(Duration::from_secs(15)))
+            .clone();
+        let mut page = recorder.action("page");
+        page.throttle(
+            Throttle::default()
+                .cooldown(Duration::from_secs(15))
+                .clone(),
+        );
+
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .schedule_named_polling(
+                "wg0",
+                *PollingSchedule::default().interval(Duration::from_secs(10)),
+                polling_func,
+            )
+            .firing_mode("wg0", FiringMode::Debounced(debounce))
+            .unwrap()
+            .register_action("up", recorder.action("log"))
+            .register_action("down", recorder.action("log"))
+            .register_action(EventKey::PollFailed, page)
+            .register_action(EventKey::PollRecovered, recorder.action("log"));
+
+        let mut harness = MonitorHarness::new(monitor, &clock);
+        harness.advance(Duration::from_secs(75));
 
-    // fn poll1() -> Result<MyEvent> {
-    //     Ok("")
-    // }
-
-    #[tokio::test]
-    async fn test_actual_watcher() {
-        // PollingMonitor::new().schedule_polling(
-        //     *PollingSchedule::default().interval(Duration::new(1, 0)),
-        //     PollingFunc::new(poll1),
-        // );
-
-        /*         let trigger_created_file_name = "trigger_created_file";
-
-        let mut sec_since_epoch = Command::new("date");
-        sec_since_epoch.arg("+%s");
-
-        let mut echo_done = Command::new("touch");
-        echo_done.arg(trigger_created_file_name);
-
-        let interval = Duration::from_millis(25);
-
-        let mut monitor = PollingMonitor::new(sec_since_epoch, echo_done);
-        monitor.interval(interval);
+        assert_eq!(
+            harness.fired_timeline(),
+            vec![
+                (0, EventKey::Polled("up")),
+                (30, EventKey::Polled("down")),
+                (40, EventKey::PollFailed),
+                (50, EventKey::PollFailed),
+                (60, EventKey::PollRecovered),
+                (70, EventKey::Polled("up")),
+            ]
+        );
+        assert_eq!(
+            recorder.timeline(),
+            vec![
+                (0, "log".to_string(), EventKey::Polled("up")),
+                (30, "log".to_string(), EventKey::Polled("down")),
+                (40, "page".to_string(), EventKey::PollFailed),
+                (60, "log".to_string(), EventKey::PollRecovered),
+                (70, "log".to_string(), EventKey::Polled("up")),
+            ]
+        );
+        assert_eq!(
+            harness
+                .action_reports()
+                .iter()
+                .filter(|report| report.outcome == ActionOutcome::Suppressed)
+                .count(),
+            1
+        );
+    }
 
-        let monitor_handle = monitor.start();
+    #[test]
+    fn test_options_of_unknown_monitor() {
+        let mut monitor = PollingMonitor::<MyEvent>::new();
+        monitor.schedule_named_polling(
+            "wg0",
+            PollingSchedule::default(),
+            PollingFunc::new(|| Ok("up")),
+        );
+        assert!(monitor.when_busy("wg0", WhenBusy::Skip).is_ok());
+        assert!(matches!(
+            monitor.depends_on("wg1", Dependency::new("wg0")),
+            Err(PollingMonitorError::UnknownMonitor(id)) if id == MonitorId::new("wg1")
+        ));
+    }
 
-        sleep(Duration::from_millis(50));
-        monitor_handle
-            .stop_and_join()
-            .expect("Join on watcher thread failed");
+    #[test]
+    fn test_events_are_published() {
+        let clock = VirtualClock::new();
+        let recorder = ActionRecorder::new(&clock);
+        let event_bus = EventBus::new();
+        let (_, published) = event_bus.channel("monitoring.wg0.**").unwrap();
+
+        let polling_func = ScriptedPolling::new()
+            .event("up")
+            .failure("timed out")
+            .event("down")
+            .into_polling_func();
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .host("server-1")
+            .event_bus(event_bus)
+            .schedule_named_polling(
+                "wg0",
+                *PollingSchedule::default().interval(Duration::from_secs(10)),
+                polling_func,
+            )
+            .register_action("up", recorder.action("log"))
+            .register_action(EventKey::PollFailed, recorder.action("page"));
+
+        let mut harness = MonitorHarness::new(monitor, &clock);
+        harness.advance(Duration::from_secs(25));
+
+        let published: Vec<event_bus::BusEvent> = published.try_iter().collect();
+        assert_eq!(
+            published
+                .iter()
+                .map(|event| event.topic.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "monitoring.wg0.up",
+                "monitoring.wg0.PollFailed",
+                "monitoring.wg0.PollRecovered",
+                "monitoring.wg0.down",
+            ]
+        );
+        assert_eq!(published[0].source, "server-1");
+        assert_eq!(
+            published[1].get("error"),
+            Some(&serde_json::Value::from("timed out"))
+        );
+    }
 
-        let status_code = Command::new("rm")
-            .arg(trigger_created_file_name)
-            .output()
-            .expect("Check of trigger created file failed to run")
-            .status
-            .code()
+    #[test]
+    fn test_subscriptions() {
+        let event_bus = EventBus::new();
+        let (ran, runs) = crossbeam::channel::unbounded();
+        let mut monitor = PollingMonitor::<&'static str>::new();
+        assert!(monitor
+            .subscribe("network..down", ActionFunc::new("invalid", |_| Ok(())))
+            .is_err());
+        monitor
+            .event_bus(event_bus.clone())
+            .concurrency_limits(ConcurrencyLimits::default().actions(1))
+            .subscribe(
+                "network.interface.*.down",
+                ActionFunc::new("record", move |event: &FiredEvent<String>| {
+                    let _ = ran.send((event.key(), event.payload.clone()));
+                    Ok(())
+                }),
+            )
             .unwrap();
-
+        let handle = monitor.start().unwrap();
+
+        event_bus.publish(
+            event_bus::BusEvent::new(event_bus::Category::Network, &["interface", "wg0", "down"])
+                .source("server-1")
+                .value("interface", "wg0"),
+        );
+        let (key, payload) = runs.recv_timeout(Duration::from_secs(5)).unwrap();
         assert_eq!(
-            status_code, 0,
-            "Watcher trigger command didn't run or didn't create a file successfully"
-        ); */
+            key,
+            EventKey::Polled("network.interface.wg0.down".to_string())
+        );
+        assert_eq!(
+            (payload.monitor_id.as_str(), payload.host.as_str()),
+            ("network", "server-1")
+        );
+        assert_eq!(payload.values["interface"], serde_json::Value::from("wg0"));
+
+        // The run counts towards the action stats and the concurrency limits
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !handle.action_stats().contains_key("record") {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(handle.concurrency().actions.unwrap().acquired, 1);
+        handle.stop_and_join().unwrap();
     }
 }
@@ -0,0 +1,345 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use internal_prelude::library_prelude::*;
+use serde::Serialize;
+
+use crate::{ActionFunc, ActionReport, Event, FiredEvent};
+
+/// How long a queued poll waits for a slot before checking whether it should still wait.
+pub(crate) const QUEUE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Bounds how many polling functions and actions run at once, so many command monitors can't overload the machine.
+///
+/// The polls and actions limits apply to all polling functions and actions respectively,
+/// a group limit applies to the polling functions and actions of the group together.
+/// Groups without a limit are unbounded.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct ConcurrencyLimits {
+    polls:   Option<usize>,
+    actions: Option<usize>,
+    groups:  BTreeMap<String, usize>,
+}
+
+impl ConcurrencyLimits {
+    pub fn polls(mut self, limit: usize) -> Self {
+        self.polls = Some(limit.max(1));
+        self
+    }
+
+    pub fn actions(mut self, limit: usize) -> Self {
+        self.actions = Some(limit.max(1));
+        self
+    }
+
+    pub fn group(mut self, group: &str, limit: usize) -> Self {
+        self.groups.insert(group.to_string(), limit.max(1));
+        self
+    }
+}
+
+/// What a poll does when its turn comes but it can't start right away,
+/// because its previous poll or others holding the slots of its limits are still running.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub enum WhenBusy {
+    /// Wait for a free slot and poll as soon as there is one.
+    /// A waiting poll is given up on when the polling function is paused, rescheduled, removed or stopped.
+    #[default]
+    Queue,
+    /// Skip the poll and try again on the next tick of the schedule.
+    Skip,
+}
+
+/// Usage of a single limit since the PollingMonitor started.
+#[derive(Serialize, PartialEq, Eq, Clone, Debug, Default)]
+pub struct LimitStats {
+    pub limit:      usize,
+    pub running:    usize,
+    pub queued:     usize,
+    pub acquired:   u64,
+    /// How many polls were skipped because the limit was reached.
+    pub skipped:    u64,
+    pub total_wait: Duration,
+    pub max_wait:   Duration,
+}
+
+#[derive(Serialize, PartialEq, Eq, Clone, Debug, Default)]
+pub struct ConcurrencyStats {
+    pub polls:   Option<LimitStats>,
+    pub actions: Option<LimitStats>,
+    pub groups:  BTreeMap<String, LimitStats>,
+}
+
+struct Semaphore {
+    stats: Mutex<LimitStats>,
+    freed: Condvar,
+}
+
+impl Semaphore {
+    fn new(limit: usize) -> Self {
+        Semaphore {
+            stats: Mutex::new(LimitStats {
+                limit,
+                ..LimitStats::default()
+            }),
+            freed: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self) {
+        let started = Instant::now();
+        let mut stats = self.stats.lock();
+        if stats.running >= stats.limit {
+            stats.queued += 1;
+            while stats.running >= stats.limit {
+                self.freed.wait(&mut stats);
+            }
+            stats.queued -= 1;
+        }
+
+        let waited = started.elapsed();
+        stats.running += 1;
+        stats.acquired += 1;
+        stats.total_wait += waited;
+        stats.max_wait = stats.max_wait.max(waited);
+    }
+
+    /// Take a slot, waiting for at most the timeout for one to be freed. Returns whether one was taken,
+    /// the wait is counted from when the caller started waiting.
+    fn acquire_within(&self, timeout: Duration, started: Instant) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut stats = self.stats.lock();
+        if stats.running >= stats.limit {
+            stats.queued += 1;
+            while stats.running >= stats.limit {
+                if self.freed.wait_until(&mut stats, deadline).timed_out() {
+                    break;
+                }
+            }
+            stats.queued -= 1;
+            if stats.running >= stats.limit {
+                return false;
+            }
+        }
+
+        let waited = started.elapsed();
+        stats.running += 1;
+        stats.acquired += 1;
+        stats.total_wait += waited;
+        stats.max_wait = stats.max_wait.max(waited);
+        true
+    }
+
+    fn try_acquire(&self) -> bool {
+        let mut stats = self.stats.lock();
+        if stats.running >= stats.limit {
+            stats.skipped += 1;
+            return false;
+        }
+        stats.running += 1;
+        stats.acquired += 1;
+        true
+    }
+
+    fn release(&self) {
+        self.stats.lock().running -= 1;
+        self.freed.notify_one();
+    }
+}
+
+/// Slots taken from the limits, freed when dropped.
+pub(crate) struct Permits<'a>(Vec<&'a Semaphore>);
+
+impl Drop for Permits<'_> {
+    fn drop(&mut self) {
+        for semaphore in &self.0 {
+            semaphore.release();
+        }
+    }
+}
+
+/// Enforces the ConcurrencyLimits of a PollingMonitor.
+///
+/// Slots are always taken group first, and polls free theirs before their actions run,
+/// so nothing holding a slot ever waits for one it already has.
+#[derive(Default)]
+pub(crate) struct Limiter {
+    polls:   Option<Semaphore>,
+    actions: Option<Semaphore>,
+    groups:  BTreeMap<String, Semaphore>,
+}
+
+impl Limiter {
+    pub(crate) fn new(limits: ConcurrencyLimits) -> Self {
+        Limiter {
+            polls:   limits.polls.map(Semaphore::new),
+            actions: limits.actions.map(Semaphore::new),
+            groups:  limits
+                .groups
+                .into_iter()
+                .map(|(group, limit)| (group, Semaphore::new(limit)))
+                .collect(),
+        }
+    }
+
+    fn semaphores<'a>(
+        &'a self,
+        kind: &'a Option<Semaphore>,
+        group: Option<&str>,
+    ) -> Vec<&'a Semaphore> {
+        group
+            .and_then(|group| self.groups.get(group))
+            .into_iter()
+            .chain(kind.as_ref())
+            .collect()
+    }
+
+    /// Take the slots of a poll, returns None without waiting if the poll should be skipped.
+    ///
+    /// A queued poll asks keep_waiting every QUEUE_CHECK_INTERVAL it waits,
+    /// and gives up the slots it already took and returns None once it answers false.
+    pub(crate) fn acquire_poll(
+        &self,
+        group: Option<&str>,
+        when_busy: WhenBusy,
+        mut keep_waiting: impl FnMut() -> bool,
+    ) -> Option<Permits<'_>> {
+        let semaphores = self.semaphores(&self.polls, group);
+        let mut permits = Permits(Vec::with_capacity(semaphores.len()));
+        for semaphore in semaphores {
+            match when_busy {
+                WhenBusy::Queue => {
+                    let started = Instant::now();
+                    while !semaphore.acquire_within(QUEUE_CHECK_INTERVAL, started) {
+                        if !keep_waiting() {
+                            return None;
+                        }
+                    }
+                }
+                WhenBusy::Skip => {
+                    if !semaphore.try_acquire() {
+                        return None;
+                    }
+                }
+            }
+            permits.0.push(semaphore);
+        }
+        Some(permits)
+    }
+
+    /// Run the action once there are slots for it.
+    pub(crate) fn run_action<E: Event>(
+        &self,
+        action: &ActionFunc<E>,
+        event: &FiredEvent<E>,
+        now: Instant,
+    ) -> ActionReport {
+        let semaphores = self.semaphores(&self.actions, action.group_name());
+        semaphores.iter().for_each(|semaphore| semaphore.acquire());
+        let _permits = Permits(semaphores);
+        action.run(event, now)
+    }
+
+    pub(crate) fn stats(&self) -> ConcurrencyStats {
+        let stats = |semaphore: &Semaphore| semaphore.stats.lock().clone();
+        ConcurrencyStats {
+            polls:   self.polls.as_ref().map(stats),
+            actions: self.actions.as_ref().map(stats),
+            groups:  self
+                .groups
+                .iter()
+                .map(|(group, semaphore)| (group.clone(), stats(semaphore)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread::{sleep, spawn},
+    };
+
+    #[test]
+    fn test_limits() {
+        let limiter = Arc::new(Limiter::new(
+            ConcurrencyLimits::default().polls(3).group("commands", 2),
+        ));
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+
+        let threads: Vec<_> = (0..6)
+            .map(|_| {
+                let limiter = Arc::clone(&limiter);
+                let running = Arc::clone(&running);
+                let max_running = Arc::clone(&max_running);
+                spawn(move || {
+                    let _permits = limiter.acquire_poll(Some("commands"), WhenBusy::Queue, || true);
+                    let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now_running, Ordering::SeqCst);
+                    sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .for_each(|thread| thread.join().unwrap());
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+
+        let stats = limiter.stats();
+        let group = &stats.groups["commands"];
+        assert_eq!((group.limit, group.running, group.acquired), (2, 0, 6));
+        assert!(group.max_wait >= Duration::from_millis(20));
+        assert_eq!(stats.polls.as_ref().map(|polls| polls.acquired), Some(6));
+        assert_eq!(stats.actions, None);
+
+        // Skipping never waits and leaves the slots of the other limits free
+        let first = limiter.acquire_poll(None, WhenBusy::Skip, || true);
+        let second = limiter.acquire_poll(Some("commands"), WhenBusy::Skip, || true);
+        let third = limiter.acquire_poll(Some("commands"), WhenBusy::Skip, || true);
+        assert!(first.is_some() && second.is_some() && third.is_some());
+        assert!(limiter
+            .acquire_poll(Some("other"), WhenBusy::Skip, || true)
+            .is_none());
+        drop(third);
+        assert!(limiter
+            .acquire_poll(Some("commands"), WhenBusy::Skip, || true)
+            .is_some());
+        let stats = limiter.stats();
+        assert_eq!(stats.polls.map(|polls| polls.skipped), Some(1));
+        assert_eq!(stats.groups["commands"].running, 1);
+    }
+
+    #[test]
+    fn test_give_up_queueing() {
+        let limiter = Limiter::new(ConcurrencyLimits::default().polls(1).group("commands", 1));
+        let running = limiter.acquire_poll(None, WhenBusy::Queue, || true);
+        assert!(running.is_some());
+
+        // The group slot is taken, the polls one never frees up
+        let mut checks = 0;
+        let queued = limiter.acquire_poll(Some("commands"), WhenBusy::Queue, || {
+            checks += 1;
+            checks < 3
+        });
+        assert!(queued.is_none());
+        assert_eq!(checks, 3);
+
+        let stats = limiter.stats();
+        assert_eq!(stats.groups["commands"].running, 0);
+        let polls = stats.polls.unwrap();
+        assert_eq!((polls.running, polls.queued, polls.acquired), (1, 0, 1));
+        drop(running);
+        assert!(limiter
+            .acquire_poll(Some("commands"), WhenBusy::Queue, || false)
+            .is_some());
+    }
+}
@@ -0,0 +1,690 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use internal_prelude::library_prelude::*;
+
+use crate::{
+    firing::Hysteresis,
+    threshold::{Measure, ThresholdRule},
+    DeferredValues, Event, Observation, PollingFunc, Values,
+};
+
+/// A single numeric measurement of a metric.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Sample {
+    pub metric: String,
+    pub value:  f64,
+    /// Distinguishes samples of the same metric, like the mount point for disk usage.
+    pub labels: BTreeMap<String, String>,
+}
+
+impl Sample {
+    pub fn new(metric: &str, value: f64) -> Self {
+        Sample {
+            metric: metric.to_string(),
+            value,
+            labels: BTreeMap::new(),
+        }
+    }
+
+    pub fn label(mut self, name: &str, value: &str) -> Self {
+        self.labels.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Identifies the series the sample belongs to, like `disk_used_percent{mount=/home}`.
+    pub fn series(&self) -> String {
+        if self.labels.is_empty() {
+            return self.metric.clone();
+        }
+
+        let labels = self
+            .labels
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .join(",");
+        format!("{}{{{}}}", self.metric, labels)
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Debug)]
+pub enum Severity {
+    Ok,
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Ok => write!(f, "ok"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/// The event produced by a MetricMonitor for each sample of a metric with ThresholdRules.
+/// The sample itself, the rule and the measured value are attached as payload values.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct ThresholdEvent {
+    pub metric:   String,
+    pub severity: Severity,
+}
+
+impl ThresholdEvent {
+    pub fn new(metric: &str, severity: Severity) -> Self {
+        ThresholdEvent {
+            metric: metric.to_string(),
+            severity,
+        }
+    }
+}
+
+impl Event for ThresholdEvent {}
+
+struct MetricRule {
+    metric:   String,
+    /// Only the samples with these labels, every sample of the metric if empty.
+    labels:   BTreeMap<String, String>,
+    severity: Severity,
+    rule:     ThresholdRule,
+}
+
+impl MetricRule {
+    fn applies_to(&self, sample: &Sample) -> bool {
+        self.metric == sample.metric
+            && self
+                .labels
+                .iter()
+                .all(|(name, value)| sample.labels.get(name) == Some(value))
+    }
+}
+
+struct Attachment {
+    metrics: Vec<String>,
+    values:  Arc<dyn Fn() -> Values + Send + Sync>,
+}
+
+#[derive(Default)]
+struct SeriesState {
+    last:           Option<(Instant, f64)>,
+    // Samples within the baseline window, oldest first
+    history:        VecDeque<(Instant, f64)>,
+    // Index of a rule to when its condition started being met
+    breached_since: HashMap<usize, Instant>,
+    // Index of a rule with a clear condition to whether the series is past it
+    hysteresis:     HashMap<usize, Hysteresis>,
+}
+
+/// Turns numeric samples into ThresholdEvents according to the ThresholdRules of their metrics.
+///
+/// Every sample of a metric with rules results in an event, with Severity::Ok when no rule is breached,
+/// so use FiringMode::Edge or FiringMode::Debounced to only act on changes.
+pub struct MetricMonitor {
+    poll:            Box<dyn Fn() -> Result<Vec<Sample>> + Send>,
+    rules:           Vec<MetricRule>,
+    baseline_window: Duration,
+    series:          HashMap<String, SeriesState>,
+    attachments:     Vec<Attachment>,
+}
+
+impl MetricMonitor {
+    pub fn new(poll: impl Fn() -> Result<Vec<Sample>> + Send + 'static) -> Self {
+        MetricMonitor {
+            poll:            Box::new(poll),
+            rules:           Vec::new(),
+            baseline_window: Duration::from_secs(60 * 60),
+            series:          HashMap::new(),
+            attachments:     Vec::new(),
+        }
+    }
+
+    /// Add a rule like `> 90 for 5m` for a metric, see ThresholdRule for the syntax.
+    pub fn rule(&mut self, metric: &str, severity: Severity, rule: &str) -> Result<&mut Self> {
+        self.rules.push(MetricRule {
+            metric: metric.to_string(),
+            labels: BTreeMap::new(),
+            severity,
+            rule: rule.parse()?,
+        });
+        Ok(self)
+    }
+
+    /// Add a rule for only the samples of a metric with a label, like `process_count` of `process=nginx`.
+    pub fn labelled_rule(
+        &mut self,
+        metric: &str,
+        label: &str,
+        value: &str,
+        severity: Severity,
+        rule: &str,
+    ) -> Result<&mut Self> {
+        self.rules.push(MetricRule {
+            metric: metric.to_string(),
+            labels: vec![(label.to_string(), value.to_string())]
+                .into_iter()
+                .collect(),
+            severity,
+            rule: rule.parse()?,
+        });
+        Ok(self)
+    }
+
+    /// The baseline of `% of baseline` rules is the average of the samples within this window.
+    pub fn baseline_window(&mut self, baseline_window: Duration) -> &mut Self {
+        self.baseline_window = baseline_window;
+        self
+    }
+
+    /// Attach the values of the function to the events of the metrics while one of their rules is breached,
+    /// like a snapshot of the top processes to CPU and memory events. It's deferred until one of those events
+    /// fires and runs actions, so FiringMode::Edge and silences spare it, and called at most once per poll.
+    pub fn attach(
+        &mut self,
+        metrics: &[&str],
+        values: impl Fn() -> Values + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.attachments.push(Attachment {
+            metrics: metrics.iter().map(|metric| metric.to_string()).collect(),
+            values:  Arc::new(values),
+        });
+        self
+    }
+
+    pub fn into_polling_func(self) -> PollingFunc<ThresholdEvent> {
+        self.into_polling_func_with(|event| event)
+    }
+
+    /// For PollingMonitors with their own event type.
+    pub fn into_polling_func_with<E: Event>(
+        self,
+        into_event: impl Fn(ThresholdEvent) -> E + Send + 'static,
+    ) -> PollingFunc<E> {
+        let monitor = Mutex::new(self);
+        PollingFunc::with_clock(move |now| {
+            let mut monitor = monitor.lock();
+            let samples = (monitor.poll)()?;
+            let observations = monitor.evaluate(samples, now);
+
+            Ok(observations
+                .into_iter()
+                .map(|observation| Observation {
+                    event:    into_event(observation.event),
+                    series:   observation.series,
+                    values:   observation.values,
+                    deferred: observation.deferred,
+                })
+                .collect())
+        })
+    }
+
+    pub(crate) fn evaluate(
+        &mut self,
+        samples: Vec<Sample>,
+        now: Instant,
+    ) -> Vec<Observation<ThresholdEvent>> {
+        let mut observations = Vec::new();
+        let mut attached: Vec<Option<DeferredValues>> =
+            self.attachments.iter().map(|_| None).collect();
+
+        for sample in samples {
+            if !self.rules.iter().any(|rule| rule.applies_to(&sample)) {
+                continue;
+            }
+
+            let series = sample.series();
+            let state = self.series.entry(series.clone()).or_default();
+
+            while let Some((at, _)) = state.history.front() {
+                if now.duration_since(*at) <= self.baseline_window {
+                    break;
+                }
+                state.history.pop_front();
+            }
+
+            let baseline = if state.history.is_empty() {
+                None
+            } else {
+                Some(
+                    state.history.iter().map(|(_, value)| value).sum::<f64>()
+                        / state.history.len() as f64,
+                )
+            };
+            let rate = state.last.and_then(|(at, value)| {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                if elapsed > 0.0 {
+                    Some((sample.value - value) / elapsed)
+                } else {
+                    None
+                }
+            });
+
+            let mut breached: Option<&MetricRule> = None;
+            for (index, metric_rule) in self.rules.iter().enumerate() {
+                if !metric_rule.applies_to(&sample) {
+                    continue;
+                }
+
+                let measured = match metric_rule.rule.measure() {
+                    Measure::Value => Some(sample.value),
+                    Measure::PercentOfBaseline => baseline
+                        .filter(|baseline| *baseline != 0.0)
+                        .map(|baseline| sample.value / baseline * 100.0),
+                    Measure::RatePerSecond => rate,
+                };
+
+                let is_met = |measured: f64| match metric_rule.rule.hysteresis() {
+                    Some(hysteresis) => state
+                        .hysteresis
+                        .entry(index)
+                        .or_insert(hysteresis)
+                        .check(measured),
+                    None => metric_rule.rule.is_met(measured),
+                };
+                if !measured.is_some_and(is_met) {
+                    state.breached_since.remove(&index);
+                    continue;
+                }
+
+                let since = *state.breached_since.entry(index).or_insert(now);
+                let is_breached = now.duration_since(since) >= metric_rule.rule.hold();
+                if is_breached && breached.is_none_or(|b| metric_rule.severity > b.severity) {
+                    breached = Some(metric_rule);
+                }
+            }
+
+            state.last = Some((now, sample.value));
+            state.history.push_back((now, sample.value));
+
+            let severity = breached.map_or(Severity::Ok, |b| b.severity);
+            let mut observation = Observation::new(ThresholdEvent::new(&sample.metric, severity))
+                .series(&series)
+                .value("metric", sample.metric.as_str())
+                .value("value", sample.value)
+                .value("severity", severity.to_string());
+            for (name, value) in &sample.labels {
+                observation = observation.value(name, value.as_str());
+            }
+            if let Some(baseline) = baseline {
+                observation = observation.value("baseline", baseline);
+            }
+            if let Some(rate) = rate {
+                observation = observation.value("rate", rate);
+            }
+            if let Some(breached) = breached {
+                observation = observation.value("rule", breached.rule.to_string());
+
+                for (attachment, deferred) in self.attachments.iter().zip(attached.iter_mut()) {
+                    if !attachment.metrics.contains(&sample.metric) {
+                        continue;
+                    }
+                    let deferred = deferred.get_or_insert_with(|| {
+                        let values = Arc::clone(&attachment.values);
+                        DeferredValues::new(move || values())
+                    });
+                    observation = observation.defer(deferred.clone());
+                }
+            }
+
+            observations.push(observation);
+        }
+
+        observations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn severities(observations: &[Observation<ThresholdEvent>]) -> Vec<Severity> {
+        observations
+            .iter()
+            .map(|observation| observation.event.severity)
+            .collect()
+    }
+
+    #[test]
+    fn test_series() {
+        assert_eq!(Sample::new("load1", 1.0).series(), "load1");
+        assert_eq!(
+            Sample::new("disk_used_percent", 1.0)
+                .label("mount", "/home")
+                .label("device", "/dev/sda2")
+                .series(),
+            "disk_used_percent{device=/dev/sda2,mount=/home}"
+        );
+    }
+
+    #[test]
+    fn test_value_with_hold() {
+        let mut monitor = MetricMonitor::new(|| Ok(vec![]));
+        monitor
+            .rule("load1", Severity::Warning, "> 4")
+            .unwrap()
+            .rule("load1", Severity::Critical, "> 8 for 5m")
+            .unwrap();
+
+        let start = Instant::now();
+        let at = |minutes: u64| start + Duration::from_secs(minutes * 60);
+        let mut evaluated = vec![];
+        for (minute, value) in &[
+            (0, 1.0),
+            (1, 10.0),
+            (3, 10.0),
+            (6, 10.0),
+            (7, 5.0),
+            (8, 2.0),
+        ] {
+            evaluated.extend(monitor.evaluate(vec![Sample::new("load1", *value)], at(*minute)));
+        }
+
+        assert_eq!(
+            severities(&evaluated),
+            vec![
+                Severity::Ok,
+                Severity::Warning,
+                Severity::Warning,
+                Severity::Critical,
+                Severity::Warning,
+                Severity::Ok
+            ]
+        );
+        assert_eq!(evaluated[3].values["rule"], Value::from("> 8 for 5m"));
+        assert_eq!(evaluated[3].values["value"], Value::from(10.0));
+        assert!(!evaluated[5].values.contains_key("rule"));
+    }
+
+    #[test]
+    fn test_hold_on_the_monitor_clock() {
+        use crate::{
+            test_support::{ActionRecorder, MonitorHarness},
+            EventKey, PollingMonitor, PollingSchedule, VirtualClock,
+        };
+
+        let mut metric_monitor = MetricMonitor::new(|| Ok(vec![Sample::new("load1", 10.0)]));
+        metric_monitor
+            .rule("load1", Severity::Warning, "> 8 for 5m")
+            .unwrap();
+
+        let clock = VirtualClock::new();
+        let recorder = ActionRecorder::new(&clock);
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .schedule_named_polling(
+                "load",
+                *PollingSchedule::default().interval(Duration::from_secs(60)),
+                metric_monitor.into_polling_func(),
+            )
+            .register_action(
+                EventKey::Polled(ThresholdEvent::new("load1", Severity::Warning)),
+                recorder.action("log"),
+            );
+        let mut harness = MonitorHarness::new(monitor, &clock);
+        harness.advance(Duration::from_secs(7 * 60));
+
+        // The hold is over once five virtual minutes have passed
+        let warning = EventKey::Polled(ThresholdEvent::new("load1", Severity::Warning));
+        assert_eq!(
+            recorder.timeline(),
+            vec![
+                (300, "log".to_string(), warning.clone()),
+                (360, "log".to_string(), warning.clone()),
+                (420, "log".to_string(), warning),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clear_threshold() {
+        let mut monitor = MetricMonitor::new(|| Ok(vec![]));
+        monitor
+            .rule("cpu_busy_percent", Severity::Warning, "> 90 clear < 85")
+            .unwrap();
+
+        let start = Instant::now();
+        let mut evaluated = vec![];
+        for (secs, value) in [
+            (0, 88.0),
+            (10, 95.0),
+            (20, 88.0),
+            (30, 91.0),
+            (40, 80.0),
+            (50, 88.0),
+        ] {
+            evaluated.extend(monitor.evaluate(
+                vec![Sample::new("cpu_busy_percent", value).label("cpu", "cpu0")],
+                start + Duration::from_secs(secs),
+            ));
+            // Another series has a state of its own
+            evaluated.extend(monitor.evaluate(
+                vec![Sample::new("cpu_busy_percent", 88.0).label("cpu", "cpu1")],
+                start + Duration::from_secs(secs),
+            ));
+        }
+
+        // Between 85 and 90 the series stays as it was, neither clearing nor raising again
+        let cpu0: Vec<Severity> = severities(&evaluated).into_iter().step_by(2).collect();
+        assert_eq!(
+            cpu0,
+            vec![
+                Severity::Ok,
+                Severity::Warning,
+                Severity::Warning,
+                Severity::Warning,
+                Severity::Ok,
+                Severity::Ok
+            ]
+        );
+        assert!(severities(&evaluated)
+            .into_iter()
+            .skip(1)
+            .step_by(2)
+            .all(|severity| severity == Severity::Ok));
+        assert_eq!(evaluated[4].values["rule"], Value::from("> 90 clear < 85"));
+    }
+
+    #[test]
+    fn test_baseline_and_rate() {
+        let mut monitor = MetricMonitor::new(|| Ok(vec![]));
+        monitor
+            .baseline_window(Duration::from_secs(100))
+            .rule("requests", Severity::Warning, "< 10% of baseline")
+            .unwrap()
+            .rule("errors", Severity::Critical, "rate > 1/s")
+            .unwrap();
+
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let poll = |monitor: &mut MetricMonitor, secs, requests, errors| {
+            monitor.evaluate(
+                vec![
+                    Sample::new("requests", requests),
+                    Sample::new("errors", errors).label("interface", "eth0"),
+                    Sample::new("unrelated", 1.0),
+                ],
+                at(secs),
+            )
+        };
+
+        let first = poll(&mut monitor, 0, 100.0, 0.0);
+        assert_eq!(severities(&first), vec![Severity::Ok, Severity::Ok]);
+        assert_eq!(first[1].series, "errors{interface=eth0}");
+        assert_eq!(first[1].values["interface"], Value::from("eth0"));
+
+        let second = poll(&mut monitor, 10, 5.0, 5.0);
+        assert_eq!(severities(&second), vec![Severity::Warning, Severity::Ok]);
+        assert_eq!(second[0].values["baseline"], Value::from(100.0));
+
+        let third = poll(&mut monitor, 20, 100.0, 30.0);
+        assert_eq!(severities(&third), vec![Severity::Ok, Severity::Critical]);
+        assert_eq!(third[1].values["rate"], Value::from(2.5));
+
+        // The samples from the first polls have left the baseline window
+        let fourth = poll(&mut monitor, 200, 2.0, 30.0);
+        assert_eq!(severities(&fourth), vec![Severity::Ok, Severity::Ok]);
+    }
+
+    #[test]
+    fn test_labelled_rule() {
+        let mut monitor = MetricMonitor::new(|| Ok(vec![]));
+        monitor
+            .rule("process_count", Severity::Critical, "< 1")
+            .unwrap()
+            .labelled_rule(
+                "process_count",
+                "process",
+                "nginx",
+                Severity::Warning,
+                "> 4",
+            )
+            .unwrap()
+            .labelled_rule(
+                "process_restarts",
+                "process",
+                "redis",
+                Severity::Warning,
+                "> 0",
+            )
+            .unwrap();
+
+        let observations = monitor.evaluate(
+            vec![
+                Sample::new("process_count", 5.0).label("process", "nginx"),
+                Sample::new("process_count", 5.0).label("process", "workers"),
+                Sample::new("process_count", 0.0).label("process", "redis"),
+                Sample::new("process_restarts", 3.0).label("process", "nginx"),
+            ],
+            Instant::now(),
+        );
+        // Samples without a rule for their labels have no events
+        assert_eq!(
+            severities(&observations),
+            vec![Severity::Warning, Severity::Ok, Severity::Critical]
+        );
+    }
+
+    #[test]
+    fn test_attach() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_clone = Arc::clone(&calls);
+        let mut monitor = MetricMonitor::new(|| Ok(vec![]));
+        monitor
+            .rule("cpu_busy_percent", Severity::Warning, "> 90")
+            .unwrap()
+            .rule("load1", Severity::Warning, "> 4")
+            .unwrap()
+            .attach(&["cpu_busy_percent", "memory_used_percent"], move || {
+                calls_clone.fetch_add(1, Ordering::SeqCst);
+                let mut values = Values::new();
+                values.insert("top_processes".to_string(), "2001 python3 85%".into());
+                values
+            });
+
+        let now = Instant::now();
+        let poll = |monitor: &mut MetricMonitor, cpu: f64, load: f64| {
+            monitor.evaluate(
+                vec![
+                    Sample::new("cpu_busy_percent", cpu).label("cpu", "cpu0"),
+                    Sample::new("cpu_busy_percent", cpu).label("cpu", "cpu1"),
+                    Sample::new("load1", load),
+                ],
+                now,
+            )
+        };
+
+        let calm = poll(&mut monitor, 20.0, 8.0);
+        assert!(calm
+            .iter()
+            .all(|observation| observation.deferred.is_empty()));
+
+        // Only the breached metrics it is attached to get the values, computed once for both series when needed
+        let busy = poll(&mut monitor, 95.0, 8.0);
+        assert_eq!(busy[0].deferred, busy[1].deferred);
+        assert!(busy[2].deferred.is_empty());
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        assert_eq!(
+            busy[1].deferred[0].get()["top_processes"],
+            Value::from("2001 python3 85%")
+        );
+        assert_eq!(busy[0].deferred[0].get(), busy[1].deferred[0].get());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_attach_to_fired_events_only() {
+        use crate::{
+            test_support::{ActionRecorder, MonitorHarness},
+            EventKey, FiringMode, PollingMonitor, PollingSchedule, VirtualClock,
+        };
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        let cpu = Arc::new(Mutex::new(VecDeque::from(vec![20.0, 95.0, 95.0, 95.0])));
+        let cpu_clone = Arc::clone(&cpu);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_clone = Arc::clone(&calls);
+        let mut metric_monitor = MetricMonitor::new(move || {
+            let cpu = cpu_clone.lock().pop_front().unwrap_or(20.0);
+            Ok(vec![Sample::new("cpu_busy_percent", cpu)])
+        });
+        metric_monitor
+            .rule("cpu_busy_percent", Severity::Warning, "> 90")
+            .unwrap()
+            .attach(&["cpu_busy_percent"], move || {
+                let calls = calls_clone.fetch_add(1, Ordering::SeqCst) + 1;
+                let mut values = Values::new();
+                values.insert("snapshot".to_string(), calls.into());
+                values
+            });
+
+        let clock = VirtualClock::new();
+        let recorder = ActionRecorder::new(&clock);
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .schedule_named_polling(
+                "cpu",
+                *PollingSchedule::default().interval(Duration::from_secs(10)),
+                metric_monitor.into_polling_func(),
+            )
+            .firing_mode("cpu", FiringMode::Edge)
+            .unwrap()
+            .register_action(
+                EventKey::Polled(ThresholdEvent::new("cpu_busy_percent", Severity::Warning)),
+                recorder.action("log"),
+            );
+        let mut harness = MonitorHarness::new(monitor, &clock);
+        harness.advance(Duration::from_secs(45));
+
+        // The breach fires once, the polls that stay breached don't take snapshots
+        let invocations = recorder.invocations();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(
+            invocations[0].event.value("snapshot"),
+            Some(&Value::from(1))
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(cpu.lock().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_rule() {
+        let mut monitor = MetricMonitor::new(|| Ok(vec![]));
+        assert!(monitor
+            .rule("load1", Severity::Warning, "over 9000")
+            .is_err());
+    }
+}
@@ -0,0 +1,751 @@
+use std::{
+    collections::VecDeque,
+    mem,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::Arc,
+    thread::{spawn, JoinHandle},
+    time::{Instant, SystemTime},
+};
+
+use crossbeam::{
+    channel::{unbounded, Receiver, RecvTimeoutError, Sender},
+    select,
+};
+use event_bus::{BusEvent, EventBus};
+use internal_prelude::{application_prelude::anyhow, library_prelude::*};
+
+use crate::{
+    action::panic_message,
+    composite::Composites,
+    dependency::{Dependencies, WhenParentFailing},
+    dispatch::Dispatcher,
+    escalation::Escalations,
+    event::PollTracker,
+    firing::FiringTracker,
+    handle::{history_key, MonitorState, MonitorStatus, PollingMonitorHandleInner},
+    history::{HistoryEntry, HistoryRecord, HISTORY_SAVE_INTERVAL},
+    incident::Incidents,
+    limit::{Limiter, WhenBusy},
+    silence::Silences,
+    watchdog::{Watchdog, WatchdogState},
+    ActionFunc, ActionReport, Clock, Event, FiredEvent, MonitorEvent, MonitorId, MonitorOptions,
+    PollingFunc, PollingMonitor, PollingSchedule, StateStore,
+};
+
+/// Instructions for a running polling process, sent by the PollingMonitorHandle.
+pub(crate) enum Command {
+    Pause,
+    Resume,
+    Reschedule(PollingSchedule),
+    CheckNow,
+    Stop,
+}
+
+/// Shared between all polling processes of a PollingMonitor and its PollingMonitorHandle.
+pub(crate) struct PollingContext<E: Event> {
+    pub(crate) host:         String,
+    // Actions must not block each other so there is no lock around it
+    pub(crate) dispatcher:   Dispatcher<E>,
+    pub(crate) inner:        Arc<Mutex<PollingMonitorHandleInner>>,
+    pub(crate) state_store:  Option<StateStore>,
+    pub(crate) clock:        Arc<dyn Clock>,
+    // Always locked before the dependencies
+    pub(crate) composites:   Mutex<Composites<E>>,
+    pub(crate) dependencies: Mutex<Dependencies<E>>,
+    // Always locked before the incidents
+    pub(crate) escalations:  Mutex<Escalations<E>>,
+    // Never locked together with the composites or dependencies
+    pub(crate) incidents:    Mutex<Incidents<E>>,
+    pub(crate) silences:     Mutex<Silences>,
+    pub(crate) event_bus:    Option<EventBus>,
+    pub(crate) limiter:      Limiter,
+}
+
+impl<E: Event> PollingContext<E> {
+    /// Take everything but the polling functions and the Watchdog out of the PollingMonitor.
+    /// Used both by PollingMonitor::start and the MonitorHarness, so tests run the same setup.
+    pub(crate) fn new(monitor: &mut PollingMonitor<E>, clock: Arc<dyn Clock>) -> Self {
+        let state_store = monitor.state_store.take();
+        PollingContext {
+            host: mem::take(&mut monitor.host),
+            dispatcher: mem::take(&mut monitor.dispatcher),
+            inner: Arc::new(Mutex::new(PollingMonitorHandleInner::new(
+                monitor.history_size,
+            ))),
+            escalations: Mutex::new(mem::take(&mut monitor.escalations)),
+            incidents: Mutex::new(Incidents::new(state_store.clone())),
+            silences: Mutex::new(Silences::new(
+                mem::take(&mut monitor.windows),
+                state_store.clone(),
+            )),
+            state_store,
+            clock,
+            composites: Mutex::new(mem::take(&mut monitor.composites)),
+            dependencies: Mutex::new(Dependencies::default()),
+            event_bus: monitor.event_bus.take(),
+            limiter: Limiter::new(mem::take(&mut monitor.limits)),
+        }
+    }
+}
+
+/// Polls a single polling function and dispatches the events it fires, without any scheduling.
+pub(crate) struct Poller<E: Event> {
+    id:             MonitorId,
+    polling_func:   PollingFunc<E>,
+    poll_tracker:   PollTracker,
+    firing_tracker: FiringTracker<E>,
+    group:          Option<String>,
+    when_busy:      WhenBusy,
+    saved_history:  SavedHistory,
+    context:        Arc<PollingContext<E>>,
+}
+
+/// What of the history of a polling function is in the StateStore.
+struct SavedHistory {
+    /// How many entries were recorded in the History when it was last saved.
+    recorded: u64,
+    /// The lines of the log in the StateStore, to rewrite it once most of them are no longer kept.
+    lines:    usize,
+    at:       Instant,
+}
+
+impl<E: Event> Poller<E> {
+    /// Register the status, dependencies and incident rule of the polling function and restore its history from the StateStore.
+    /// Fails if the dependencies would form a cycle.
+    pub(crate) fn new(
+        id: MonitorId,
+        schedule: PollingSchedule,
+        polling_func: PollingFunc<E>,
+        options: MonitorOptions<E>,
+        context: Arc<PollingContext<E>>,
+    ) -> Result<Self> {
+        context
+            .dependencies
+            .lock()
+            .add(&id, options.dependencies, options.when_parent_failing)?;
+        context
+            .incidents
+            .lock()
+            .set_rule(&id, options.incident_rule);
+
+        let restored = context
+            .state_store
+            .as_ref()
+            .map(|state_store| state_store.load_log(&history_key(&id)));
+        let mut saved_history = SavedHistory {
+            recorded: 0,
+            lines:    0,
+            at:       context.clock.now(),
+        };
+
+        let mut inner = context.inner.lock();
+        inner
+            .monitors
+            .insert(id.clone(), MonitorStatus::new(id.clone(), schedule));
+        if let Some(status) = inner.monitors.get_mut(&id) {
+            status.last_tick = Some(context.clock.system_now());
+        }
+        match restored {
+            Some(Ok(Some(entries))) => {
+                saved_history.lines = entries.len();
+                inner.history.restore(&id, entries);
+            }
+            Some(Err(err)) => log::warn!("Failed to restore the history of {}: {:#}", id, err),
+            _ => {}
+        }
+        saved_history.recorded = inner.history.recorded(&id);
+        drop(inner);
+
+        Ok(Poller {
+            id,
+            polling_func,
+            poll_tracker: PollTracker::default(),
+            firing_tracker: FiringTracker::new(options.firing_mode),
+            group: options.group,
+            when_busy: options.when_busy,
+            saved_history,
+            context,
+        })
+    }
+
+    /// Poll once, run the actions of the fired events and record everything in the handle.
+    /// Returns the fired events, except those suppressed because of a failing parent or a silence
+    /// and repeats of acknowledged incidents.
+    ///
+    /// Waits for free slots of the concurrency limits first, or skips the poll if its WhenBusy is Skip.
+    /// While waiting, keep_waiting is asked every QUEUE_CHECK_INTERVAL whether to go on, the poll is dropped once it answers false.
+    pub(crate) fn poll(&mut self, keep_waiting: impl FnMut() -> bool) -> Vec<FiredEvent<E>> {
+        let queued = Instant::now();
+        let permits =
+            self.context
+                .limiter
+                .acquire_poll(self.group.as_deref(), self.when_busy, keep_waiting);
+        let queue_wait = queued.elapsed();
+        if permits.is_none() {
+            match self.when_busy {
+                WhenBusy::Skip => {
+                    log::info!("Skipped polling {}, its limits are busy", self.id);
+                    self.skip();
+                }
+                WhenBusy::Queue => log::info!("Stopped waiting to poll {}", self.id),
+            }
+            return vec![];
+        }
+
+        let clock = &self.context.clock;
+        let polled_at = clock.system_now();
+        let started = Instant::now();
+        // A panicking polling function must not take the polling loop down with it
+        let poll_result = catch_unwind(AssertUnwindSafe(|| (self.polling_func.0)(clock.now())))
+            .unwrap_or_else(|panic| {
+                Err(anyhow!(
+                    "polling function panicked: {}",
+                    panic_message(panic)
+                ))
+            });
+        let poll_duration = started.elapsed();
+        // The actions take slots of their own
+        drop(permits);
+        let observed = self.poll_tracker.observe(poll_result);
+
+        let error_chain = observed.iter().find_map(|observed| match &observed.event {
+            MonitorEvent::PollFailed(failure) => Some(failure.error_chain.clone()),
+            _ => None,
+        });
+        let poll_failed = error_chain.is_some();
+        let mut history = vec![HistoryRecord::Poll {
+            duration: poll_duration,
+            observations: if error_chain.is_some() {
+                0
+            } else {
+                observed
+                    .iter()
+                    .filter(|observed| matches!(observed.event, MonitorEvent::Polled(_)))
+                    .count()
+            },
+            error_chain,
+        }
+        .at(&self.id, polled_at)];
+
+        let mut composites = self.context.composites.lock();
+        for observed in &observed {
+            composites.observe(
+                &self.id,
+                &observed.series,
+                observed.event.key(),
+                clock.now(),
+            );
+        }
+        let failing_parent = self
+            .context
+            .dependencies
+            .lock()
+            .failing_parent(&self.id, composites.observations());
+
+        let mut to_fire = Vec::new();
+        for observed in observed {
+            if let MonitorEvent::Polled(polled) = &observed.event {
+                if !self
+                    .firing_tracker
+                    .should_fire(&observed.series, polled, clock.now())
+                {
+                    continue;
+                }
+            }
+
+            let mut event = FiredEvent::new(
+                observed.event,
+                &self.id,
+                &self.context.host,
+                observed.values,
+            );
+            let mut suppressed = false;
+            if let Some((parent, when_parent_failing)) = &failing_parent {
+                let name = match when_parent_failing {
+                    WhenParentFailing::Suppress => "suppressed_by",
+                    WhenParentFailing::Annotate => "parent_failing",
+                };
+                event
+                    .payload
+                    .values
+                    .insert(name.to_string(), parent.as_str().into());
+                suppressed = *when_parent_failing == WhenParentFailing::Suppress;
+            }
+            to_fire.push((event, observed.series, observed.deferred, suppressed));
+        }
+        for (id, composite, values) in composites.evaluate(clock.now()) {
+            let event = FiredEvent::new(
+                MonitorEvent::Polled(composite),
+                &id,
+                &self.context.host,
+                values,
+            );
+            to_fire.push((event, String::new(), Vec::new(), false));
+        }
+        drop(composites);
+
+        let mut fired = Vec::new();
+        let mut reports = Vec::new();
+        for (mut event, series, deferred, suppressed) in to_fire {
+            let id = event.payload.monitor_id.clone();
+            event.payload.timestamp = clock.system_now();
+            let silenced_by = self.context.silences.lock().silenced_by(&event);
+            let suppressed = match silenced_by {
+                Some(silenced_by) => {
+                    log::info!("{:?} of {} silenced by {}", event.key(), id, silenced_by);
+                    event
+                        .payload
+                        .values
+                        .insert("silenced_by".to_string(), silenced_by.into());
+                    true
+                }
+                None => suppressed,
+            };
+            let dispatch = self
+                .context
+                .incidents
+                .lock()
+                .update(&mut event, &series, suppressed);
+            if !suppressed && dispatch {
+                for deferred in &deferred {
+                    event.payload.values.extend(deferred.get());
+                }
+            }
+            let mut escalations = self.context.escalations.lock();
+            escalations.observe(&event, &series);
+            if !suppressed && dispatch {
+                escalations.start(&event, &series, clock.now());
+            }
+            drop(escalations);
+            history.push(
+                HistoryRecord::Event {
+                    event: format!("{:?}", event.key()),
+                    series,
+                    values: event.payload.values.clone(),
+                }
+                .at(&id, event.payload.timestamp),
+            );
+            if suppressed || !dispatch {
+                continue;
+            }
+
+            let dispatched =
+                self.context
+                    .dispatcher
+                    .dispatch(&event, clock.now(), &self.context.limiter);
+            for report in dispatched {
+                history.push(HistoryRecord::Action(report.clone()).at(&id, clock.system_now()));
+                reports.push(report);
+            }
+            if let Some(event_bus) = &self.context.event_bus {
+                event_bus.publish(&event);
+            }
+            fired.push(event);
+        }
+        let (escalation_reports, escalation_history) = run_escalations(&self.context);
+        reports.extend(escalation_reports);
+        history.extend(escalation_history);
+
+        let mut inner = self.context.inner.lock();
+        inner.record(reports, history);
+        if let Some(status) = inner.monitors.get_mut(&self.id) {
+            status.polls += 1;
+            status.consecutive_failures = self.poll_tracker.consecutive_failures();
+            status.last_poll = Some(polled_at);
+            status.last_poll_duration = Some(poll_duration);
+            status.stats.record(polled_at, poll_duration, !poll_failed);
+            status.last_tick = Some(clock.system_now());
+            status.last_queue_wait = Some(queue_wait);
+            status.total_queue_wait += queue_wait;
+        }
+
+        drop(inner);
+        if clock.now().saturating_duration_since(self.saved_history.at) >= HISTORY_SAVE_INTERVAL {
+            self.save_history();
+        }
+
+        fired
+    }
+
+    /// Append the entries recorded since the last save to the StateStore, if any.
+    /// The log is rewritten with only the kept entries once it has grown to twice their number.
+    fn save_history(&mut self) {
+        let state_store = match &self.context.state_store {
+            Some(state_store) => state_store,
+            None => return,
+        };
+        let key = history_key(&self.id);
+        let inner = self.context.inner.lock();
+        let recorded = inner.history.recorded(&self.id);
+        let new = recorded.saturating_sub(self.saved_history.recorded) as usize;
+        self.saved_history.at = self.context.clock.now();
+        if new == 0 {
+            return;
+        }
+
+        let (result, lines) = if self.saved_history.lines + new > 2 * inner.history.size() {
+            let entries = inner.history.entries(&self.id);
+            drop(inner);
+            (state_store.replace_log(&key, &entries), entries.len())
+        } else {
+            let entries = inner
+                .history
+                .recorded_since(&self.id, self.saved_history.recorded);
+            drop(inner);
+            (
+                state_store.append(&key, &entries),
+                self.saved_history.lines + entries.len(),
+            )
+        };
+        match result {
+            Ok(()) => {
+                self.saved_history.recorded = recorded;
+                self.saved_history.lines = lines;
+            }
+            Err(err) => log::warn!("Failed to persist the history of {}: {:#}", self.id, err),
+        }
+    }
+
+    /// Count a poll that was not run because its WhenBusy is Skip.
+    pub(crate) fn skip(&self) {
+        if let Some(status) = self.context.inner.lock().monitors.get_mut(&self.id) {
+            status.skipped_ticks += 1;
+            status.last_tick = Some(self.context.clock.system_now());
+        }
+    }
+
+    /// Record that the polling loop paused or resumed, a resumed loop starts ticking again from now.
+    fn set_state(&self, state: MonitorState) {
+        if let Some(status) = self.context.inner.lock().monitors.get_mut(&self.id) {
+            status.state = state;
+            status.last_tick = Some(self.context.clock.system_now());
+        }
+    }
+
+    pub(crate) fn when_busy(&self) -> WhenBusy {
+        self.when_busy
+    }
+}
+
+impl<E: Event> Drop for Poller<E> {
+    fn drop(&mut self) {
+        self.save_history();
+    }
+}
+
+/// Runs a single polling function on its schedule in its own thread.
+pub(crate) struct PollingProcess<E: Event> {
+    poller:   Poller<E>,
+    schedule: PollingSchedule,
+    commands: Receiver<Command>,
+    // Commands received while skipping checks, handled before any new ones
+    pending:  VecDeque<Command>,
+    paused:   bool,
+}
+
+impl<E: Event> PollingProcess<E> {
+    /// Start polling in a new thread.
+    pub(crate) fn spawn(
+        poller: Poller<E>,
+        schedule: PollingSchedule,
+    ) -> (Sender<Command>, JoinHandle<()>) {
+        let (sender, receiver) = unbounded();
+        let process = PollingProcess {
+            poller,
+            schedule,
+            commands: receiver,
+            pending: VecDeque::new(),
+            paused: false,
+        };
+
+        (sender, spawn(move || process.run()))
+    }
+
+    fn run(mut self) {
+        // This interval starts ticking down after finishing the current poll
+        let mut last_poll: Option<Instant> = None;
+        let mut next_poll = Instant::now();
+
+        loop {
+            let command = if let Some(command) = self.pending.pop_front() {
+                Ok(command)
+            } else if self.paused {
+                self.commands
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                self.commands
+                    .recv_timeout(next_poll.saturating_duration_since(Instant::now()))
+            };
+
+            match command {
+                Err(RecvTimeoutError::Timeout) | Ok(Command::CheckNow) => {
+                    let commands = &self.commands;
+                    let pending = &mut self.pending;
+                    let context = Arc::clone(&self.poller.context);
+                    let mut gave_up = false;
+                    // A poll queued behind busy limits still has to stop, pause or reschedule when asked
+                    self.poller.poll(|| {
+                        pending.extend(commands.try_iter());
+                        gave_up = !context.inner.lock().is_running
+                            || pending
+                                .iter()
+                                .any(|command| !matches!(command, Command::CheckNow));
+                        !gave_up
+                    });
+                    // A poll given up on is still due
+                    if !gave_up {
+                        last_poll = Some(Instant::now());
+                        next_poll = Instant::now() + self.schedule.interval;
+                    }
+                    if self.poller.when_busy() == WhenBusy::Skip {
+                        // Checks requested while the poll was still running are skipped
+                        for command in self.commands.try_iter() {
+                            match command {
+                                Command::CheckNow => self.poller.skip(),
+                                command => self.pending.push_back(command),
+                            }
+                        }
+                    }
+                }
+                // Only the polling process writes the state, so it can't report a state it isn't in
+                Ok(Command::Pause) => {
+                    self.paused = true;
+                    self.poller.set_state(MonitorState::Paused);
+                }
+                Ok(Command::Resume) => {
+                    self.paused = false;
+                    self.poller.set_state(MonitorState::Running);
+                }
+                Ok(Command::Reschedule(schedule)) => {
+                    self.schedule = schedule;
+                    next_poll = last_poll
+                        .map_or_else(Instant::now, |last_poll| last_poll + schedule.interval);
+                }
+                Ok(Command::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            if !self.poller.context.inner.lock().is_running {
+                return;
+            }
+        }
+    }
+}
+
+/// Runs the steps of escalations when they are due, for PollingMonitors with EscalationPolicies.
+pub(crate) struct EscalationProcess<E: Event> {
+    context:  Arc<PollingContext<E>>,
+    commands: Receiver<Command>,
+}
+
+impl<E: Event> EscalationProcess<E> {
+    /// Start running escalations in a new thread, starting an escalation wakes it up.
+    pub(crate) fn spawn(context: Arc<PollingContext<E>>) -> (Sender<Command>, JoinHandle<()>) {
+        let (sender, receiver) = unbounded();
+        context.escalations.lock().set_wakeup(sender.clone());
+        let process = EscalationProcess {
+            context,
+            commands: receiver,
+        };
+
+        (sender, spawn(move || process.run()))
+    }
+
+    fn run(self) {
+        loop {
+            let next_due = self.context.escalations.lock().next_due();
+            let command = match next_due {
+                Some(next_due) => self
+                    .commands
+                    .recv_timeout(next_due.saturating_duration_since(self.context.clock.now())),
+                None => self
+                    .commands
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match command {
+                Err(RecvTimeoutError::Timeout) | Ok(Command::CheckNow) => {
+                    let (reports, history) = run_escalations(&self.context);
+                    self.context.inner.lock().record(reports, history);
+                }
+                Ok(Command::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+                Ok(_) => {}
+            }
+
+            if !self.context.inner.lock().is_running {
+                return;
+            }
+        }
+    }
+}
+
+/// Watches the polling loops and sends heartbeats, for PollingMonitors with a Watchdog.
+pub(crate) struct WatchdogProcess<E: Event> {
+    context:  Arc<PollingContext<E>>,
+    state:    WatchdogState,
+    commands: Receiver<Command>,
+}
+
+impl<E: Event> WatchdogProcess<E> {
+    /// Start watching in a new thread.
+    pub(crate) fn spawn(
+        context: Arc<PollingContext<E>>,
+        watchdog: Watchdog,
+    ) -> (Sender<Command>, JoinHandle<()>) {
+        let (sender, receiver) = unbounded();
+        let process = WatchdogProcess {
+            context,
+            state: WatchdogState::new(watchdog),
+            commands: receiver,
+        };
+
+        (sender, spawn(move || process.run()))
+    }
+
+    fn run(mut self) {
+        loop {
+            match self.commands.recv_timeout(self.state.interval()) {
+                Err(RecvTimeoutError::Timeout) | Ok(Command::CheckNow) => {
+                    for event in self.state.check(&self.context) {
+                        fire_event(&self.context, event);
+                    }
+                }
+                Ok(Command::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+                Ok(_) => {}
+            }
+
+            if !self.context.inner.lock().is_running {
+                return;
+            }
+        }
+    }
+}
+
+/// Runs the action of a subscription of the PollingMonitor for the matching events of the EventBus,
+/// one at a time so a slow action holds up neither the publisher nor other subscriptions.
+pub(crate) struct SubscriptionProcess<E: Event> {
+    context:  Arc<PollingContext<E>>,
+    action:   ActionFunc<String>,
+    events:   Receiver<BusEvent>,
+    commands: Receiver<Command>,
+}
+
+impl<E: Event> SubscriptionProcess<E> {
+    /// Start running the action for the events in a new thread.
+    pub(crate) fn spawn(
+        context: Arc<PollingContext<E>>,
+        action: ActionFunc<String>,
+        events: Receiver<BusEvent>,
+    ) -> (Sender<Command>, JoinHandle<()>) {
+        let (sender, receiver) = unbounded();
+        let process = SubscriptionProcess {
+            context,
+            action,
+            events,
+            commands: receiver,
+        };
+
+        (sender, spawn(move || process.run()))
+    }
+
+    fn run(self) {
+        loop {
+            select! {
+                recv(self.commands) -> command => match command {
+                    Ok(Command::Stop) | Err(_) => return,
+                    Ok(_) => {}
+                },
+                recv(self.events) -> event => match event {
+                    Ok(event) => {
+                        let report = self.context.limiter.run_action(
+                            &self.action,
+                            &FiredEvent::of_bus_event(&event),
+                            self.context.clock.now(),
+                        );
+                        self.context.inner.lock().record(vec![report], vec![]);
+                    }
+                    Err(_) => return,
+                },
+            }
+
+            if !self.context.inner.lock().is_running {
+                return;
+            }
+        }
+    }
+}
+
+/// Record and dispatch an event the Watchdog fires, unless it is silenced.
+///
+/// Its actions run without waiting for slots of the concurrency limits, stalled polling loops often hold them.
+pub(crate) fn fire_event<E: Event>(context: &PollingContext<E>, mut event: FiredEvent<E>) {
+    let clock = &context.clock;
+    let id = event.payload.monitor_id.clone();
+    event.payload.timestamp = clock.system_now();
+    let silenced_by = context.silences.lock().silenced_by(&event);
+    if let Some(silenced_by) = &silenced_by {
+        log::info!("{:?} of {} silenced by {}", event.key(), id, silenced_by);
+        event
+            .payload
+            .values
+            .insert("silenced_by".to_string(), silenced_by.as_str().into());
+    }
+
+    let mut history = vec![HistoryRecord::Event {
+        event:  format!("{:?}", event.key()),
+        series: String::new(),
+        values: event.payload.values.clone(),
+    }
+    .at(&id, event.payload.timestamp)];
+    let mut reports = Vec::new();
+    if silenced_by.is_none() {
+        for report in context
+            .dispatcher
+            .dispatch(&event, clock.now(), &Limiter::default())
+        {
+            history.push(HistoryRecord::Action(report.clone()).at(&id, clock.system_now()));
+            reports.push(report);
+        }
+        if let Some(event_bus) = &context.event_bus {
+            event_bus.publish(&event);
+        }
+    }
+    context.inner.lock().record(reports, history);
+}
+
+/// Run the due steps of escalations, returns the reports of their actions and the history to record.
+pub(crate) fn run_escalations<E: Event>(
+    context: &PollingContext<E>,
+) -> (Vec<ActionReport>, Vec<HistoryEntry>) {
+    let clock = &context.clock;
+    let due = {
+        let mut escalations = context.escalations.lock();
+        let incidents = context.incidents.lock();
+        escalations.due(clock.now(), &incidents)
+    };
+
+    let mut reports = Vec::new();
+    let mut history = Vec::new();
+    for step in due {
+        let id = &step.event().payload.monitor_id;
+        for action in step.actions() {
+            let report = context
+                .limiter
+                .run_action(action, step.event(), clock.now());
+            history.push(HistoryRecord::Action(report.clone()).at(id, clock.system_now()));
+            reports.push(report);
+        }
+    }
+    (reports, history)
+}
+
+impl HistoryRecord {
+    fn at(self, id: &MonitorId, at: SystemTime) -> HistoryEntry {
+        HistoryEntry {
+            monitor_id: id.clone(),
+            at,
+            record: self,
+        }
+    }
+}
@@ -0,0 +1,426 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use internal_prelude::library_prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{Event, FiredEvent, MonitorId, StateStore};
+
+/// The key the silences are kept under in the StateStore.
+const SILENCES_KEY: &str = "silences";
+
+const DAY: u64 = 24 * 60 * 60;
+const WEEK: u64 = 7 * DAY;
+
+#[derive(Error, Debug)]
+pub enum SilenceError {
+    #[error("No silence with id {0}.")]
+    UnknownSilence(SilenceId),
+    #[error("Invalid event pattern '{0}'.")]
+    InvalidPattern(String),
+    #[error("Invalid weekday '{0}', expected one of Mon, Tue, Wed, Thu, Fri, Sat or Sun.")]
+    InvalidWeekday(String),
+    #[error("Invalid time of day '{0}', expected HH:MM.")]
+    InvalidTimeOfDay(String),
+}
+
+/// Which events a Silence or MaintenanceWindow applies to, every part that is set must match.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, Default)]
+pub struct SilenceMatcher {
+    #[serde(default)]
+    pub host:    Option<String>,
+    #[serde(default)]
+    pub monitor: Option<MonitorId>,
+    /// Matched against the polled event as formatted by Debug without quotes, or PollFailed and PollRecovered.
+    /// `*` matches any number of characters, like `disk_*`.
+    #[serde(default)]
+    pub event:   Option<String>,
+}
+
+impl SilenceMatcher {
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = Some(host.to_string());
+        self
+    }
+
+    pub fn monitor(mut self, monitor: impl Into<MonitorId>) -> Self {
+        self.monitor = Some(monitor.into());
+        self
+    }
+
+    pub fn event(mut self, pattern: &str) -> Result<Self, SilenceError> {
+        pattern_regex(pattern)?;
+        self.event = Some(pattern.to_string());
+        Ok(self)
+    }
+
+    pub fn matches<E: Event>(&self, event: &FiredEvent<E>) -> bool {
+        let payload = &event.payload;
+        self.host.as_ref().is_none_or(|host| *host == payload.host)
+            && self
+                .monitor
+                .as_ref()
+                .is_none_or(|monitor| *monitor == payload.monitor_id)
+            && self.event.as_ref().is_none_or(|pattern| {
+                pattern_regex(pattern).is_ok_and(|regex| regex.is_match(&event.key().name()))
+            })
+    }
+}
+
+impl fmt::Display for SilenceMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts = [
+            self.host.as_ref().map(|host| format!("host {}", host)),
+            self.monitor
+                .as_ref()
+                .map(|monitor| format!("monitor {}", monitor)),
+            self.event.as_ref().map(|event| format!("event {}", event)),
+        ];
+        match parts.iter().flatten().join(", ") {
+            everything if everything.is_empty() => write!(f, "everything"),
+            parts => write!(f, "{}", parts),
+        }
+    }
+}
+
+fn pattern_regex(pattern: &str) -> Result<regex::Regex, SilenceError> {
+    let regex = format!("^{}$", pattern.split('*').map(regex::escape).join(".*"));
+    regex::Regex::new(&regex).map_err(|_| SilenceError::InvalidPattern(pattern.to_string()))
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone, Debug)]
+pub struct SilenceId(pub u64);
+
+impl fmt::Display for SilenceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Keeps the actions of matching events from running for a time range, like during planned work.
+/// The events are still recorded in the history, with the silence as their `silenced_by` value.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct Silence {
+    /// Assigned when the silence is added to a running PollingMonitor.
+    pub id:         SilenceId,
+    pub matcher:    SilenceMatcher,
+    pub starts_at:  SystemTime,
+    pub ends_at:    SystemTime,
+    pub reason:     String,
+    pub created_by: String,
+}
+
+impl Silence {
+    pub fn new(matcher: SilenceMatcher, starts_at: SystemTime, duration: Duration) -> Self {
+        Silence {
+            id: SilenceId(0),
+            matcher,
+            starts_at,
+            ends_at: starts_at + duration,
+            reason: String::new(),
+            created_by: String::new(),
+        }
+    }
+
+    pub fn reason(mut self, reason: &str) -> Self {
+        self.reason = reason.to_string();
+        self
+    }
+
+    pub fn created_by(mut self, created_by: &str) -> Self {
+        self.created_by = created_by.to_string();
+        self
+    }
+
+    pub fn is_active(&self, at: SystemTime) -> bool {
+        self.starts_at <= at && at < self.ends_at
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl FromStr for Weekday {
+    type Err = SilenceError;
+
+    fn from_str(weekday: &str) -> Result<Self, Self::Err> {
+        let weekday = match weekday.trim().to_lowercase().get(..3) {
+            Some("mon") => Weekday::Mon,
+            Some("tue") => Weekday::Tue,
+            Some("wed") => Weekday::Wed,
+            Some("thu") => Weekday::Thu,
+            Some("fri") => Weekday::Fri,
+            Some("sat") => Weekday::Sat,
+            Some("sun") => Weekday::Sun,
+            _ => return Err(SilenceError::InvalidWeekday(weekday.to_string())),
+        };
+        Ok(weekday)
+    }
+}
+
+/// A silence recurring every week, like Sundays from 02:00 to 04:00.
+/// Times are in UTC unless a UTC offset is given, a window ending before it starts ends the next day.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct MaintenanceWindow {
+    pub name:       String,
+    pub matcher:    SilenceMatcher,
+    pub days:       Vec<Weekday>,
+    /// Seconds since midnight.
+    pub starts:     u64,
+    pub duration:   Duration,
+    /// Minutes east of UTC of the time zone of the times of day.
+    pub utc_offset: i64,
+}
+
+impl MaintenanceWindow {
+    /// A window on the days between two times of day formatted as HH:MM.
+    pub fn weekly(
+        name: &str,
+        matcher: SilenceMatcher,
+        days: Vec<Weekday>,
+        from: &str,
+        to: &str,
+    ) -> Result<Self, SilenceError> {
+        let starts = parse_time_of_day(from)?;
+        let ends = parse_time_of_day(to)?;
+        Ok(MaintenanceWindow {
+            name: name.to_string(),
+            matcher,
+            days,
+            starts,
+            duration: Duration::from_secs((ends + DAY - starts) % DAY),
+            utc_offset: 0,
+        })
+    }
+
+    /// Interpret the times of day in a time zone this many minutes east of UTC, negative for west.
+    pub fn utc_offset(mut self, minutes: i64) -> Self {
+        self.utc_offset = minutes;
+        self
+    }
+
+    pub fn is_active(&self, at: SystemTime) -> bool {
+        let utc = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let local = (utc as i64 + self.utc_offset * 60).max(0) as u64;
+        // The epoch was on a Thursday
+        let since_monday = (local + 3 * DAY) % WEEK;
+
+        self.days.iter().any(|day| {
+            let starts = *day as u64 * DAY + self.starts;
+            (since_monday + WEEK - starts) % WEEK < self.duration.as_secs()
+        })
+    }
+}
+
+fn parse_time_of_day(time: &str) -> Result<u64, SilenceError> {
+    let invalid = || SilenceError::InvalidTimeOfDay(time.to_string());
+    let (hours, minutes) = time.trim().split_once(':').ok_or_else(invalid)?;
+    let hours: u64 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u64 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(hours * 60 * 60 + minutes * 60)
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SilenceLog {
+    last_id:  u64,
+    silences: Vec<Silence>,
+}
+
+/// The silences and maintenance windows of a running PollingMonitor.
+pub(crate) struct Silences {
+    windows:     Vec<MaintenanceWindow>,
+    log:         SilenceLog,
+    state_store: Option<StateStore>,
+}
+
+impl Silences {
+    /// Restore the silences from the StateStore, if any.
+    pub(crate) fn new(windows: Vec<MaintenanceWindow>, state_store: Option<StateStore>) -> Self {
+        let log = match state_store.as_ref().map(|store| store.load(SILENCES_KEY)) {
+            Some(Ok(Some(log))) => log,
+            Some(Err(err)) => {
+                log::warn!("Failed to restore the silences: {:#}", err);
+                SilenceLog::default()
+            }
+            _ => SilenceLog::default(),
+        };
+
+        Silences {
+            windows,
+            log,
+            state_store,
+        }
+    }
+
+    /// The silences that have not ended yet.
+    pub(crate) fn silences(&self, now: SystemTime) -> Vec<Silence> {
+        self.log
+            .silences
+            .iter()
+            .filter(|silence| now < silence.ends_at)
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn add(&mut self, mut silence: Silence, now: SystemTime) -> SilenceId {
+        self.log.silences.retain(|silence| now < silence.ends_at);
+        self.log.last_id += 1;
+        silence.id = SilenceId(self.log.last_id);
+        log::info!(
+            "Silenced {} until {:?}: {}",
+            silence.matcher,
+            silence.ends_at,
+            silence.reason
+        );
+        self.log.silences.push(silence);
+        self.save();
+        SilenceId(self.log.last_id)
+    }
+
+    pub(crate) fn remove(&mut self, id: SilenceId) -> Result<Silence, SilenceError> {
+        let index = self
+            .log
+            .silences
+            .iter()
+            .position(|silence| silence.id == id)
+            .ok_or(SilenceError::UnknownSilence(id))?;
+        let silence = self.log.silences.remove(index);
+        self.save();
+        Ok(silence)
+    }
+
+    /// What silences the event, like `silence 3` or `maintenance window weekly`.
+    pub(crate) fn silenced_by<E: Event>(&self, event: &FiredEvent<E>) -> Option<String> {
+        let at = event.payload.timestamp;
+        self.log
+            .silences
+            .iter()
+            .find(|silence| silence.is_active(at) && silence.matcher.matches(event))
+            .map(|silence| format!("silence {}", silence.id))
+            .or_else(|| {
+                self.windows
+                    .iter()
+                    .find(|window| window.is_active(at) && window.matcher.matches(event))
+                    .map(|window| format!("maintenance window {}", window.name))
+            })
+    }
+
+    fn save(&self) {
+        if let Some(state_store) = &self.state_store {
+            if let Err(err) = state_store.save(SILENCES_KEY, &self.log) {
+                log::warn!("Failed to persist the silences: {:#}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_support::{ActionRecorder, MonitorHarness, ScriptedPolling},
+        Clock, EventKey, HistoryQuery, HistoryRecord, PollingMonitor, PollingSchedule,
+        VirtualClock,
+    };
+    use serde_json::Value;
+
+    #[test]
+    fn test_maintenance_window() {
+        let window = MaintenanceWindow::weekly(
+            "weekly",
+            SilenceMatcher::default(),
+            vec!["Sunday".parse().unwrap()],
+            "23:00",
+            "01:30",
+        )
+        .unwrap();
+        assert_eq!(window.duration, Duration::from_secs(2 * 60 * 60 + 30 * 60));
+
+        // 2024-01-07 was a Sunday
+        let sunday = UNIX_EPOCH + Duration::from_secs(1_704_585_600);
+        let at =
+            |hours: u64, minutes: u64| sunday + Duration::from_secs(hours * 3600 + minutes * 60);
+        assert!(!window.is_active(at(22, 59)));
+        assert!(window.is_active(at(23, 0)));
+        assert!(window.is_active(at(25, 29)));
+        assert!(!window.is_active(at(25, 30)));
+        assert!(!window.is_active(at(23 + 24 * 6, 0)));
+
+        let window = window.utc_offset(120);
+        assert!(window.is_active(at(21, 0)));
+        assert!(!window.is_active(at(23, 30)));
+
+        assert!(
+            MaintenanceWindow::weekly("", SilenceMatcher::default(), vec![], "24:00", "1:00")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_silenced_events_are_recorded() {
+        let clock = VirtualClock::new();
+        let recorder = ActionRecorder::new(&clock);
+        let every_10s = *PollingSchedule::default().interval(Duration::from_secs(10));
+
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .host("server-1")
+            .schedule_named_polling(
+                "disk",
+                every_10s,
+                ScriptedPolling::new()
+                    .event("disk_full")
+                    .into_polling_func(),
+            )
+            .schedule_named_polling(
+                "wg0",
+                every_10s,
+                ScriptedPolling::new().event("down").into_polling_func(),
+            )
+            .register_action("disk_full", recorder.action("page"))
+            .register_action("down", recorder.action("page"));
+
+        let mut harness = MonitorHarness::new(monitor, &clock);
+        let matcher = SilenceMatcher::default()
+            .host("server-1")
+            .event("disk_*")
+            .unwrap();
+        let silence = Silence::new(matcher, clock.system_now(), Duration::from_secs(15))
+            .reason("resizing the volume");
+        assert_eq!(harness.silence(silence), SilenceId(1));
+        harness.advance(Duration::from_secs(20));
+
+        assert_eq!(
+            recorder.timeline(),
+            vec![
+                (0, "page".to_string(), EventKey::Polled("down")),
+                (10, "page".to_string(), EventKey::Polled("down")),
+                (20, "page".to_string(), EventKey::Polled("disk_full")),
+                (20, "page".to_string(), EventKey::Polled("down")),
+            ]
+        );
+        let silenced: Vec<_> = harness
+            .history(HistoryQuery::default().monitor("disk"))
+            .into_iter()
+            .filter_map(|entry| match entry.record {
+                HistoryRecord::Event { values, .. } => values.get("silenced_by").cloned(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(silenced, vec![Value::from("silence 1"); 2]);
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    fmt::Write as _,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use internal_prelude::library_prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Keeps state that should survive restarts of the daemon, one JSON file per key in a directory.
+/// State that only grows, like the history, is kept as a log of JSON lines instead, so it can be appended to.
+#[derive(Clone, Debug)]
+pub struct StateStore {
+    dir: PathBuf,
+}
+
+impl StateStore {
+    /// Use the directory for the state, creating it if it does not exist.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(StateStore { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Replace the state under the key.
+    /// The state is written to a temporary file first so a crash never leaves it half written.
+    pub fn save<T: Serialize>(&self, key: &str, state: &T) -> Result<()> {
+        replace(&self.path(key, "json"), &serde_json::to_vec(state)?)
+    }
+
+    /// The state under the key, or None if nothing has been saved under it.
+    pub fn load<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match fs::read(self.path(key, "json")) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Add items to the end of the log under the key.
+    pub fn append<T: Serialize>(&self, key: &str, items: &[T]) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(key, "jsonl"))?;
+        file.write_all(&lines(items)?)?;
+        Ok(())
+    }
+
+    /// Replace the log under the key with the items, like to drop the ones no longer needed.
+    pub fn replace_log<T: Serialize>(&self, key: &str, items: &[T]) -> Result<()> {
+        replace(&self.path(key, "jsonl"), &lines(items)?)
+    }
+
+    /// The items of the log under the key, oldest first, or None if nothing has been appended under it.
+    /// A last line cut short by a crash while appending is left out.
+    pub fn load_log<T: DeserializeOwned>(&self, key: &str) -> Result<Option<Vec<T>>> {
+        let contents = match fs::read_to_string(self.path(key, "jsonl")) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let lines: Vec<&str> = contents.lines().collect();
+        let mut items = Vec::with_capacity(lines.len());
+        for (index, line) in lines.iter().enumerate() {
+            match serde_json::from_str(line) {
+                Ok(item) => items.push(item),
+                Err(_) if index == lines.len() - 1 && !contents.ends_with('\n') => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(Some(items))
+    }
+
+    /// The file of the key, characters other than ASCII letters, digits, `-` and `_` are percent encoded
+    /// so that different keys never share a file.
+    fn path(&self, key: &str, extension: &str) -> PathBuf {
+        let mut file_name = String::with_capacity(key.len());
+        for byte in key.bytes() {
+            match byte {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' => {
+                    file_name.push(byte as char)
+                }
+                _ => {
+                    let _ = write!(file_name, "%{:02X}", byte);
+                }
+            }
+        }
+        self.dir.join(format!("{}.{}", file_name, extension))
+    }
+}
+
+/// Write to a temporary file first so a crash never leaves the file half written.
+fn replace(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+fn lines<T: Serialize>(items: &[T]) -> Result<Vec<u8>> {
+    let mut lines = Vec::new();
+    for item in items {
+        serde_json::to_writer(&mut lines, item)?;
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{
+        collections::BTreeMap,
+        sync::atomic::{AtomicU32, Ordering},
+    };
+
+    /// A fresh StateStore in the temp directory for each call.
+    pub(crate) fn temp_state_store() -> StateStore {
+        static STORES: AtomicU32 = AtomicU32::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "monitoring-service-state-{}-{}",
+            std::process::id(),
+            STORES.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        StateStore::open(dir).unwrap()
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let store = temp_state_store();
+        assert_eq!(store.load::<Vec<u32>>("history/wg0").unwrap(), None);
+
+        let mut state = BTreeMap::new();
+        state.insert("wg0".to_string(), vec![1, 2, 3]);
+        store.save("history/wg0", &state).unwrap();
+        assert_eq!(store.load("history/wg0").unwrap(), Some(state));
+        assert!(store.dir().join("history%2Fwg0.json").exists());
+
+        // Keys that only differ in characters outside of file names don't share a file
+        store.save("history/wg1", &1).unwrap();
+        store.save("history_wg1", &2).unwrap();
+        assert_eq!(store.load("history/wg1").unwrap(), Some(1));
+        assert_eq!(store.load("history_wg1").unwrap(), Some(2));
+
+        fs::write(store.dir().join("broken.json"), "{").unwrap();
+        assert!(store.load::<Vec<u32>>("broken").is_err());
+
+        fs::remove_dir_all(store.dir()).unwrap();
+    }
+
+    #[test]
+    fn test_log() {
+        let store = temp_state_store();
+        assert_eq!(store.load_log::<u32>("history-wg0").unwrap(), None);
+
+        store.append("history-wg0", &[1, 2]).unwrap();
+        store.append("history-wg0", &[3]).unwrap();
+        assert_eq!(store.load_log("history-wg0").unwrap(), Some(vec![1, 2, 3]));
+
+        store.replace_log("history-wg0", &[3]).unwrap();
+        assert_eq!(store.load_log("history-wg0").unwrap(), Some(vec![3]));
+
+        // A line cut short while appending is left out, a broken line before it is an error
+        let path = store.dir().join("history-wg0.jsonl");
+        fs::write(&path, "3\n4\n[5,").unwrap();
+        assert_eq!(store.load_log("history-wg0").unwrap(), Some(vec![3, 4]));
+        fs::write(&path, "3\n[4,\n5\n").unwrap();
+        assert!(store.load_log::<u32>("history-wg0").is_err());
+
+        fs::remove_dir_all(store.dir()).unwrap();
+    }
+}
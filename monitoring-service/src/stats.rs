@@ -0,0 +1,193 @@
+use std::time::{Duration, SystemTime};
+
+use serde::Serialize;
+
+use crate::{ActionOutcome, ActionReport};
+
+/// Upper bounds of the buckets of a DurationHistogram in milliseconds, longer durations go in a last bucket.
+pub const DURATION_BUCKETS_MS: [u64; 11] =
+    [1, 5, 10, 50, 100, 500, 1000, 5000, 10000, 30000, 60000];
+
+/// How long the runs of a polling function or an action took.
+#[derive(Serialize, PartialEq, Eq, Clone, Debug)]
+pub struct DurationHistogram {
+    /// How many runs took up to the bound of the bucket of the same index in DURATION_BUCKETS_MS
+    /// but longer than the bound before it, the last count is of runs longer than all bounds.
+    pub counts: Vec<u64>,
+    pub count:  u64,
+    pub total:  Duration,
+    pub max:    Duration,
+}
+
+impl Default for DurationHistogram {
+    fn default() -> Self {
+        DurationHistogram {
+            counts: vec![0; DURATION_BUCKETS_MS.len() + 1],
+            count:  0,
+            total:  Duration::from_secs(0),
+            max:    Duration::from_secs(0),
+        }
+    }
+}
+
+impl DurationHistogram {
+    pub fn record(&mut self, duration: Duration) {
+        let bucket = DURATION_BUCKETS_MS
+            .iter()
+            .position(|bound| duration <= Duration::from_millis(*bound))
+            .unwrap_or(DURATION_BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.total += duration;
+        self.max = self.max.max(duration);
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count {
+            0 => None,
+            count => Some(self.total / count as u32),
+        }
+    }
+
+    /// The bound of the bucket the quantile falls in, like 0.95 for the 95th percentile.
+    /// Quantiles beyond the last bound are the longest duration seen.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = ((quantile.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(match DURATION_BUCKETS_MS.get(bucket) {
+                    Some(bound) => Duration::from_millis(*bound).min(self.max),
+                    None => self.max,
+                });
+            }
+        }
+        Some(self.max)
+    }
+}
+
+/// Runs, failures and durations of a polling function or an action since the PollingMonitor started.
+#[derive(Serialize, PartialEq, Eq, Clone, Debug, Default)]
+pub struct ExecutionStats {
+    pub runs:         u64,
+    pub failures:     u64,
+    pub last_success: Option<SystemTime>,
+    pub last_failure: Option<SystemTime>,
+    pub durations:    DurationHistogram,
+}
+
+impl ExecutionStats {
+    pub(crate) fn record(&mut self, at: SystemTime, duration: Duration, succeeded: bool) {
+        self.runs += 1;
+        if succeeded {
+            self.last_success = Some(at);
+        } else {
+            self.failures += 1;
+            self.last_failure = Some(at);
+        }
+        self.durations.record(duration);
+    }
+
+    /// Record the run of an action, actions held back by a Throttle did not run and are not counted.
+    pub(crate) fn record_action(&mut self, report: &ActionReport) {
+        if report.outcome != ActionOutcome::Suppressed {
+            self.record(
+                report.finished_at,
+                report.duration,
+                report.outcome == ActionOutcome::Succeeded,
+            );
+        }
+    }
+
+    /// The share of runs that failed, 0 if there were no runs.
+    pub fn failure_rate(&self) -> f64 {
+        match self.runs {
+            0 => 0.0,
+            runs => self.failures as f64 / runs as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use internal_prelude::application_prelude::anyhow;
+
+    use crate::{
+        test_support::{ActionRecorder, MonitorHarness, ScriptedPolling},
+        ActionFunc, EventKey, PollingMonitor, PollingSchedule, VirtualClock,
+    };
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = DurationHistogram::default();
+        assert_eq!(histogram.quantile(0.5), None);
+        assert_eq!(histogram.mean(), None);
+
+        for ms in &[3, 4, 8, 40, 45, 90, 700, 2000, 2500, 90_000] {
+            histogram.record(Duration::from_millis(*ms));
+        }
+        assert_eq!(histogram.counts, vec![0, 2, 1, 2, 1, 0, 1, 2, 0, 0, 0, 1]);
+        assert_eq!(histogram.count, 10);
+        assert_eq!(histogram.mean(), Some(Duration::from_millis(9539)));
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_millis(50)));
+        assert_eq!(histogram.quantile(0.9), Some(Duration::from_millis(5000)));
+        assert_eq!(histogram.quantile(1.0), Some(Duration::from_secs(90)));
+        assert_eq!(histogram.quantile(0.0), Some(Duration::from_millis(5)));
+
+        let mut stats = ExecutionStats::default();
+        stats.record(SystemTime::UNIX_EPOCH, Duration::from_millis(3), true);
+        stats.record(SystemTime::UNIX_EPOCH, Duration::from_millis(3), false);
+        stats.record(SystemTime::UNIX_EPOCH, Duration::from_millis(3), false);
+        stats.record(SystemTime::UNIX_EPOCH, Duration::from_millis(3), true);
+        assert_eq!((stats.runs, stats.failures), (4, 2));
+        assert!((stats.failure_rate() - 0.5).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_monitor_and_action_stats() {
+        let clock = VirtualClock::new();
+        let recorder = ActionRecorder::new(&clock);
+        let polling_func = ScriptedPolling::new()
+            .event("up")
+            .failure("timed out")
+            .failure("timed out")
+            .event("up")
+            .into_polling_func();
+
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .schedule_named_polling(
+                "wg0",
+                *PollingSchedule::default().interval(Duration::from_secs(10)),
+                polling_func,
+            )
+            .register_action("up", recorder.action("log"))
+            .register_action(
+                EventKey::PollFailed,
+                ActionFunc::new("page", |_| Err(anyhow!("smtp down"))),
+            );
+
+        let mut harness = MonitorHarness::new(monitor, &clock);
+        harness.advance(Duration::from_secs(35));
+
+        let stats = harness.status("wg0").unwrap().stats;
+        assert_eq!((stats.runs, stats.failures), (4, 2));
+        assert_eq!(stats.durations.count, 4);
+        assert!(stats.last_failure.unwrap() < stats.last_success.unwrap());
+
+        let action_stats = harness.action_stats();
+        assert_eq!(
+            action_stats
+                .iter()
+                .map(|(action, stats)| (action.as_str(), stats.runs, stats.failures))
+                .collect::<Vec<_>>(),
+            vec![("log", 2, 0), ("page", 2, 2)]
+        );
+    }
+}
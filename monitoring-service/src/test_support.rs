@@ -0,0 +1,429 @@
+//! Deterministic testing of PollingMonitors.
+//!
+//! A MonitorHarness runs the polling functions of a PollingMonitor on a VirtualClock in the calling thread,
+//! so schedules, firing modes, throttles and the Watchdog can be tested by advancing time step by step.
+//! ScriptedPolling and ActionRecorder provide the polling functions and actions to drive and observe it.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
+
+use internal_prelude::{application_prelude::anyhow, library_prelude::*};
+
+use crate::{
+    runner::{fire_event, run_escalations, Poller, PollingContext},
+    watchdog::WatchdogState,
+    ActionFunc, ActionReport, Clock, Event, EventKey, ExecutionStats, FiredEvent, HistoryEntry,
+    HistoryQuery, Incident, IncidentError, IncidentId, MonitorId, MonitorStatus, Observation,
+    PollingFunc, PollingMonitor, PollingSchedule, Silence, SilenceId, VirtualClock,
+};
+
+#[derive(Clone)]
+enum Step<E: Event> {
+    Observations(Vec<Observation<E>>),
+    Failure(String),
+}
+
+/// A polling function returning scripted results in order, repeating the last one once they run out.
+pub struct ScriptedPolling<E: Event> {
+    steps: VecDeque<Step<E>>,
+}
+
+impl<E: Event> Default for ScriptedPolling<E> {
+    fn default() -> Self {
+        ScriptedPolling {
+            steps: VecDeque::new(),
+        }
+    }
+}
+
+impl<E: Event> ScriptedPolling<E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The next poll returns the event.
+    pub fn event(self, event: E) -> Self {
+        self.observations(vec![Observation::new(event)])
+    }
+
+    /// The next `polls` polls return the event.
+    pub fn events(mut self, event: E, polls: usize) -> Self {
+        for _ in 0..polls {
+            self = self.event(event.clone());
+        }
+        self
+    }
+
+    /// The next poll returns the observations.
+    pub fn observations(mut self, observations: Vec<Observation<E>>) -> Self {
+        self.steps.push_back(Step::Observations(observations));
+        self
+    }
+
+    /// The next poll fails with the error.
+    pub fn failure(mut self, error: &str) -> Self {
+        self.steps.push_back(Step::Failure(error.to_string()));
+        self
+    }
+
+    pub fn into_polling_func(self) -> PollingFunc<E> {
+        let steps = Mutex::new(self.steps);
+        PollingFunc::with_observations(move || {
+            let mut steps = steps.lock();
+            let step = if steps.len() > 1 {
+                steps.pop_front()
+            } else {
+                steps.front().cloned()
+            };
+
+            match step {
+                Some(Step::Observations(observations)) => Ok(observations),
+                Some(Step::Failure(error)) => Err(anyhow!(error)),
+                None => Ok(vec![]),
+            }
+        })
+    }
+}
+
+/// A run of an action recorded by an ActionRecorder.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Invocation<E: Event> {
+    /// When the action ran, relative to when the VirtualClock was created.
+    pub at:     Duration,
+    pub action: String,
+    pub event:  FiredEvent<E>,
+}
+
+/// Creates actions that record when they are run and for what.
+#[derive(Clone)]
+pub struct ActionRecorder<E: Event> {
+    clock:       VirtualClock,
+    invocations: Arc<Mutex<Vec<Invocation<E>>>>,
+}
+
+impl<E: Event> ActionRecorder<E> {
+    pub fn new(clock: &VirtualClock) -> Self {
+        ActionRecorder {
+            clock:       clock.clone(),
+            invocations: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// An action that records its runs and succeeds.
+    pub fn action(&self, name: &str) -> ActionFunc<E> {
+        let recorder = self.clone();
+        let action = name.to_string();
+        ActionFunc::new(name, move |event: &FiredEvent<E>| {
+            recorder.invocations.lock().push(Invocation {
+                at:     recorder.clock.elapsed(),
+                action: action.clone(),
+                event:  event.clone(),
+            });
+            Ok(())
+        })
+    }
+
+    pub fn invocations(&self) -> Vec<Invocation<E>> {
+        self.invocations.lock().clone()
+    }
+
+    /// When, in whole seconds, which action ran for which event, in the order they ran.
+    pub fn timeline(&self) -> Vec<(u64, String, EventKey<E>)> {
+        self.invocations
+            .lock()
+            .iter()
+            .map(|invocation| {
+                (
+                    invocation.at.as_secs(),
+                    invocation.action.clone(),
+                    invocation.event.key(),
+                )
+            })
+            .collect()
+    }
+}
+
+struct ScheduledPoller<E: Event> {
+    poller:    Poller<E>,
+    schedule:  PollingSchedule,
+    next_poll: Duration,
+}
+
+struct ScheduledWatchdog {
+    state:      WatchdogState,
+    next_check: Duration,
+}
+
+/// Runs the polling functions of a PollingMonitor in the calling thread on a VirtualClock.
+///
+/// Each polling function is polled when the harness is created and then every interval of its schedule,
+/// polls take no virtual time. Polls due at the same time run in the order of their MonitorIds.
+/// The Watchdog of the PollingMonitor checks when the harness is created and then every check interval.
+pub struct MonitorHarness<E: Event> {
+    clock:    VirtualClock,
+    context:  Arc<PollingContext<E>>,
+    pollers:  BTreeMap<MonitorId, ScheduledPoller<E>>,
+    hanging:  BTreeSet<MonitorId>,
+    watchdog: Option<ScheduledWatchdog>,
+    fired:    Vec<(Duration, FiredEvent<E>)>,
+}
+
+impl<E: Event> MonitorHarness<E> {
+    /// Panics if the dependencies of the polling functions form a cycle.
+    pub fn new(mut monitor: PollingMonitor<E>, clock: &VirtualClock) -> Self {
+        let context = Arc::new(PollingContext::new(&mut monitor, Arc::new(clock.clone())));
+
+        let pollers = monitor
+            .polling_schedule
+            .into_iter()
+            .map(|(id, scheduled)| {
+                let poller = Poller::new(
+                    id.clone(),
+                    scheduled.schedule,
+                    scheduled.polling_func,
+                    scheduled.options,
+                    Arc::clone(&context),
+                )
+                .expect("valid monitor dependencies");
+                let scheduled_poller = ScheduledPoller {
+                    poller,
+                    schedule: scheduled.schedule,
+                    next_poll: clock.elapsed(),
+                };
+                (id, scheduled_poller)
+            })
+            .collect();
+
+        let watchdog = monitor.watchdog.map(|watchdog| ScheduledWatchdog {
+            state:      WatchdogState::new(watchdog),
+            next_check: clock.elapsed(),
+        });
+
+        MonitorHarness {
+            clock: clock.clone(),
+            context,
+            pollers,
+            hanging: BTreeSet::new(),
+            watchdog,
+            fired: Vec::new(),
+        }
+    }
+
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    /// Move the clock to the next due poll and run it, returns the MonitorId polled.
+    /// Steps of escalations and checks of the Watchdog that become due on the way run before it.
+    pub fn step(&mut self) -> Option<MonitorId> {
+        let (id, next_poll) = self
+            .pollers
+            .iter()
+            .filter(|(id, _)| !self.hanging.contains(*id))
+            .map(|(id, scheduled)| (id.clone(), scheduled.next_poll))
+            .min_by_key(|(_, next_poll)| *next_poll)?;
+
+        self.run_background_until(next_poll);
+        self.clock.advance_to(next_poll);
+        self.poll(&id);
+        Some(id)
+    }
+
+    /// Move the clock forward, running every poll, step of an escalation and check of the Watchdog
+    /// that becomes due on the way.
+    pub fn advance(&mut self, by: Duration) -> &mut Self {
+        let until = self.clock.elapsed() + by;
+        while self
+            .pollers
+            .iter()
+            .any(|(id, scheduled)| !self.hanging.contains(id) && scheduled.next_poll <= until)
+        {
+            self.step();
+        }
+        self.run_background_until(until);
+        self.clock.advance_to(until);
+        self
+    }
+
+    /// Stop polling the function, like a poll that never returns, until it is released.
+    pub fn hang(&mut self, id: impl Into<MonitorId>) -> &mut Self {
+        self.hanging.insert(id.into());
+        self
+    }
+
+    /// Finish the hanging poll of the function right away, the next poll is due an interval from now.
+    pub fn release(&mut self, id: impl Into<MonitorId>) -> &mut Self {
+        let id = id.into();
+        self.hanging.remove(&id);
+        self.poll(&id);
+        self
+    }
+
+    /// Poll right away, the next poll is due an interval from now.
+    ///
+    /// Panics if no polling function is scheduled under the id.
+    pub fn check_now(&mut self, id: impl Into<MonitorId>) -> &mut Self {
+        self.poll(&id.into());
+        self
+    }
+
+    /// Every fired event with when it fired, in order.
+    pub fn fired(&self) -> &[(Duration, FiredEvent<E>)] {
+        &self.fired
+    }
+
+    /// When, in whole seconds, which events fired, in order.
+    pub fn fired_timeline(&self) -> Vec<(u64, EventKey<E>)> {
+        self.fired
+            .iter()
+            .map(|(at, event)| (at.as_secs(), event.key()))
+            .collect()
+    }
+
+    pub fn history(&self, query: &HistoryQuery) -> Vec<HistoryEntry> {
+        self.context.inner.lock().history.query(query)
+    }
+
+    pub fn status(&self, id: impl Into<MonitorId>) -> Option<MonitorStatus> {
+        self.context.inner.lock().monitors.get(&id.into()).cloned()
+    }
+
+    pub fn action_stats(&self) -> BTreeMap<String, ExecutionStats> {
+        self.context.inner.lock().action_stats()
+    }
+
+    pub fn action_reports(&self) -> Vec<ActionReport> {
+        self.context.inner.lock().action_reports()
+    }
+
+    pub fn incidents(&self) -> Vec<Incident> {
+        self.context.incidents.lock().incidents()
+    }
+
+    pub fn acknowledge(&mut self, id: IncidentId, by: &str) -> Result<Incident, IncidentError> {
+        self.context
+            .incidents
+            .lock()
+            .acknowledge(id, by, self.clock.system_now())
+    }
+
+    pub fn silence(&mut self, silence: Silence) -> SilenceId {
+        self.context
+            .silences
+            .lock()
+            .add(silence, self.clock.system_now())
+    }
+
+    /// Run the steps of escalations and checks of the Watchdog that become due until then, in order.
+    fn run_background_until(&mut self, until: Duration) {
+        loop {
+            let next_escalation = self.context.escalations.lock().next_due().map(|next_due| {
+                self.clock.elapsed() + next_due.saturating_duration_since(self.clock.now())
+            });
+            let next_check = self.watchdog.as_ref().map(|watchdog| watchdog.next_check);
+            let next_due = match next_escalation.into_iter().chain(next_check).min() {
+                Some(next_due) if next_due <= until => next_due,
+                _ => return,
+            };
+
+            self.clock.advance_to(next_due);
+            match &mut self.watchdog {
+                Some(watchdog) if watchdog.next_check == next_due => {
+                    for event in watchdog.state.check(&self.context) {
+                        self.fired.push((next_due, event.clone()));
+                        fire_event(&self.context, event);
+                    }
+                    watchdog.next_check = next_due + watchdog.state.interval();
+                }
+                _ => {
+                    let (reports, history) = run_escalations(&self.context);
+                    self.context.inner.lock().record(reports, history);
+                }
+            }
+        }
+    }
+
+    fn poll(&mut self, id: &MonitorId) {
+        let scheduled = match self.pollers.get_mut(id) {
+            Some(scheduled) => scheduled,
+            None => panic!("no polling function scheduled with id {}", id),
+        };
+
+        let at = self.clock.elapsed();
+        for event in scheduled.poller.poll(|| true) {
+            self.fired.push((at, event));
+        }
+        scheduled.next_poll = at + scheduled.schedule.interval;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scripted_polling() {
+        let polling_func = ScriptedPolling::new()
+            .event("up")
+            .failure("timed out")
+            .events("down", 2)
+            .into_polling_func();
+
+        let polled: Vec<String> = (0..5)
+            .map(|_| match (polling_func.0)(std::time::Instant::now()) {
+                Ok(observations) => format!("{:?}", observations[0].event),
+                Err(err) => err.to_string(),
+            })
+            .collect();
+        assert_eq!(
+            polled,
+            vec!["\"up\"", "timed out", "\"down\"", "\"down\"", "\"down\""]
+        );
+    }
+
+    #[test]
+    fn test_harness_schedule() {
+        let clock = VirtualClock::new();
+        let every = |secs| *PollingSchedule::default().interval(Duration::from_secs(secs));
+
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .schedule_named_polling(
+                "a",
+                every(10),
+                ScriptedPolling::new().event("a").into_polling_func(),
+            )
+            .schedule_named_polling(
+                "b",
+                every(15),
+                ScriptedPolling::new().event("b").into_polling_func(),
+            );
+        let mut harness = MonitorHarness::new(monitor, &clock);
+
+        harness.advance(Duration::from_secs(30));
+        assert_eq!(clock.elapsed(), Duration::from_secs(30));
+        assert_eq!(
+            harness.fired_timeline(),
+            vec![
+                (0, EventKey::Polled("a")),
+                (0, EventKey::Polled("b")),
+                (10, EventKey::Polled("a")),
+                (15, EventKey::Polled("b")),
+                (20, EventKey::Polled("a")),
+                (30, EventKey::Polled("a")),
+                (30, EventKey::Polled("b")),
+            ]
+        );
+
+        harness.advance(Duration::from_secs(5)).check_now("b");
+        assert_eq!(harness.step(), Some(MonitorId::new("a")));
+        assert_eq!(clock.elapsed(), Duration::from_secs(40));
+        // Checking b at 35 moved its next poll to 50, ties run in order of the ids
+        assert_eq!(harness.step(), Some(MonitorId::new("a")));
+        assert_eq!(harness.step(), Some(MonitorId::new("b")));
+        assert_eq!(clock.elapsed(), Duration::from_secs(50));
+    }
+}
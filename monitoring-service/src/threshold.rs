@@ -0,0 +1,278 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use internal_prelude::library_prelude::*;
+
+use crate::Hysteresis;
+
+/// A declarative condition on the samples of a metric, parsed from strings like:
+///
+/// - `> 90` the value is above 90
+/// - `>= 90 for 5m` the value is at least 90 for 5 minutes straight
+/// - `< 10% of baseline` the value is below 10% of the average value over the baseline window
+/// - `rate > 100/s` the value grows by more than 100 per second between polls
+/// - `> 90 clear < 85` the value went above 90 and hasn't gone below 85 since, so it doesn't flap around 90
+#[derive(PartialEq, Clone, Debug)]
+pub struct ThresholdRule {
+    source:     String,
+    measure:    Measure,
+    comparison: Comparison,
+    // For rates this is always per second, like the clear threshold
+    threshold:  f64,
+    hold:       Duration,
+    clear:      Option<(Comparison, f64)>,
+}
+
+/// What a ThresholdRule compares against its threshold.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Measure {
+    Value,
+    PercentOfBaseline,
+    RatePerSecond,
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Comparison {
+    Above,
+    AtLeast,
+    Below,
+    AtMost,
+}
+
+impl Comparison {
+    pub(crate) fn holds(self, measured: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Above => measured > threshold,
+            Comparison::AtLeast => measured >= threshold,
+            Comparison::Below => measured < threshold,
+            Comparison::AtMost => measured <= threshold,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ThresholdRuleError {
+    #[error("Invalid threshold rule '{0}', expected something like '> 90 for 5m', '< 10% of baseline' or 'rate > 100/s'.")]
+    InvalidRule(String),
+    #[error("Invalid threshold rule '{0}', the clear condition must be on the other side of the threshold, like '> 90 clear < 85'.")]
+    InvalidClear(String),
+    #[error("Invalid duration '{0}', expected a whole number followed by s, m, h or d.")]
+    InvalidDuration(String),
+}
+
+impl ThresholdRule {
+    pub fn measure(&self) -> Measure {
+        self.measure
+    }
+
+    /// How long the condition must hold before the rule is breached.
+    pub fn hold(&self) -> Duration {
+        self.hold
+    }
+
+    /// Whether the measured value, as described by measure(), meets the condition.
+    pub fn is_met(&self, measured: f64) -> bool {
+        self.comparison.holds(measured, self.threshold)
+    }
+
+    /// For rules with a clear condition, the state deciding whether the measured values of a series meet the rule.
+    pub fn hysteresis(&self) -> Option<Hysteresis> {
+        self.clear
+            .map(|clear| Hysteresis::between((self.comparison, self.threshold), clear))
+    }
+}
+
+fn parse_comparison(comparison: &str) -> Comparison {
+    match comparison {
+        ">" => Comparison::Above,
+        ">=" => Comparison::AtLeast,
+        "<" => Comparison::Below,
+        _ => Comparison::AtMost,
+    }
+}
+
+impl FromStr for ThresholdRule {
+    type Err = ThresholdRuleError;
+
+    fn from_str(rule: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || ThresholdRuleError::InvalidRule(rule.to_string());
+        let c = RULE_RE.captures(rule).ok_or_else(invalid)?;
+
+        let comparison = parse_comparison(&c["comparison"]);
+        let mut threshold = c["threshold"].parse::<f64>().map_err(|_| invalid())?;
+        let mut clear = match (c.name("clear_comparison"), c.name("clear")) {
+            (Some(clear_comparison), Some(clear)) => Some((
+                parse_comparison(clear_comparison.as_str()),
+                clear.as_str().parse::<f64>().map_err(|_| invalid())?,
+            )),
+            _ => None,
+        };
+
+        let measure = match (c.name("rate"), c.name("percent"), c.name("per")) {
+            (Some(_), None, per) => {
+                if let Some(per) = per {
+                    threshold /= unit_seconds(per.as_str()) as f64;
+                }
+                if let (Some((_, clear)), Some(per)) = (&mut clear, c.name("clear_per")) {
+                    *clear /= unit_seconds(per.as_str()) as f64;
+                }
+                Measure::RatePerSecond
+            }
+            _ if c.name("clear_per").is_some() => return Err(invalid()),
+            (None, Some(_), None) => Measure::PercentOfBaseline,
+            (None, None, None) => Measure::Value,
+            _ => return Err(invalid()),
+        };
+
+        let hold = match c.name("hold") {
+            Some(hold) => parse_duration(hold.as_str())?,
+            None => Duration::from_secs(0),
+        };
+
+        if let Some((clear_comparison, clear_threshold)) = clear {
+            let above = matches!(comparison, Comparison::Above | Comparison::AtLeast);
+            let clears_below = matches!(clear_comparison, Comparison::Below | Comparison::AtMost);
+            let on_other_side = if above {
+                clears_below && clear_threshold <= threshold
+            } else {
+                !clears_below && clear_threshold >= threshold
+            };
+            if !on_other_side {
+                return Err(ThresholdRuleError::InvalidClear(rule.to_string()));
+            }
+        }
+
+        Ok(ThresholdRule {
+            source: rule.trim().to_string(),
+            measure,
+            comparison,
+            threshold,
+            hold,
+            clear,
+        })
+    }
+}
+
+impl fmt::Display for ThresholdRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Parse durations like `30s`, `5m`, `2h` or `1d`.
+pub fn parse_duration(duration: &str) -> std::result::Result<Duration, ThresholdRuleError> {
+    let invalid = || ThresholdRuleError::InvalidDuration(duration.to_string());
+    let duration = duration.trim();
+    if duration.len() < 2 {
+        return Err(invalid());
+    }
+
+    let (amount, unit) = duration.split_at(duration.len() - 1);
+    let amount = amount.parse::<u64>().map_err(|_| invalid())?;
+    match unit {
+        "s" | "m" | "h" | "d" => Ok(Duration::from_secs(amount * unit_seconds(unit))),
+        _ => Err(invalid()),
+    }
+}
+
+fn unit_seconds(unit: &str) -> u64 {
+    match unit {
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => 1,
+    }
+}
+
+lazy_static! {
+    /// [rate] <comparison> <threshold>[% of baseline | /<unit>] [for <duration>] [clear <comparison> <threshold>[/<unit>]]
+    static ref RULE_RE: regex::Regex = regex::Regex::new(
+        r"^\s*(?P<rate>rate\s+)?(?P<comparison>>=|<=|>|<)\s*(?P<threshold>-?\d+(?:\.\d+)?)\s*(?:(?P<percent>%)\s*of\s+baseline|/(?P<per>[smhd]))?(?:\s+for\s+(?P<hold>\d+[smhd]))?(?:\s+clear\s+(?P<clear_comparison>>=|<=|>|<)\s*(?P<clear>-?\d+(?:\.\d+)?)(?:/(?P<clear_per>[smhd]))?)?\s*$"
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rules() {
+        let rule: ThresholdRule = "> 90 for 5m".parse().unwrap();
+        assert_eq!(rule.measure(), Measure::Value);
+        assert_eq!(rule.hold(), Duration::from_secs(300));
+        assert!(rule.is_met(90.5));
+        assert!(!rule.is_met(90.0));
+
+        let rule: ThresholdRule = "< 10% of baseline".parse().unwrap();
+        assert_eq!(rule.measure(), Measure::PercentOfBaseline);
+        assert_eq!(rule.hold(), Duration::from_secs(0));
+        assert!(rule.is_met(9.0));
+
+        let rule: ThresholdRule = "rate > 120/m for 30s".parse().unwrap();
+        assert_eq!(rule.measure(), Measure::RatePerSecond);
+        assert_eq!(rule.hold(), Duration::from_secs(30));
+        assert!(rule.is_met(2.5));
+        assert!(!rule.is_met(2.0));
+
+        let rule: ThresholdRule = "rate >= 0.5".parse().unwrap();
+        assert!(rule.is_met(0.5));
+        assert_eq!(rule.to_string(), "rate >= 0.5");
+
+        let rule: ThresholdRule = "<= -3".parse().unwrap();
+        assert!(rule.is_met(-3.0));
+        assert!(rule.hysteresis().is_none());
+
+        let rule: ThresholdRule = "> 90 for 5m clear < 85".parse().unwrap();
+        assert_eq!(rule.hold(), Duration::from_secs(300));
+        let hysteresis = rule.hysteresis().unwrap();
+        let values = [90.0, 91.0, 86.0, 85.0, 84.0, 88.0];
+        let met: Vec<bool> = values.iter().map(|v| hysteresis.check(*v)).collect();
+        assert_eq!(met, vec![false, true, true, true, false, false]);
+
+        let rule: ThresholdRule = "rate < 60/m clear >= 120/m".parse().unwrap();
+        let hysteresis = rule.hysteresis().unwrap();
+        let met: Vec<bool> = [0.5, 1.5, 2.0]
+            .iter()
+            .map(|v| hysteresis.check(*v))
+            .collect();
+        assert_eq!(met, vec![true, true, false]);
+    }
+
+    #[test]
+    fn test_parse_invalid_rules() {
+        for rule in &[
+            "90",
+            "> ninety",
+            "> 90 for 5 minutes",
+            "rate > 5% of baseline",
+            "> 5/s",
+            "> 90 clear",
+            "> 90 clear < 85/s",
+        ] {
+            assert!(
+                rule.parse::<ThresholdRule>().is_err(),
+                "rule '{}' should not parse",
+                rule
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid_clear() {
+        for rule in &["> 90 clear > 95", "> 90 clear < 95", "< 10 clear < 5"] {
+            assert_eq!(
+                rule.parse::<ThresholdRule>().unwrap_err().to_string(),
+                format!("Invalid threshold rule '{}', the clear condition must be on the other side of the threshold, like '> 90 clear < 85'.", rule)
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45s").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86400));
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("5w").is_err());
+    }
+}
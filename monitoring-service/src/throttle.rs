@@ -0,0 +1,257 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+
+use crate::{Event, FiredEvent};
+
+/// Limits how often an action, or all actions of an event, run.
+///
+/// Occurrences that are held back are counted and the count is handed to the next
+/// occurrence that does go through as FiredEvent::suppressed.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct Throttle {
+    cooldown:   Option<Duration>,
+    dedup_by:   Vec<String>,
+    rate_limit: Option<RateLimit>,
+}
+
+/// A token bucket holding up to `burst` tokens which is refilled completely over `per`.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct RateLimit {
+    burst: u32,
+    per:   Duration,
+}
+
+impl Throttle {
+    /// After going through, further occurrences are held back for the cooldown.
+    /// When deduplicating the cooldown applies to each deduplication key separately.
+    pub fn cooldown(&mut self, cooldown: Duration) -> &mut Self {
+        self.cooldown = Some(cooldown);
+        self
+    }
+
+    /// Add a payload value to the deduplication key, occurrences with different keys
+    /// have separate cooldowns. For example deduplicating by "interface" with a one hour
+    /// cooldown results in at most one alert per interface per hour.
+    pub fn dedup_by(&mut self, value_name: &str) -> &mut Self {
+        self.dedup_by.push(value_name.to_string());
+        self
+    }
+
+    /// Let through at most `burst` occurrences at once, and `burst` per `per` over time.
+    pub fn rate_limit(&mut self, burst: u32, per: Duration) -> &mut Self {
+        self.rate_limit = Some(RateLimit { burst, per });
+        self
+    }
+
+    fn dedup_key<E: Event>(&self, event: &FiredEvent<E>) -> String {
+        self.dedup_by
+            .iter()
+            .map(|name| match event.value(name) {
+                Some(Value::String(value)) => value.clone(),
+                Some(value) => value.to_string(),
+                None => String::new(),
+            })
+            .collect::<Vec<_>>()
+            .join("\u{1f}")
+    }
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub(crate) enum ThrottleDecision {
+    /// Let the occurrence through, with how many similar ones were held back before it.
+    Pass {
+        suppressed: u32,
+    },
+    Suppress,
+}
+
+#[derive(Default)]
+struct TokenBucket {
+    tokens:      f64,
+    last_refill: Option<Instant>,
+}
+
+impl TokenBucket {
+    fn take(&mut self, limit: RateLimit, now: Instant) -> bool {
+        let burst = f64::from(limit.burst);
+        self.tokens = match self.last_refill {
+            Some(last_refill) => {
+                let refilled =
+                    now.duration_since(last_refill).as_secs_f64() / limit.per.as_secs_f64();
+                f64::min(burst, self.tokens + refilled * burst)
+            }
+            None => burst,
+        };
+        self.last_refill = Some(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The mutable state needed to apply a Throttle.
+pub(crate) struct ThrottleState {
+    throttle:   Throttle,
+    last_pass:  HashMap<String, Instant>,
+    suppressed: HashMap<String, u32>,
+    bucket:     TokenBucket,
+}
+
+impl ThrottleState {
+    pub(crate) fn new(throttle: Throttle) -> Self {
+        ThrottleState {
+            throttle,
+            last_pass: HashMap::new(),
+            suppressed: HashMap::new(),
+            bucket: TokenBucket::default(),
+        }
+    }
+
+    pub(crate) fn check<E: Event>(
+        &mut self,
+        event: &FiredEvent<E>,
+        now: Instant,
+    ) -> ThrottleDecision {
+        let key = self.throttle.dedup_key(event);
+
+        let cooling_down = match (self.throttle.cooldown, self.last_pass.get(&key)) {
+            (Some(cooldown), Some(last_pass)) => now.duration_since(*last_pass) < cooldown,
+            _ => false,
+        };
+        let rate_limited = match self.throttle.rate_limit {
+            Some(limit) if !cooling_down => !self.bucket.take(limit, now),
+            _ => false,
+        };
+
+        if cooling_down || rate_limited {
+            *self.suppressed.entry(key).or_insert(0) += 1;
+            return ThrottleDecision::Suppress;
+        }
+
+        self.last_pass.insert(key.clone(), now);
+        ThrottleDecision::Pass {
+            suppressed: self.suppressed.remove(&key).unwrap_or(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MonitorEvent, MonitorId, Values};
+
+    fn interface_down(interface: &str) -> FiredEvent<&'static str> {
+        let mut values = Values::new();
+        values.insert("interface".to_string(), Value::from(interface));
+        FiredEvent::new(
+            MonitorEvent::Polled("interface_down"),
+            &MonitorId::new("interfaces"),
+            "server-1",
+            values,
+        )
+    }
+
+    fn decisions(state: &mut ThrottleState, occurrences: &[(&str, u64)]) -> Vec<ThrottleDecision> {
+        let start = Instant::now();
+        occurrences
+            .iter()
+            .map(|(interface, secs)| {
+                state.check(
+                    &interface_down(interface),
+                    start + Duration::from_secs(*secs),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let mut state = ThrottleState::new(
+            Throttle::default()
+                .cooldown(Duration::from_secs(10))
+                .clone(),
+        );
+        assert_eq!(
+            decisions(
+                &mut state,
+                &[("wg0", 0), ("wg0", 5), ("eth0", 6), ("wg0", 9), ("wg0", 10)]
+            ),
+            vec![
+                ThrottleDecision::Pass { suppressed: 0 },
+                ThrottleDecision::Suppress,
+                ThrottleDecision::Suppress,
+                ThrottleDecision::Suppress,
+                ThrottleDecision::Pass { suppressed: 3 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut state = ThrottleState::new(
+            Throttle::default()
+                .cooldown(Duration::from_secs(3600))
+                .dedup_by("interface")
+                .clone(),
+        );
+        assert_eq!(
+            decisions(
+                &mut state,
+                &[
+                    ("wg0", 0),
+                    ("wg0", 60),
+                    ("eth0", 120),
+                    ("wg0", 3599),
+                    ("wg0", 3600),
+                    ("eth0", 3600)
+                ]
+            ),
+            vec![
+                ThrottleDecision::Pass { suppressed: 0 },
+                ThrottleDecision::Suppress,
+                ThrottleDecision::Pass { suppressed: 0 },
+                ThrottleDecision::Suppress,
+                ThrottleDecision::Pass { suppressed: 2 },
+                ThrottleDecision::Suppress,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut state = ThrottleState::new(
+            Throttle::default()
+                .rate_limit(2, Duration::from_secs(60))
+                .clone(),
+        );
+        assert_eq!(
+            decisions(
+                &mut state,
+                &[
+                    ("wg0", 0),
+                    ("wg0", 0),
+                    ("wg0", 1),
+                    ("wg0", 29),
+                    ("wg0", 31),
+                    ("wg0", 32)
+                ]
+            ),
+            vec![
+                ThrottleDecision::Pass { suppressed: 0 },
+                ThrottleDecision::Pass { suppressed: 0 },
+                ThrottleDecision::Suppress,
+                ThrottleDecision::Suppress,
+                ThrottleDecision::Pass { suppressed: 2 },
+                ThrottleDecision::Suppress,
+            ]
+        );
+    }
+}
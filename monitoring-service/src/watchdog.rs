@@ -0,0 +1,286 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use internal_prelude::library_prelude::*;
+
+use crate::{
+    handle::MonitorState, runner::PollingContext, CommandSpec, Event, FiredEvent, MonitorEvent,
+    MonitorId, Severity, Stall, Values,
+};
+
+/// Notices polling loops that stopped coming around, like a hanging polling function or a dead thread.
+///
+/// A running monitor whose loop has not ticked for stall_factor times its interval fires a Stalled
+/// event once, with the `critical` severity. Paused monitors are not watched.
+/// The actions of Stalled events run without waiting for the concurrency limits, which a stalled poll may be holding.
+pub struct Watchdog {
+    stall_factor:   u32,
+    check_interval: Duration,
+    heartbeat:      Option<Heartbeat>,
+}
+
+impl Watchdog {
+    pub fn new(stall_factor: u32) -> Self {
+        Watchdog {
+            stall_factor:   stall_factor.max(1),
+            check_interval: Duration::from_secs(1),
+            heartbeat:      None,
+        }
+    }
+
+    /// How often the polling loops are checked, defaults to every second.
+    pub fn check_interval(mut self, check_interval: Duration) -> Self {
+        self.check_interval = check_interval;
+        self
+    }
+
+    /// Send heartbeats as long as no polling loop is stalled, so their absence can be noticed from outside.
+    pub fn heartbeat(mut self, heartbeat: Heartbeat) -> Self {
+        self.heartbeat = Some(heartbeat);
+        self
+    }
+}
+
+/// A dead man's switch, written to a file and/or sent by running a command on every beat.
+pub struct Heartbeat {
+    interval: Duration,
+    file:     Option<PathBuf>,
+    command:  Option<CommandSpec>,
+}
+
+impl Heartbeat {
+    pub fn new(interval: Duration) -> Self {
+        Heartbeat {
+            interval,
+            file: None,
+            command: None,
+        }
+    }
+
+    /// Write the time of the beat to the file, in seconds since the epoch.
+    pub fn file(mut self, file: impl Into<PathBuf>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    /// Run the command on every beat, like `curl -fsS https://hc-ping.com/<uuid>`.
+    pub fn command(mut self, command: CommandSpec) -> Self {
+        self.command = Some(command);
+        self
+    }
+
+    fn beat(&self, at: SystemTime) -> Result<()> {
+        if let Some(file) = &self.file {
+            // Written aside and renamed so readers never see a partial file
+            let seconds = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            let temporary = file.with_extension("tmp");
+            fs::write(&temporary, format!("{}\n", seconds))?;
+            fs::rename(&temporary, file)?;
+        }
+        if let Some(command) = &self.command {
+            let output = command.run()?;
+            if output.exit_code != Some(0) {
+                return Err(crate::CommandError::Failed {
+                    command:   command.to_string(),
+                    exit_code: output.exit_code,
+                    stderr:    output.stderr.trim().to_string(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the watchdog process remembers between checks.
+pub(crate) struct WatchdogState {
+    watchdog:  Watchdog,
+    stalled:   BTreeSet<MonitorId>,
+    last_beat: Option<SystemTime>,
+}
+
+impl WatchdogState {
+    pub(crate) fn new(watchdog: Watchdog) -> Self {
+        WatchdogState {
+            watchdog,
+            stalled: BTreeSet::new(),
+            last_beat: None,
+        }
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        self.watchdog.check_interval
+    }
+
+    /// Find newly stalled polling loops and beat if none are stalled, returns the Stalled events to fire.
+    pub(crate) fn check<E: Event>(&mut self, context: &PollingContext<E>) -> Vec<FiredEvent<E>> {
+        let now = context.clock.system_now();
+        let mut stalls = Vec::new();
+        let mut stalled = BTreeSet::new();
+        for status in context.inner.lock().monitors.values() {
+            let last_tick = match status.last_tick {
+                Some(last_tick) if status.state == MonitorState::Running => last_tick,
+                _ => continue,
+            };
+            let stalled_for = now.duration_since(last_tick).unwrap_or_default();
+            if stalled_for <= status.schedule.interval * self.watchdog.stall_factor {
+                continue;
+            }
+
+            stalled.insert(status.id.clone());
+            if !self.stalled.contains(&status.id) {
+                stalls.push((status.id.clone(), stalled_for, status.schedule.interval));
+            }
+        }
+        for id in self.stalled.difference(&stalled) {
+            log::info!("The polling loop of {} is ticking again", id);
+        }
+        self.stalled = stalled;
+
+        let mut events = Vec::with_capacity(stalls.len());
+        for (id, stalled_for, interval) in stalls {
+            log::error!(
+                "The polling loop of {} has not ticked for {:?}, its interval is {:?}",
+                id,
+                stalled_for,
+                interval
+            );
+            let mut values = Values::new();
+            values.insert(
+                "severity".to_string(),
+                Severity::Critical.to_string().into(),
+            );
+            values.insert("stalled_for".to_string(), stalled_for.as_secs().into());
+            events.push(FiredEvent::new(
+                MonitorEvent::Stalled(Stall {
+                    stalled_for,
+                    interval,
+                }),
+                &id,
+                &context.host,
+                values,
+            ));
+        }
+
+        self.beat(now);
+        events
+    }
+
+    fn beat(&mut self, now: SystemTime) {
+        let heartbeat = match &self.watchdog.heartbeat {
+            Some(heartbeat) => heartbeat,
+            None => return,
+        };
+        if !self.stalled.is_empty() {
+            log::warn!(
+                "Holding back the heartbeat, stalled polling loops: {}",
+                self.stalled.iter().join(", ")
+            );
+            return;
+        }
+        let due = self.last_beat.is_none_or(|last_beat| {
+            now.duration_since(last_beat).unwrap_or_default() >= heartbeat.interval
+        });
+        if !due {
+            return;
+        }
+
+        self.last_beat = Some(now);
+        if let Err(err) = heartbeat.beat(now) {
+            log::error!("Sending the heartbeat failed: {:#}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        test_support::{ActionRecorder, MonitorHarness, ScriptedPolling},
+        EventKey, PollingMonitor, PollingSchedule, VirtualClock,
+    };
+
+    #[test]
+    fn test_stalled_monitor_stops_the_heartbeat() {
+        let directory = std::env::temp_dir().join(format!("watchdog-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let heartbeat_file = directory.join("heartbeat");
+        let beats_file = directory.join("beats");
+        let beats = || {
+            fs::read_to_string(&beats_file)
+                .map(|beats| beats.lines().count())
+                .unwrap_or_default()
+        };
+
+        let clock = VirtualClock::new();
+        let recorder = ActionRecorder::new(&clock);
+        let every_5s = *PollingSchedule::default().interval(Duration::from_secs(5));
+
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .schedule_named_polling(
+                "healthy",
+                every_5s,
+                ScriptedPolling::new().event("ok").into_polling_func(),
+            )
+            .schedule_named_polling(
+                "hanging",
+                every_5s,
+                ScriptedPolling::new().event("ok").into_polling_func(),
+            )
+            .register_action(EventKey::Stalled, recorder.action("page"))
+            .watchdog(
+                Watchdog::new(3)
+                    .check_interval(Duration::from_secs(5))
+                    .heartbeat(
+                        Heartbeat::new(Duration::from_secs(1))
+                            .file(&heartbeat_file)
+                            .command(
+                                CommandSpec::new("sh")
+                                    .args(&[
+                                        "-c",
+                                        "echo beat >> \"$0\"",
+                                        beats_file.to_str().unwrap(),
+                                    ])
+                                    .clone(),
+                            ),
+                    ),
+            );
+        let mut harness = MonitorHarness::new(monitor, &clock);
+        harness.hang("hanging");
+
+        // Not ticking for more than 3 intervals is a stall, noticed by the check at 20s
+        harness.advance(Duration::from_secs(15));
+        assert!(recorder.invocations().is_empty());
+        assert_eq!(beats(), 4);
+        harness.advance(Duration::from_secs(15));
+        assert_eq!(
+            recorder.timeline(),
+            vec![(20, "page".to_string(), EventKey::Stalled)]
+        );
+        let stall = &recorder.invocations()[0].event;
+        assert_eq!(stall.payload.monitor_id, MonitorId::new("hanging"));
+        assert_eq!(stall.value("severity"), Some(&"critical".into()));
+        assert_eq!(stall.value("stalled_for"), Some(&20.into()));
+        let seconds: u64 = fs::read_to_string(&heartbeat_file)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        assert!(seconds > 0);
+        // No beats while stalled
+        assert_eq!(beats(), 4);
+
+        // Ticking again restarts the heartbeat without firing another Stalled event
+        harness.release("hanging").advance(Duration::from_secs(10));
+        assert_eq!(beats(), 6);
+        assert_eq!(recorder.invocations().len(), 1);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
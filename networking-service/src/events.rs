@@ -0,0 +1,152 @@
+use std::net::IpAddr;
+
+use event_bus::{BusEvent, Category, Publish};
+
+use crate::network_interfaces::NetInterface;
+
+/// A change of the network of the machine, published on the EventBus as `network.<subject>.<change>`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum NetworkEvent {
+    InterfaceAdded(NetInterface),
+    InterfaceRemoved(NetInterface),
+    AddressesChanged {
+        name:   String,
+        before: Vec<IpAddr>,
+        after:  Vec<IpAddr>,
+    },
+    PublicIpChanged {
+        before: Option<IpAddr>,
+        after:  IpAddr,
+    },
+}
+
+fn addresses(addresses: &[IpAddr]) -> Vec<String> {
+    addresses.iter().map(IpAddr::to_string).collect()
+}
+
+impl Publish for NetworkEvent {
+    fn to_bus_event(&self) -> BusEvent {
+        match self {
+            NetworkEvent::InterfaceAdded(interface) => {
+                BusEvent::new(Category::Network, &["interface", &interface.name, "added"])
+                    .value("interface", interface.name.as_str())
+                    .value("addresses", addresses(&interface.addresses))
+            }
+            NetworkEvent::InterfaceRemoved(interface) => BusEvent::new(
+                Category::Network,
+                &["interface", &interface.name, "removed"],
+            )
+            .value("interface", interface.name.as_str())
+            .value("addresses", addresses(&interface.addresses)),
+            NetworkEvent::AddressesChanged {
+                name,
+                before,
+                after,
+            } => BusEvent::new(Category::Network, &["interface", name, "addresses_changed"])
+                .value("interface", name.as_str())
+                .value("before", addresses(before))
+                .value("after", addresses(after)),
+            NetworkEvent::PublicIpChanged { before, after } => {
+                BusEvent::new(Category::Network, &["public_ip", "changed"])
+                    .value("before", before.map(|ip| ip.to_string()))
+                    .value("after", after.to_string())
+            }
+        }
+    }
+}
+
+/// The NetworkEvents between two listings of the network interfaces, in the order of the interface names.
+pub fn interface_changes(before: &[NetInterface], after: &[NetInterface]) -> Vec<NetworkEvent> {
+    let mut changes = Vec::new();
+    for interface in before {
+        match after.iter().find(|other| other.name == interface.name) {
+            None => changes.push(NetworkEvent::InterfaceRemoved(interface.clone())),
+            Some(other) => {
+                let mut before_addresses = interface.addresses.clone();
+                let mut after_addresses = other.addresses.clone();
+                before_addresses.sort();
+                after_addresses.sort();
+                if before_addresses != after_addresses {
+                    changes.push(NetworkEvent::AddressesChanged {
+                        name:   interface.name.clone(),
+                        before: before_addresses,
+                        after:  after_addresses,
+                    });
+                }
+            }
+        }
+    }
+    for interface in after {
+        if !before.iter().any(|other| other.name == interface.name) {
+            changes.push(NetworkEvent::InterfaceAdded(interface.clone()));
+        }
+    }
+    changes.sort_by(|a, b| Ord::cmp(name(a), name(b)));
+    changes
+}
+
+fn name(event: &NetworkEvent) -> &str {
+    match event {
+        NetworkEvent::InterfaceAdded(interface) | NetworkEvent::InterfaceRemoved(interface) => {
+            &interface.name
+        }
+        NetworkEvent::AddressesChanged { name, .. } => name,
+        NetworkEvent::PublicIpChanged { .. } => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interface(name: &str, addresses: &[&str]) -> NetInterface {
+        NetInterface {
+            name:      name.to_string(),
+            addresses: addresses
+                .iter()
+                .map(|address| address.parse().unwrap())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_interface_changes() {
+        let before = vec![
+            interface("eth0", &["192.168.1.5"]),
+            interface("lo", &["127.0.0.1", "::1"]),
+            interface("wg0", &["10.0.0.2"]),
+        ];
+        let after = vec![
+            interface("eth0", &["192.168.1.7"]),
+            interface("lo", &["::1", "127.0.0.1"]),
+            interface("wg1", &["10.0.1.2"]),
+        ];
+
+        let changes = interface_changes(&before, &after);
+        let topics: Vec<String> = changes
+            .iter()
+            .map(|change| change.to_bus_event().topic)
+            .collect();
+        assert_eq!(
+            topics,
+            vec![
+                "network.interface.eth0.addresses_changed",
+                "network.interface.wg0.removed",
+                "network.interface.wg1.added",
+            ]
+        );
+        assert_eq!(
+            changes[0].to_bus_event().get("after"),
+            Some(&vec!["192.168.1.7"].into())
+        );
+        assert!(interface_changes(&after, &after).is_empty());
+
+        let public_ip = NetworkEvent::PublicIpChanged {
+            before: None,
+            after:  "1.2.3.4".parse().unwrap(),
+        }
+        .to_bus_event();
+        assert_eq!(public_ip.topic, "network.public_ip.changed");
+        assert_eq!(public_ip.get("before"), Some(&().into()));
+    }
+}
@@ -1,2 +1,4 @@
+pub mod events;
 pub mod network_interfaces;
 pub mod public_ip;
+pub mod watcher;
@@ -153,7 +153,7 @@ veth60de6b9: flags=4163<UP,BROADCAST,RUNNING,MULTICAST>  mtu 1500
             stdout: IFCONFIG_OUTPUT.into(),
         };
         let real = IfConfig().parse_output(output).await.unwrap();
-        let expected = vec![
+        let expected = [
             (
                 "br-b83013461f0c",
                 vec!["172.23.0.1".parse::<IpAddr>().unwrap()],
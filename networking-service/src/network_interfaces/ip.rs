@@ -158,7 +158,7 @@ mod tests {
         };
 
         let real = Ip().parse_output(output).await.unwrap();
-        let expected = vec![
+        let expected = [
             (
                 "br-60984024090a",
                 vec!["172.18.0.1".parse::<IpAddr>().unwrap()],
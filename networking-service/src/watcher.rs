@@ -0,0 +1,180 @@
+use std::{net::IpAddr, time::Duration};
+
+use event_bus::EventBus;
+use internal_prelude::library_prelude::*;
+
+use crate::{
+    events::{interface_changes, NetworkEvent},
+    network_interfaces::{GetNetInterfaces, NetInterface},
+    public_ip::GetPublicIP,
+};
+
+/// Publishes the changes of the network interfaces and the public IP of the machine on the EventBus,
+/// by comparing every check with the previous one.
+///
+/// The first listing of the interfaces is only what later ones are compared with,
+/// the first public IP found is published with no IP before it.
+pub struct NetworkWatcher {
+    event_bus:       EventBus,
+    interfaces:      Box<dyn GetNetInterfaces + Send>,
+    public_ip:       Option<Box<dyn GetPublicIP + Send>>,
+    last_interfaces: Option<Vec<NetInterface>>,
+    last_public_ip:  Option<IpAddr>,
+}
+
+impl NetworkWatcher {
+    pub fn new(event_bus: EventBus, interfaces: impl GetNetInterfaces + Send + 'static) -> Self {
+        NetworkWatcher {
+            event_bus,
+            interfaces: Box::new(interfaces),
+            public_ip: None,
+            last_interfaces: None,
+            last_public_ip: None,
+        }
+    }
+
+    /// Also watch the public IP.
+    pub fn public_ip(mut self, public_ip: impl GetPublicIP + Send + 'static) -> Self {
+        self.public_ip = Some(Box::new(public_ip));
+        self
+    }
+
+    /// List the interfaces and look up the public IP, publishing what changed since the last check.
+    /// Returns the published events.
+    pub async fn check(&mut self) -> Result<Vec<NetworkEvent>> {
+        let interfaces = self.interfaces.get_network_interfaces().await?;
+        let mut changes = match &self.last_interfaces {
+            Some(last_interfaces) => interface_changes(last_interfaces, &interfaces),
+            None => Vec::new(),
+        };
+        self.last_interfaces = Some(interfaces);
+        self.publish(&changes);
+
+        if let Some(public_ip) = &self.public_ip {
+            let after = public_ip.get_public_ip().await?;
+            if self.last_public_ip != Some(after) {
+                let change = NetworkEvent::PublicIpChanged {
+                    before: self.last_public_ip.replace(after),
+                    after,
+                };
+                self.event_bus.publish(&change);
+                changes.push(change);
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Check every interval for as long as the future is polled, failed checks are logged.
+    pub async fn run(mut self, interval: Duration) {
+        loop {
+            if let Err(err) = self.check().await {
+                log::warn!("Checking the network failed: {:#}", err);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    fn publish(&self, changes: &[NetworkEvent]) {
+        for change in changes {
+            self.event_bus.publish(change);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_interfaces::GetNetInterfacesResult;
+    use crate::public_ip::GetPublicIPResult;
+    use std::collections::VecDeque;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Output};
+
+    /// Returns the listings in order, repeating the last one once they run out.
+    struct Listings(Mutex<VecDeque<Vec<NetInterface>>>);
+
+    #[async_trait]
+    impl GetNetInterfaces for Listings {
+        async fn get_network_interfaces(&self) -> GetNetInterfacesResult {
+            let mut listings = self.0.lock();
+            match listings.len() {
+                1 => Ok(listings[0].clone()),
+                _ => Ok(listings.pop_front().unwrap_or_default()),
+            }
+        }
+    }
+
+    /// Answers with the IPs in order, repeating the last one once they run out.
+    struct PublicIps(Mutex<VecDeque<&'static str>>);
+
+    impl GetPublicIP for PublicIps {}
+
+    #[async_trait]
+    impl CLIProgram<GetPublicIPResult> for PublicIps {
+        fn name(&self) -> &str {
+            "public-ips"
+        }
+
+        async fn call(&self) -> Result<Output> {
+            let mut ips = self.0.lock();
+            let ip = match ips.len() {
+                1 => ips[0],
+                _ => ips.pop_front().unwrap_or_default(),
+            };
+            Ok(Output {
+                status: ExitStatus::from_raw(0),
+                stdout: ip.into(),
+                stderr: Vec::new(),
+            })
+        }
+
+        async fn parse_output(&self, output: Output) -> GetPublicIPResult {
+            Ok(String::from_utf8(output.stdout)?.parse()?)
+        }
+    }
+
+    fn interface(name: &str, address: &str) -> NetInterface {
+        NetInterface {
+            name:      name.to_string(),
+            addresses: vec![address.parse().unwrap()],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let event_bus = EventBus::new();
+        let (_, published) = event_bus.channel("network.**").unwrap();
+        let listings = vec![
+            vec![interface("eth0", "192.168.1.5")],
+            vec![interface("eth0", "192.168.1.5")],
+            vec![
+                interface("eth0", "192.168.1.5"),
+                interface("wg0", "10.0.0.2"),
+            ],
+        ];
+        let mut watcher = NetworkWatcher::new(event_bus, Listings(Mutex::new(listings.into())))
+            .public_ip(PublicIps(Mutex::new(
+                vec!["1.2.3.4", "1.2.3.4", "5.6.7.8"].into(),
+            )));
+
+        let mut topics = Vec::new();
+        for _ in 0..4 {
+            watcher.check().await.unwrap();
+            topics.push(
+                published
+                    .try_iter()
+                    .map(|event| event.topic)
+                    .collect::<Vec<_>>(),
+            );
+        }
+        assert_eq!(
+            topics,
+            vec![
+                vec!["network.public_ip.changed"],
+                vec![],
+                vec!["network.interface.wg0.added", "network.public_ip.changed"],
+                vec![],
+            ]
+        );
+    }
+}
@@ -1,16 +1,98 @@
 pub mod smtpgmail;
 
+use event_bus::{BusEvent, EventBus};
 use lettre::transport::smtp::authentication::Credentials as LettreCredentials;
 
 use internal_prelude::library_prelude::*;
 
-pub struct Credentials(LettreCredentials);
+use crate::events::NotificationEvent;
 
+pub struct Credentials(#[allow(dead_code)] LettreCredentials);
+
+#[derive(PartialEq, Eq, Clone, Debug)]
 pub struct Notification {
-    message: String,
+    pub subject: String,
+    pub message: String,
+}
+
+impl Notification {
+    pub fn new(subject: &str, message: &str) -> Self {
+        Notification {
+            subject: subject.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    /// A notification of an event of the EventBus, its topic as the subject and a line per value as the message.
+    pub fn of_bus_event(event: &BusEvent) -> Self {
+        let mut message = format!("source: {}\n", event.source);
+        for (name, value) in &event.values {
+            message.push_str(&format!("{}: {}\n", name, value));
+        }
+        Notification::new(&event.topic, &message)
+    }
 }
 
 #[async_trait]
-pub trait Notify {
-    async fn notify(credentials: LettreCredentials, notification: Notification);
+pub trait Notify: Sync {
+    async fn notify(&self, notification: &Notification) -> Result<()>;
+}
+
+/// Email the notification and publish whether it was sent on the EventBus,
+/// as `notification.email.sent` or `notification.email.failed`.
+pub async fn notify_and_publish(
+    notifier: &dyn Notify,
+    notification: &Notification,
+    event_bus: &EventBus,
+) -> Result<()> {
+    let result = notifier.notify(notification).await;
+    let event = match &result {
+        Ok(()) => NotificationEvent::Sent {
+            channel: "email".to_string(),
+        },
+        Err(err) => NotificationEvent::Failed {
+            channel: "email".to_string(),
+            error:   format!("{:#}", err),
+        },
+    };
+    event_bus.publish(&event);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use event_bus::Category;
+    use internal_prelude::application_prelude::anyhow;
+
+    struct Failing;
+
+    #[async_trait]
+    impl Notify for Failing {
+        async fn notify(&self, _notification: &Notification) -> Result<()> {
+            Err(anyhow!("connection refused"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_notify_and_publish() {
+        let event_bus = EventBus::new();
+        let (_, published) = event_bus.channel("notification.**").unwrap();
+
+        let down = BusEvent::new(Category::Monitoring, &["uplink", "down"])
+            .source("server-1")
+            .value("exit_code", 1);
+        let notification = Notification::of_bus_event(&down);
+        assert_eq!(
+            notification,
+            Notification::new("monitoring.uplink.down", "source: server-1\nexit_code: 1\n")
+        );
+
+        assert!(notify_and_publish(&Failing, &notification, &event_bus)
+            .await
+            .is_err());
+        let failed = published.try_recv().unwrap();
+        assert_eq!(failed.topic, "notification.email.failed");
+        assert_eq!(failed.get("error"), Some(&"connection refused".into()));
+    }
 }
@@ -5,29 +5,38 @@ use crate::email::{Notification, Notify};
 
 use internal_prelude::library_prelude::*;
 
-pub struct SmtpGmail();
+/// Emails notifications from the Gmail address to itself.
+pub struct SmtpGmail {
+    mailbox:     Mailbox,
+    credentials: Credentials,
+}
+
+impl SmtpGmail {
+    /// Fails if the address is not a valid email address.
+    pub fn new(address: &str, password: &str) -> Result<Self> {
+        Ok(SmtpGmail {
+            mailbox:     address.parse()?,
+            credentials: Credentials::new(address.to_string(), password.to_string()),
+        })
+    }
+}
 
 #[async_trait]
 impl Notify for SmtpGmail {
-    async fn notify(credentials: Credentials, notification: Notification) {
-        let nmio: Mailbox = "".parse().unwrap();
-
+    async fn notify(&self, notification: &Notification) -> Result<()> {
         let msg = Message::builder()
-            .from(nmio.clone())
-            .to(nmio)
-            .subject("Noty notification")
-            .body(notification.message)
-            .unwrap();
-
-        let mailer = SmtpTransport::relay("smtp.gmail.com")
-            .unwrap()
-            .credentials(credentials)
+            .from(self.mailbox.clone())
+            .to(self.mailbox.clone())
+            .subject(notification.subject.as_str())
+            .body(notification.message.clone())?;
+
+        let mailer = SmtpTransport::relay("smtp.gmail.com")?
+            .credentials(self.credentials.clone())
             .build();
 
-        match mailer.send(&msg) {
-            Ok(r) => println!("Email sent successfully: {:?}", r),
-            Err(e) => panic!("Could not send email: {:?}", e),
-        }
+        let response = mailer.send(&msg)?;
+        log::debug!("Email sent successfully: {:?}", response);
+        Ok(())
     }
 }
 
@@ -37,13 +46,11 @@ mod test {
 
     #[tokio::test]
     async fn test() {
-        // let creds = Credentials::new("kristo.koert@gmail.com".into(), "".into());
-        // SmtpGmail::notify(
-        //     creds,
-        //     Notification {
-        //         message: "test".to_string(),
-        //     },
-        // )
-        // .await;
+        assert!(SmtpGmail::new("not an address", "").is_err());
+        // SmtpGmail::new("kristo.koert@gmail.com", "")
+        //     .unwrap()
+        //     .notify(&Notification::new("Noty notification", "test"))
+        //     .await
+        //     .unwrap();
     }
 }
@@ -0,0 +1,46 @@
+use event_bus::{BusEvent, Category, Publish};
+
+/// The outcome of sending a notification, published on the EventBus as `notification.<channel>.<outcome>`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum NotificationEvent {
+    Sent { channel: String },
+    Failed { channel: String, error: String },
+}
+
+impl Publish for NotificationEvent {
+    fn to_bus_event(&self) -> BusEvent {
+        match self {
+            NotificationEvent::Sent { channel } => {
+                BusEvent::new(Category::Notification, &[channel, "sent"])
+            }
+            NotificationEvent::Failed { channel, error } => {
+                BusEvent::new(Category::Notification, &[channel, "failed"])
+                    .value("error", error.as_str())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topics() {
+        let failed = NotificationEvent::Failed {
+            channel: "email".to_string(),
+            error:   "connection refused".to_string(),
+        }
+        .to_bus_event();
+        assert_eq!(failed.topic, "notification.email.failed");
+        assert_eq!(failed.get("error"), Some(&"connection refused".into()));
+        assert_eq!(
+            NotificationEvent::Sent {
+                channel: "email".to_string(),
+            }
+            .to_bus_event()
+            .topic,
+            "notification.email.sent"
+        );
+    }
+}
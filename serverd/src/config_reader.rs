@@ -1,30 +1,544 @@
+use event_bus::TopicPattern;
 use internal_prelude::library_prelude::*;
+use monitoring_service::{
+    parse_duration, CommandAction, CommandMonitor, CommandSpec, ConcurrencyLimits, Dependency,
+    Heartbeat, MaintenanceWindow, PollingMonitor, PollingSchedule, SilenceMatcher, Watchdog,
+    WhenParentFailing,
+};
+use notification_service::email::smtpgmail::SmtpGmail;
 use serde::Deserialize;
-use std::{fs::File, path::Path};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::{Path, PathBuf},
+};
 
 const CONFIG_PATH_FALLBACK: &str = "/etc/serverd.conf";
+const CONTROL_SOCKET_FALLBACK: &str = "/run/serverd.sock";
 
+#[derive(Deserialize, Debug, Default)]
+pub struct ServerdConfig {
+    #[serde(default)]
+    pub monitors:            Vec<MonitorConfig>,
+    #[serde(default)]
+    pub maintenance_windows: Vec<MaintenanceWindowConfig>,
+    /// Where the daemon listens for control requests, like the ones of the CLI.
+    pub control_socket:      Option<PathBuf>,
+    #[serde(default)]
+    pub subscriptions:       Vec<SubscriptionConfig>,
+    #[serde(default)]
+    pub concurrency:         ConcurrencyConfig,
+    /// Watches the polling loops and sends heartbeats, not watched if unset.
+    pub watchdog:            Option<WatchdogConfig>,
+    #[serde(default)]
+    pub network:             NetworkConfig,
+    /// Emails events of the event bus, nothing is emailed if unset.
+    pub email:               Option<EmailConfig>,
+}
+
+/// Runs a command on a schedule, the event is the one of its exit code or otherwise the fallback, like
+/// `{"id": "uplink", "interval": "30s", "command": ["ping", "-c1", "1.1.1.1"], "on_exit_code": {"0": "up"}, "otherwise": "down"}`.
+/// The events are published on the event bus as `monitoring.<id>.<event>`, subscriptions run commands for them.
 #[derive(Deserialize, Debug)]
-pub struct ServerdConfig {}
+pub struct MonitorConfig {
+    pub id:                  String,
+    pub command:             Vec<String>,
+    #[serde(default = "default_interval")]
+    pub interval:            String,
+    /// Kills the command if it runs longer, runs it for as long as it takes if unset.
+    pub timeout:             Option<String>,
+    #[serde(default)]
+    pub on_exit_code:        BTreeMap<i32, String>,
+    pub otherwise:           Option<String>,
+    /// Events of this monitor that make the events of the monitors depending on it meaningless,
+    /// besides failing to run the command.
+    #[serde(default)]
+    pub failing_on:          Vec<String>,
+    /// Ids of the monitors whose failure makes the events of this one meaningless.
+    #[serde(default)]
+    pub depends_on:          Vec<String>,
+    /// `suppress` or `annotate` the events while a monitor it depends on is failing, defaults to suppress.
+    #[serde(default)]
+    pub when_parent_failing: WhenParentFailing,
+    /// The concurrency group the polls count against.
+    pub group:               Option<String>,
+}
+
+fn default_interval() -> String {
+    "1m".to_string()
+}
+
+impl MonitorConfig {
+    fn to_command_monitor(&self) -> Result<CommandMonitor<String>> {
+        if self.on_exit_code.is_empty() && self.otherwise.is_none() {
+            return Err(ConfigError::NoEvents(self.id.clone()).into());
+        }
+        let mut command = command_spec(&self.command)
+            .ok_or_else(|| ConfigError::EmptyMonitorCommand(self.id.clone()))?;
+        if let Some(timeout) = &self.timeout {
+            command.timeout(parse_duration(timeout)?);
+        }
+
+        let mut monitor = CommandMonitor::new(command);
+        for (exit_code, event) in &self.on_exit_code {
+            monitor.on_exit_code(*exit_code, event.clone());
+        }
+        if let Some(otherwise) = &self.otherwise {
+            monitor.otherwise(otherwise.clone());
+        }
+        Ok(monitor)
+    }
+}
+
+/// Silences the matching events every week, like `{"name": "patching", "days": ["Sun"], "from": "02:00", "to": "04:00"}`.
+#[derive(Deserialize, Debug)]
+pub struct MaintenanceWindowConfig {
+    pub name:       String,
+    pub days:       Vec<String>,
+    pub from:       String,
+    pub to:         String,
+    /// Minutes east of UTC of the time zone of from and to, negative for west.
+    #[serde(default)]
+    pub utc_offset: i64,
+    #[serde(default)]
+    pub matcher:    SilenceMatcher,
+}
+
+/// How many polls and actions run at once, like `{"polls": 4, "groups": {"commands": 2}}`.
+/// Unset limits are unbounded.
+#[derive(Deserialize, Debug, Default)]
+pub struct ConcurrencyConfig {
+    pub polls:   Option<usize>,
+    pub actions: Option<usize>,
+    #[serde(default)]
+    pub groups:  BTreeMap<String, usize>,
+}
+
+impl ConcurrencyConfig {
+    pub fn to_limits(&self) -> ConcurrencyLimits {
+        let mut limits = ConcurrencyLimits::default();
+        if let Some(polls) = self.polls {
+            limits = limits.polls(polls);
+        }
+        if let Some(actions) = self.actions {
+            limits = limits.actions(actions);
+        }
+        for (group, limit) in &self.groups {
+            limits = limits.group(group, *limit);
+        }
+        limits
+    }
+}
+
+/// Fires Stalled events for polling loops that have not ticked for stall_factor times their interval,
+/// like `{"stall_factor": 3, "heartbeat": {"interval": "1m", "file": "/run/serverd.heartbeat"}}`.
+#[derive(Deserialize, Debug)]
+pub struct WatchdogConfig {
+    #[serde(default = "default_stall_factor")]
+    pub stall_factor:   u32,
+    /// How often the polling loops are checked, like `10s`, every second if unset.
+    pub check_interval: Option<String>,
+    pub heartbeat:      Option<HeartbeatConfig>,
+}
+
+fn default_stall_factor() -> u32 {
+    3
+}
+
+/// Beats while no polling loop is stalled, by writing the file and/or running the command,
+/// like `{"interval": "1m", "command": ["curl", "-fsS", "https://hc-ping.com/<uuid>"]}`.
+#[derive(Deserialize, Debug)]
+pub struct HeartbeatConfig {
+    pub interval: String,
+    pub file:     Option<PathBuf>,
+    #[serde(default)]
+    pub command:  Vec<String>,
+}
+
+impl WatchdogConfig {
+    pub fn to_watchdog(&self) -> Result<Watchdog> {
+        let mut watchdog = Watchdog::new(self.stall_factor);
+        if let Some(check_interval) = &self.check_interval {
+            watchdog = watchdog.check_interval(parse_duration(check_interval)?);
+        }
+        if let Some(heartbeat) = &self.heartbeat {
+            watchdog = watchdog.heartbeat(heartbeat.to_heartbeat()?);
+        }
+        Ok(watchdog)
+    }
+}
+
+impl HeartbeatConfig {
+    pub fn to_heartbeat(&self) -> Result<Heartbeat> {
+        let mut heartbeat = Heartbeat::new(parse_duration(&self.interval)?);
+        if let Some(file) = &self.file {
+            heartbeat = heartbeat.file(file);
+        }
+        if let Some(command) = command_spec(&self.command) {
+            heartbeat = heartbeat.command(command);
+        }
+        Ok(heartbeat)
+    }
+}
+
+/// The program and arguments of a command like `["logger", "{{interface}} removed"]`, None if it is empty.
+fn command_spec(command: &[String]) -> Option<CommandSpec> {
+    let (program, args) = command.split_first()?;
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    Some(CommandSpec::new(program).args(&args).clone())
+}
+
+/// Runs a command for the events on the event bus matching the topic pattern,
+/// like `{"pattern": "network.interface.*.removed", "command": ["logger", "{{interface}} removed"]}`.
+#[derive(Deserialize, Debug)]
+pub struct SubscriptionConfig {
+    pub pattern: String,
+    pub command: Vec<String>,
+}
+
+impl SubscriptionConfig {
+    /// Subscribe the command to the event bus of the PollingMonitor, fails if the pattern is invalid.
+    pub fn subscribe(&self, polling_monitor: &mut PollingMonitor<String>) -> Result<()> {
+        let command = command_spec(&self.command)
+            .ok_or_else(|| ConfigError::EmptyCommand(self.pattern.clone()))?;
+        polling_monitor.subscribe(
+            &self.pattern,
+            CommandAction::new(command).into_action_func(&self.command[0]),
+        )?;
+        Ok(())
+    }
+}
+
+/// Publishes the changes of the network interfaces, and of the public IP if enabled, on the event bus
+/// as `network.interface.<name>.<change>` and `network.public_ip.changed`, like `{"interval": "30s", "public_ip": true}`.
+#[derive(Deserialize, Debug)]
+pub struct NetworkConfig {
+    #[serde(default = "default_interval")]
+    pub interval:  String,
+    /// Looks the public IP up with dig.
+    #[serde(default)]
+    pub public_ip: bool,
+}
 
-impl Default for ServerdConfig {
+impl Default for NetworkConfig {
     fn default() -> Self {
-        ServerdConfig {}
+        NetworkConfig {
+            interval:  default_interval(),
+            public_ip: false,
+        }
     }
 }
 
-pub fn read_config(config_path: Option<&Path>) -> Result<ServerdConfig> {
-    if let Some(path) = config_path {
-        if path.exists() {
-            let file = File::open(path)?;
-            serde_json::from_reader(file)?
+/// Emails the events on the event bus matching the pattern through Gmail, from the address to itself,
+/// like `{"pattern": "monitoring.*.down", "address": "ops@gmail.com", "password": "<app password>"}`.
+/// Whether each email was sent is published as `notification.email.sent` or `notification.email.failed`.
+#[derive(Deserialize)]
+pub struct EmailConfig {
+    pub pattern:  String,
+    pub address:  String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for EmailConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmailConfig")
+            .field("pattern", &self.pattern)
+            .field("address", &self.address)
+            .finish()
+    }
+}
+
+impl EmailConfig {
+    /// Fails if the address is not a valid email address.
+    pub fn notifier(&self) -> Result<SmtpGmail> {
+        SmtpGmail::new(&self.address, &self.password)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("The subscription to '{0}' has no command.")]
+    EmptyCommand(String),
+    #[error("Monitor {0} has no command.")]
+    EmptyMonitorCommand(String),
+    #[error("Monitor {0} has no events, set on_exit_code or otherwise.")]
+    NoEvents(String),
+}
+
+impl MaintenanceWindowConfig {
+    pub fn to_maintenance_window(&self) -> Result<MaintenanceWindow> {
+        let days = self
+            .days
+            .iter()
+            .map(|day| day.parse())
+            .collect::<Result<_, _>>()?;
+        let window = MaintenanceWindow::weekly(
+            &self.name,
+            self.matcher.clone(),
+            days,
+            &self.from,
+            &self.to,
+        )?
+        .utc_offset(self.utc_offset);
+        Ok(window)
+    }
+}
+
+impl ServerdConfig {
+    pub fn control_socket(&self) -> PathBuf {
+        self.control_socket
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(CONTROL_SOCKET_FALLBACK))
+    }
+
+    pub fn maintenance_windows(&self) -> Result<Vec<MaintenanceWindow>> {
+        self.maintenance_windows
+            .iter()
+            .map(MaintenanceWindowConfig::to_maintenance_window)
+            .collect()
+    }
+
+    /// The PollingMonitor running the monitors and the commands of the subscriptions,
+    /// with the maintenance windows, concurrency limits and watchdog.
+    pub fn polling_monitor(&self) -> Result<PollingMonitor<String>> {
+        let mut polling_monitor = PollingMonitor::new();
+        polling_monitor.concurrency_limits(self.concurrency.to_limits());
+        for window in self.maintenance_windows()? {
+            polling_monitor.maintenance_window(window);
+        }
+        if let Some(watchdog) = &self.watchdog {
+            polling_monitor.watchdog(watchdog.to_watchdog()?);
+        }
+        for subscription in &self.subscriptions {
+            subscription.subscribe(&mut polling_monitor)?;
         }
+
+        for monitor in &self.monitors {
+            polling_monitor.schedule_named_polling(
+                monitor.id.as_str(),
+                *PollingSchedule::default().interval(parse_duration(&monitor.interval)?),
+                monitor.to_command_monitor()?.into_polling_func(),
+            );
+        }
+        for monitor in &self.monitors {
+            for parent in &monitor.depends_on {
+                let mut dependency = Dependency::new(parent.as_str());
+                let failing_on = self
+                    .monitors
+                    .iter()
+                    .find(|known| known.id == *parent)
+                    .map(|parent| parent.failing_on.as_slice())
+                    .unwrap_or_default();
+                for event in failing_on {
+                    dependency = dependency.failing_on(event.clone());
+                }
+                polling_monitor.depends_on(monitor.id.as_str(), dependency)?;
+            }
+            polling_monitor
+                .when_parent_failing(monitor.id.as_str(), monitor.when_parent_failing)?;
+            if let Some(group) = &monitor.group {
+                polling_monitor.group(monitor.id.as_str(), group)?;
+            }
+        }
+        Ok(polling_monitor)
     }
 
-    if Path::new(CONFIG_PATH_FALLBACK).exists() {
-        let file = File::open(CONFIG_PATH_FALLBACK)?;
-        serde_json::from_reader(file)?
+    /// Make sure the monitors are valid, only depend on configured monitors and the dependencies form no cycles,
+    /// and that the maintenance windows, subscriptions, watchdog, network watching and email are valid.
+    pub fn validate(&self) -> Result<()> {
+        parse_duration(&self.network.interval)?;
+        if let Some(email) = &self.email {
+            email.pattern.parse::<TopicPattern>()?;
+            email.notifier()?;
+        }
+        self.polling_monitor()?.validate()
     }
+}
 
-    Ok(ServerdConfig::default())
+pub fn read_config(config_path: Option<&Path>) -> Result<ServerdConfig> {
+    let config_path = config_path
+        .filter(|path| path.exists())
+        .or_else(|| Some(Path::new(CONFIG_PATH_FALLBACK)).filter(|path| path.exists()));
+
+    let config: ServerdConfig = match config_path {
+        Some(path) => serde_json::from_reader(File::open(path)?)?,
+        None => ServerdConfig::default(),
+    };
+    config.validate()?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use monitoring_service::MonitorId;
+
+    #[test]
+    fn test_validate_dependencies() {
+        let config: ServerdConfig = serde_json::from_str(
+            r#"{"monitors": [
+                {"id": "uplink", "command": ["true"], "otherwise": "up"},
+                {"id": "public_ip", "command": ["true"], "otherwise": "up", "depends_on": ["uplink"]},
+                {"id": "http", "command": ["true"], "otherwise": "up", "depends_on": ["public_ip"]}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+
+        let config: ServerdConfig = serde_json::from_str(
+            r#"{"monitors": [
+                {"id": "uplink", "command": ["true"], "otherwise": "up", "depends_on": ["http"]},
+                {"id": "public_ip", "command": ["true"], "otherwise": "up", "depends_on": ["uplink"]},
+                {"id": "http", "command": ["true"], "otherwise": "up", "depends_on": ["public_ip"]}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "Monitor dependencies form a cycle: http -> public_ip -> uplink -> http."
+        );
+
+        let config: ServerdConfig = serde_json::from_str(
+            r#"{"monitors": [{"id": "http", "command": ["true"], "otherwise": "up", "depends_on": ["dns"]}]}"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_monitors() {
+        let config: ServerdConfig = serde_json::from_str(
+            r#"{"monitors": [
+                {"id": "uplink", "interval": "30s", "command": ["ping", "-c1", "192.0.2.1"],
+                 "on_exit_code": {"0": "up"}, "otherwise": "down", "failing_on": ["down"]},
+                {"id": "http", "command": ["curl", "-fs", "http://localhost"], "on_exit_code": {"0": "up", "7": "refused"},
+                 "depends_on": ["uplink"], "when_parent_failing": "annotate", "group": "commands"}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.monitors[1].interval, "1m");
+        assert_eq!(
+            config.monitors[1].when_parent_failing,
+            WhenParentFailing::Annotate
+        );
+
+        let config: ServerdConfig =
+            serde_json::from_str(r#"{"monitors": [{"id": "http", "command": ["true"]}]}"#).unwrap();
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "Monitor http has no events, set on_exit_code or otherwise."
+        );
+        let config: ServerdConfig = serde_json::from_str(
+            r#"{"monitors": [{"id": "http", "command": ["true"], "otherwise": "up", "interval": "often"}]}"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_maintenance_windows() {
+        let config: ServerdConfig = serde_json::from_str(
+            r#"{"maintenance_windows": [
+                {"name": "patching", "days": ["Sun"], "from": "02:00", "to": "04:00", "utc_offset": 120,
+                 "matcher": {"monitor": "http"}}
+            ]}"#,
+        )
+        .unwrap();
+        let windows = config.maintenance_windows().unwrap();
+        assert_eq!(windows[0].duration.as_secs(), 2 * 60 * 60);
+        assert_eq!(windows[0].utc_offset, 120);
+        assert_eq!(windows[0].matcher.monitor, Some(MonitorId::new("http")));
+
+        let config: ServerdConfig = serde_json::from_str(
+            r#"{"maintenance_windows": [{"name": "patching", "days": ["Sun"], "from": "2am", "to": "4am"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "Invalid time of day '2am', expected HH:MM."
+        );
+    }
+
+    #[test]
+    fn test_subscriptions() {
+        let config: ServerdConfig = serde_json::from_str(
+            r#"{"subscriptions": [
+                {"pattern": "network.interface.*.removed", "command": ["logger", "{{interface}} removed"]},
+                {"pattern": "monitoring.**", "command": ["true"]}
+            ]}"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+
+        let config: ServerdConfig = serde_json::from_str(
+            r#"{"subscriptions": [{"pattern": "network..removed", "command": ["true"]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "Invalid topic pattern 'network..removed'."
+        );
+
+        let config: ServerdConfig =
+            serde_json::from_str(r#"{"subscriptions": [{"pattern": "**", "command": []}]}"#)
+                .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_network_and_email() {
+        let config: ServerdConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.network.interval, "1m");
+        assert!(!config.network.public_ip);
+        assert!(config.email.is_none());
+
+        let config: ServerdConfig = serde_json::from_str(
+            r#"{"network": {"interval": "30s", "public_ip": true},
+                "email": {"pattern": "monitoring.*.down", "address": "ops@example.com", "password": "secret"}}"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert!(!format!("{:?}", config.email).contains("secret"));
+
+        let config: ServerdConfig = serde_json::from_str(
+            r#"{"email": {"pattern": "monitoring.*.down", "address": "ops", "password": ""}}"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+        let config: ServerdConfig =
+            serde_json::from_str(r#"{"network": {"interval": "often"}}"#).unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_concurrency() {
+        let config: ServerdConfig =
+            serde_json::from_str(r#"{"concurrency": {"polls": 4, "groups": {"commands": 2}}}"#)
+                .unwrap();
+        assert_eq!(
+            config.concurrency.to_limits(),
+            ConcurrencyLimits::default().polls(4).group("commands", 2)
+        );
+        assert_eq!(
+            ServerdConfig::default().concurrency.to_limits(),
+            ConcurrencyLimits::default()
+        );
+    }
+
+    #[test]
+    fn test_watchdog() {
+        let config: ServerdConfig = serde_json::from_str(
+            r#"{"watchdog": {"heartbeat": {"interval": "1m", "file": "/run/serverd.heartbeat"}}}"#,
+        )
+        .unwrap();
+        let watchdog = config.watchdog.as_ref().unwrap();
+        assert_eq!(watchdog.stall_factor, 3);
+        assert!(config.validate().is_ok());
+
+        let config: ServerdConfig =
+            serde_json::from_str(r#"{"watchdog": {"heartbeat": {"interval": "often"}}}"#).unwrap();
+        assert!(config.validate().is_err());
+        assert!(ServerdConfig::default().watchdog.is_none());
+    }
 }
@@ -0,0 +1,234 @@
+//! The control socket of the daemon, a Unix socket taking one JSON request per line
+//! and answering each with one JSON response per line.
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::spawn,
+    time::{Duration, SystemTime},
+};
+
+use internal_prelude::{application_prelude::anyhow, library_prelude::*};
+use monitoring_service::{
+    Event, IncidentId, PollingMonitorHandle, Silence, SilenceId, SilenceMatcher,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// How long a connection may go without sending a request before it is closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// Silence the matching events from now on for the duration.
+    Silence {
+        #[serde(default)]
+        matcher:    SilenceMatcher,
+        duration:   Duration,
+        #[serde(default)]
+        reason:     String,
+        #[serde(default)]
+        created_by: String,
+    },
+    Unsilence {
+        id: SilenceId,
+    },
+    Silences,
+    Acknowledge {
+        incident: IncidentId,
+        by:       String,
+    },
+    Incidents,
+    /// The state and stats of every monitor and action, and how busy the concurrency limits are.
+    Status,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlResponse {
+    Ok(Value),
+    Error(String),
+}
+
+pub struct ControlServer {
+    listener: UnixListener,
+    path:     PathBuf,
+}
+
+impl ControlServer {
+    /// Listen on the socket, replacing a stale socket left behind by a daemon that is no longer running.
+    pub fn bind(path: &Path) -> Result<Self> {
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(anyhow!(
+                    "Another daemon is listening on {}.",
+                    path.display()
+                ));
+            }
+            fs::remove_file(path)?;
+        }
+
+        Ok(ControlServer {
+            listener: UnixListener::bind(path)?,
+            path:     path.to_path_buf(),
+        })
+    }
+
+    /// Answer requests with the running PollingMonitor until the socket fails, each connection in its own thread.
+    /// Connections idle for longer than IDLE_TIMEOUT are closed.
+    pub fn serve<E: Event>(&self, handle: &Arc<PollingMonitorHandle<E>>) -> Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+            let handle = Arc::clone(handle);
+            spawn(move || {
+                if let Err(err) = serve_connection(stream, &handle) {
+                    log::warn!("Control connection failed: {:#}", err);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn serve_connection<E: Event>(stream: UnixStream, handle: &PollingMonitorHandle<E>) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                log::debug!("Closing control connection idle for {:?}", IDLE_TIMEOUT);
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => match handle_request(request, handle) {
+                Ok(value) => ControlResponse::Ok(value),
+                Err(err) => ControlResponse::Error(format!("{:#}", err)),
+            },
+            Err(err) => ControlResponse::Error(format!("Invalid request: {}", err)),
+        };
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn handle_request<E: Event>(
+    request: ControlRequest,
+    handle: &PollingMonitorHandle<E>,
+) -> Result<Value> {
+    let value = match request {
+        ControlRequest::Silence {
+            matcher,
+            duration,
+            reason,
+            created_by,
+        } => {
+            let silence = Silence::new(matcher, SystemTime::now(), duration)
+                .reason(&reason)
+                .created_by(&created_by);
+            serde_json::to_value(handle.silence(silence))?
+        }
+        ControlRequest::Unsilence { id } => serde_json::to_value(handle.unsilence(id)?)?,
+        ControlRequest::Silences => serde_json::to_value(handle.silences())?,
+        ControlRequest::Acknowledge { incident, by } => {
+            serde_json::to_value(handle.acknowledge(incident, &by)?)?
+        }
+        ControlRequest::Incidents => serde_json::to_value(handle.incidents())?,
+        ControlRequest::Status => json!({
+            "monitors": handle.status(),
+            "actions": handle.action_stats(),
+            "concurrency": handle.concurrency(),
+        }),
+    };
+    Ok(value)
+}
+
+/// Send a request to the daemon listening on the socket, returns the value it answered with.
+pub fn request(path: &Path, request: &ControlRequest) -> Result<Value> {
+    let mut stream = UnixStream::connect(path)
+        .map_err(|err| anyhow!("Unable to connect to {}: {}", path.display(), err))?;
+    serde_json::to_writer(&mut stream, request)?;
+    stream.write_all(b"\n")?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    match serde_json::from_str(&line)? {
+        ControlResponse::Ok(value) => Ok(value),
+        ControlResponse::Error(message) => Err(anyhow!(message)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use monitoring_service::PollingMonitor;
+
+    #[derive(PartialEq, Eq, Hash, Clone, Debug)]
+    struct NoEvent;
+
+    impl Event for NoEvent {}
+
+    #[test]
+    fn test_silence_over_socket() {
+        let path = std::env::temp_dir().join(format!("serverd-test-{}.sock", std::process::id()));
+        let server = ControlServer::bind(&path).unwrap();
+        let handle = Arc::new(PollingMonitor::<NoEvent>::new().start().unwrap());
+        let server_handle = Arc::clone(&handle);
+        spawn(move || server.serve(&server_handle));
+
+        // A client that never sends a request doesn't hold up the others
+        let _idle = UnixStream::connect(&path).unwrap();
+
+        let id = request(
+            &path,
+            &ControlRequest::Silence {
+                matcher:    SilenceMatcher::default().monitor("http"),
+                duration:   Duration::from_secs(3600),
+                reason:     "deploying".to_string(),
+                created_by: "ops".to_string(),
+            },
+        )
+        .unwrap();
+        assert_eq!(id, Value::from(1));
+
+        let silences = request(&path, &ControlRequest::Silences).unwrap();
+        assert_eq!(silences[0]["reason"], Value::from("deploying"));
+        assert_eq!(
+            handle.silences()[0]
+                .matcher
+                .monitor
+                .as_ref()
+                .unwrap()
+                .as_str(),
+            "http"
+        );
+
+        request(&path, &ControlRequest::Unsilence { id: SilenceId(1) }).unwrap();
+        assert!(handle.silences().is_empty());
+        assert_eq!(
+            request(&path, &ControlRequest::Unsilence { id: SilenceId(1) })
+                .unwrap_err()
+                .to_string(),
+            "No silence with id 1."
+        );
+
+        let status = request(&path, &ControlRequest::Status).unwrap();
+        assert_eq!(status["monitors"], json!([]));
+        assert_eq!(status["concurrency"]["polls"], Value::Null);
+
+        assert!(ControlServer::bind(&path).is_err());
+    }
+}
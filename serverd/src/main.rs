@@ -1,9 +1,262 @@
 mod config_reader;
-// use monitoring_service::PollingMonitor;
-// use networking_service::{network_interfaces, public_ip};
+mod control;
 
-fn main() {
-    let _ = config_reader::read_config(None).expect("Unable to find serverd config");
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
 
-    // PollingMonitor::new(cmd_to_monitor, cmd_to_trigger)
+use clap::{App, Arg, ArgMatches};
+use event_bus::{Category, EventBus};
+use internal_prelude::application_prelude::*;
+use monitoring_service::{
+    parse_duration, IncidentId, PollingMonitorHandle, SilenceId, SilenceMatcher,
+};
+use networking_service::{
+    network_interfaces::getifaddrs::GetIfAddrs, public_ip::dig::Dig, watcher::NetworkWatcher,
+};
+use notification_service::email::{notify_and_publish, Notification};
+
+use config_reader::{EmailConfig, NetworkConfig, ServerdConfig};
+use control::{ControlRequest, ControlServer};
+
+fn cli() -> App<'static> {
+    App::new("serverd")
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .takes_value(true)
+                .about("Path of the config file, defaults to /etc/serverd.conf"),
+        )
+        .arg(
+            Arg::new("socket")
+                .long("socket")
+                .takes_value(true)
+                .about("Path of the control socket of the daemon, overrides the config"),
+        )
+        .subcommand(
+            App::new("silence")
+                .about("Silence matching events for a while")
+                .arg(Arg::new("host").long("host").takes_value(true))
+                .arg(Arg::new("monitor").long("monitor").takes_value(true))
+                .arg(
+                    Arg::new("event")
+                        .long("event")
+                        .takes_value(true)
+                        .about("Event pattern, * matches any number of characters"),
+                )
+                .arg(
+                    Arg::new("for")
+                        .long("for")
+                        .takes_value(true)
+                        .required(true)
+                        .about("How long to silence for, like 30m or 2h"),
+                )
+                .arg(Arg::new("reason").long("reason").takes_value(true)),
+        )
+        .subcommand(
+            App::new("unsilence")
+                .about("Lift a silence")
+                .arg(Arg::new("id").required(true).index(1)),
+        )
+        .subcommand(App::new("silences").about("List the silences that have not ended yet"))
+        .subcommand(
+            App::new("acknowledge")
+                .about("Acknowledge an incident, stopping its repeats")
+                .arg(Arg::new("incident").required(true).index(1))
+                .arg(Arg::new("by").long("by").takes_value(true)),
+        )
+        .subcommand(App::new("incidents").about("List the open and recently resolved incidents"))
+        .subcommand(
+            App::new("status")
+                .about("Show the state and stats of the monitors and actions of the daemon"),
+        )
+}
+
+fn silence_request(args: &ArgMatches) -> Result<ControlRequest> {
+    let mut matcher = SilenceMatcher::default();
+    if let Some(host) = args.value_of("host") {
+        matcher = matcher.host(host);
+    }
+    if let Some(monitor) = args.value_of("monitor") {
+        matcher = matcher.monitor(monitor);
+    }
+    if let Some(event) = args.value_of("event") {
+        matcher = matcher.event(event)?;
+    }
+
+    Ok(ControlRequest::Silence {
+        matcher,
+        duration: parse_duration(args.value_of("for").unwrap_or_default())?,
+        reason: args.value_of("reason").unwrap_or_default().to_string(),
+        created_by: current_user(),
+    })
+}
+
+fn current_user() -> String {
+    std::env::var("USER").unwrap_or_default()
+}
+
+/// Start the PollingMonitor of the config, publishing its events on the event bus and running the subscriptions to it.
+fn start_daemon(
+    config: &ServerdConfig,
+    event_bus: &EventBus,
+) -> Result<PollingMonitorHandle<String>> {
+    let mut monitor = config.polling_monitor()?;
+    monitor.event_bus(event_bus.clone());
+    monitor.start()
+}
+
+/// Publish the changes of the network on the event bus, checked in a thread of its own.
+fn watch_network(config: &NetworkConfig, event_bus: &EventBus) -> Result<()> {
+    let mut watcher = NetworkWatcher::new(event_bus.clone(), GetIfAddrs::default());
+    if config.public_ip {
+        watcher = watcher.public_ip(Dig::default());
+    }
+    let interval = parse_duration(&config.interval)?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    thread::Builder::new()
+        .name("network".to_string())
+        .spawn(move || runtime.block_on(watcher.run(interval)))?;
+    Ok(())
+}
+
+/// Email the matching events of the event bus, sent in a thread of its own so a slow mail server
+/// never holds up the publishers.
+fn send_emails(config: &EmailConfig, event_bus: &EventBus) -> Result<()> {
+    let notifier = config.notifier()?;
+    let (_, events) = event_bus.channel(&config.pattern)?;
+    let event_bus = event_bus.clone();
+    thread::Builder::new()
+        .name("email".to_string())
+        .spawn(move || {
+            // Whether an email was sent is not emailed itself
+            for event in events
+                .iter()
+                .filter(|event| event.category != Category::Notification)
+            {
+                let notification = Notification::of_bus_event(&event);
+                let sent = notify_and_publish(&notifier, &notification, &event_bus);
+                if let Err(err) = futures::executor::block_on(sent) {
+                    log::warn!("Emailing {} failed: {:#}", event.topic, err);
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Run the daemon and answer control requests until the socket fails.
+fn run_daemon(config: &ServerdConfig, socket: &Path) -> Result<()> {
+    let event_bus = EventBus::new();
+    if let Some(email) = &config.email {
+        send_emails(email, &event_bus)?;
+    }
+    let handle = Arc::new(start_daemon(config, &event_bus)?);
+    watch_network(&config.network, &event_bus)?;
+    ControlServer::bind(socket)?.serve(&handle)
+}
+
+fn main() -> Result<()> {
+    let matches = cli().get_matches();
+    let config = config_reader::read_config(matches.value_of("config").map(Path::new))
+        .expect("Unable to find serverd config");
+    let socket = matches
+        .value_of("socket")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| config.control_socket());
+
+    let request = match matches.subcommand() {
+        Some(("silence", args)) => silence_request(args)?,
+        Some(("unsilence", args)) => ControlRequest::Unsilence {
+            id: SilenceId(args.value_of("id").unwrap_or_default().parse()?),
+        },
+        Some(("silences", _)) => ControlRequest::Silences,
+        Some(("acknowledge", args)) => ControlRequest::Acknowledge {
+            incident: IncidentId(args.value_of("incident").unwrap_or_default().parse()?),
+            by:       args
+                .value_of("by")
+                .map_or_else(current_user, str::to_string),
+        },
+        Some(("incidents", _)) => ControlRequest::Incidents,
+        Some(("status", _)) => ControlRequest::Status,
+        _ => return run_daemon(&config, &socket),
+    };
+
+    let response = control::request(&socket, &request)?;
+    println!("{}", serde_json::to_string_pretty(&response)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use monitoring_service::{HistoryQuery, HistoryRecord};
+    use std::{
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    fn wait_until(what: &str, condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(
+                Instant::now() < deadline,
+                "timed out waiting until {}",
+                what
+            );
+            sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_daemon_runs_configured_monitors() {
+        let marker =
+            std::env::temp_dir().join(format!("serverd-uplink-down-{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let config: ServerdConfig = serde_json::from_value(serde_json::json!({
+            "monitors": [
+                {"id": "uplink", "interval": "1h", "command": ["false"],
+                 "on_exit_code": {"0": "up"}, "otherwise": "down", "failing_on": ["down"]},
+                {"id": "http", "interval": "1h", "command": ["true"], "on_exit_code": {"0": "up"},
+                 "depends_on": ["uplink"]}
+            ],
+            "subscriptions": [
+                {"pattern": "monitoring.uplink.down", "command": ["touch", marker]}
+            ]
+        }))
+        .unwrap();
+        config.validate().unwrap();
+
+        let handle = start_daemon(&config, &EventBus::new()).unwrap();
+        wait_until("both monitors polled", || {
+            handle.status().iter().all(|status| status.polls == 1)
+        });
+        let ids: Vec<String> = handle
+            .status()
+            .iter()
+            .map(|status| status.id.to_string())
+            .collect();
+        assert_eq!(ids, vec!["http", "uplink"]);
+
+        // The subscription ran for the uplink going down
+        wait_until("subscription ran", || marker.exists());
+        // Which suppresses the events of http, once uplink has been polled before it
+        handle.check_now("http").unwrap();
+        wait_until("http polled again", || {
+            handle.monitor_status("http").unwrap().polls == 2
+        });
+        let history = handle.history(HistoryQuery::default().monitor("http"));
+        match &history.last().unwrap().record {
+            HistoryRecord::Event { values, .. } => {
+                assert_eq!(values["suppressed_by"], serde_json::Value::from("uplink"))
+            }
+            record => panic!("expected the suppressed event, got {:?}", record),
+        }
+
+        handle.stop_and_join().unwrap();
+        std::fs::remove_file(&marker).unwrap();
+    }
 }
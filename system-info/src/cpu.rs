@@ -0,0 +1,236 @@
+use internal_prelude::library_prelude::*;
+use monitoring_service::Sample;
+
+use crate::{Probe, ProcError, ProcFs};
+
+const STAT: &str = "stat";
+
+/// Time spent by a CPU in each mode since boot, in clock ticks. Guest time is included in user and nice.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub struct CpuTimes {
+    pub user:    u64,
+    pub nice:    u64,
+    pub system:  u64,
+    pub idle:    u64,
+    pub iowait:  u64,
+    pub irq:     u64,
+    pub softirq: u64,
+    pub steal:   u64,
+}
+
+impl CpuTimes {
+    pub fn total(&self) -> u64 {
+        self.user
+            + self.nice
+            + self.system
+            + self.idle
+            + self.iowait
+            + self.irq
+            + self.softirq
+            + self.steal
+    }
+
+    /// Time not spent idle or waiting for I/O.
+    pub fn busy(&self) -> u64 {
+        self.total() - self.idle - self.iowait
+    }
+
+    /// The share of time spent in each mode since the earlier times, None if no time has passed.
+    /// Counters that went backwards, like after a CPU came back online, count as no time.
+    pub fn usage_since(&self, earlier: &CpuTimes) -> Option<CpuUsage> {
+        let delta = |now: u64, then: u64| now.saturating_sub(then) as f64;
+        let total = delta(self.total(), earlier.total());
+        if total == 0.0 {
+            return None;
+        }
+
+        let percent = |ticks: f64| ticks / total * 100.0;
+        Some(CpuUsage {
+            busy_percent:   percent(delta(self.busy(), earlier.busy())),
+            user_percent:   percent(delta(self.user + self.nice, earlier.user + earlier.nice)),
+            system_percent: percent(delta(
+                self.system + self.irq + self.softirq,
+                earlier.system + earlier.irq + earlier.softirq,
+            )),
+            iowait_percent: percent(delta(self.iowait, earlier.iowait)),
+            steal_percent:  percent(delta(self.steal, earlier.steal)),
+        })
+    }
+}
+
+/// The share of time a CPU spent in each mode between two readings of `/proc/stat`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct CpuUsage {
+    pub busy_percent:   f64,
+    pub user_percent:   f64,
+    /// Including the time spent serving interrupts.
+    pub system_percent: f64,
+    pub iowait_percent: f64,
+    pub steal_percent:  f64,
+}
+
+/// The CPU lines of `/proc/stat`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct CpuStat {
+    /// All CPUs together.
+    pub total: CpuTimes,
+    /// Each online CPU by its name, like `cpu0`, in the order of the file.
+    pub cpus:  Vec<(String, CpuTimes)>,
+}
+
+pub fn parse_stat(contents: &str) -> Result<CpuStat, ProcError> {
+    let mut total = None;
+    let mut cpus = Vec::new();
+    for line in contents.lines().filter(|line| line.starts_with("cpu")) {
+        let malformed = || ProcError::malformed(STAT, line);
+        let mut fields = line.split_whitespace();
+        let name = fields.next().ok_or_else(malformed)?;
+        let ticks = fields
+            .map(|field| field.parse::<u64>().map_err(|_| malformed()))
+            .collect::<Result<Vec<_>, _>>()?;
+        // Kernels before 2.6.33 have fewer columns, the ones they lack count as 0
+        if ticks.len() < 4 {
+            return Err(malformed());
+        }
+        let tick = |index: usize| ticks.get(index).copied().unwrap_or_default();
+        let times = CpuTimes {
+            user:    tick(0),
+            nice:    tick(1),
+            system:  tick(2),
+            idle:    tick(3),
+            iowait:  tick(4),
+            irq:     tick(5),
+            softirq: tick(6),
+            steal:   tick(7),
+        };
+
+        if name == "cpu" {
+            total = Some(times);
+        } else {
+            cpus.push((name.to_string(), times));
+        }
+    }
+
+    Ok(CpuStat {
+        total: total.ok_or_else(|| ProcError::missing(STAT, "cpu line"))?,
+        cpus,
+    })
+}
+
+/// Samples `cpu_busy_percent`, `cpu_user_percent`, `cpu_system_percent`, `cpu_iowait_percent`
+/// and `cpu_steal_percent` of all CPUs together since the previous poll, so the first poll has no samples.
+pub struct CpuProbe {
+    proc:    ProcFs,
+    per_cpu: bool,
+    last:    Option<CpuStat>,
+}
+
+impl CpuProbe {
+    pub fn new(proc: ProcFs) -> Self {
+        CpuProbe {
+            proc,
+            per_cpu: false,
+            last: None,
+        }
+    }
+
+    /// Also sample each CPU, labelled with its name like `cpu=cpu0`.
+    pub fn per_cpu(mut self) -> Self {
+        self.per_cpu = true;
+        self
+    }
+
+    fn samples(&self, earlier: &CpuStat, now: &CpuStat) -> Vec<Sample> {
+        let mut samples = Vec::new();
+        if let Some(usage) = now.total.usage_since(&earlier.total) {
+            samples.extend(usage_samples(&usage));
+        }
+        if !self.per_cpu {
+            return samples;
+        }
+
+        for (name, times) in &now.cpus {
+            let earlier = earlier
+                .cpus
+                .iter()
+                .find(|(earlier_name, _)| earlier_name == name);
+            if let Some(usage) = earlier.and_then(|(_, earlier)| times.usage_since(earlier)) {
+                samples.extend(
+                    usage_samples(&usage)
+                        .into_iter()
+                        .map(|sample| sample.label("cpu", name)),
+                );
+            }
+        }
+        samples
+    }
+}
+
+fn usage_samples(usage: &CpuUsage) -> Vec<Sample> {
+    vec![
+        Sample::new("cpu_busy_percent", usage.busy_percent),
+        Sample::new("cpu_user_percent", usage.user_percent),
+        Sample::new("cpu_system_percent", usage.system_percent),
+        Sample::new("cpu_iowait_percent", usage.iowait_percent),
+        Sample::new("cpu_steal_percent", usage.steal_percent),
+    ]
+}
+
+impl Probe for CpuProbe {
+    fn sample(&mut self) -> Result<Vec<Sample>> {
+        let stat = parse_stat(&self.proc.read(STAT)?)?;
+        let samples = match &self.last {
+            Some(last) => self.samples(last, &stat),
+            None => Vec::new(),
+        };
+        self.last = Some(stat);
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures;
+
+    #[test]
+    fn test_stat() {
+        let earlier = parse_stat(include_str!("../fixtures/proc/stat")).unwrap();
+        assert_eq!(earlier.total.idle, 3699176);
+        assert_eq!(earlier.cpus.len(), 4);
+        assert_eq!(earlier.cpus[3].0, "cpu3");
+        assert!(parse_stat("intr 1 2 3").is_err());
+        assert!(parse_stat("cpu 1 2 x 4").is_err());
+
+        let later = parse_stat(include_str!("../fixtures/stat-later")).unwrap();
+        let usage = later.total.usage_since(&earlier.total).unwrap();
+        assert_eq!(
+            usage,
+            CpuUsage {
+                busy_percent:   30.0,
+                user_percent:   20.0,
+                system_percent: 10.0,
+                iowait_percent: 10.0,
+                steal_percent:  0.0,
+            }
+        );
+        assert_eq!(earlier.total.usage_since(&earlier.total), None);
+
+        let probe = CpuProbe::new(fixtures()).per_cpu();
+        let samples = probe.samples(&earlier, &later);
+        assert_eq!(samples.len(), 5 * 5);
+        assert_eq!(
+            samples[5],
+            Sample::new("cpu_busy_percent", 50.0).label("cpu", "cpu0")
+        );
+        assert_eq!(
+            samples[10],
+            Sample::new("cpu_busy_percent", 0.0).label("cpu", "cpu1")
+        );
+
+        // Nothing to compare the first reading with, and no ticks pass between readings of the fixture
+        let mut probe = CpuProbe::new(fixtures());
+        assert!(probe.sample().unwrap().is_empty());
+        assert_eq!(probe.sample().unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,310 @@
+use std::time::{Duration, Instant};
+
+use internal_prelude::library_prelude::*;
+use monitoring_service::Sample;
+
+use crate::{Probe, ProcError, ProcFs};
+
+const DISKSTATS: &str = "diskstats";
+/// `/proc/diskstats` counts in 512 byte sectors, whatever the sector size of the device.
+const SECTOR_BYTES: u64 = 512;
+
+/// Devices without I/O of their own worth watching, or whose I/O is that of other devices:
+/// loop devices, ramdisks and device mapper targets like LVM volumes.
+pub const VIRTUAL_DEVICES: [&str; 3] = ["loop", "ram", "dm-"];
+
+/// The counters of a device in `/proc/diskstats` since boot.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct DiskStats {
+    pub device:        String,
+    pub reads:         u64,
+    pub read_sectors:  u64,
+    pub read_time:     Duration,
+    pub writes:        u64,
+    pub write_sectors: u64,
+    pub write_time:    Duration,
+    /// I/Os currently in flight, not a counter.
+    pub in_progress:   u64,
+    /// Time with at least one I/O in flight.
+    pub io_time:       Duration,
+}
+
+pub fn parse_diskstats(contents: &str) -> Result<Vec<DiskStats>, ProcError> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let malformed = || ProcError::malformed(DISKSTATS, line);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 14 {
+                return Err(malformed());
+            }
+            let counter = |index: usize| fields[index].parse::<u64>().map_err(|_| malformed());
+            let millis = |index: usize| counter(index).map(Duration::from_millis);
+            Ok(DiskStats {
+                device:        fields[2].to_string(),
+                reads:         counter(3)?,
+                read_sectors:  counter(5)?,
+                read_time:     millis(6)?,
+                writes:        counter(7)?,
+                write_sectors: counter(9)?,
+                write_time:    millis(10)?,
+                in_progress:   counter(11)?,
+                io_time:       millis(12)?,
+            })
+        })
+        .collect()
+}
+
+/// Whether the device is a partition of another device of the list, like `sda1` of `sda` or `nvme0n1p1` of `nvme0n1`.
+///
+/// The kernel separates the partition number with a `p` when the name of the disk ends in a digit,
+/// so `md10`, `nvme0n10` and `dm-10` are devices of their own rather than partitions of `md1`, `nvme0n1` and `dm-1`.
+fn is_partition(device: &str, devices: &[DiskStats]) -> bool {
+    let name = device.trim_end_matches(|c: char| c.is_ascii_digit());
+    if name.len() == device.len() {
+        return false;
+    }
+    let disk = match name.strip_suffix('p') {
+        Some(disk) if disk.ends_with(|c: char| c.is_ascii_digit()) => disk,
+        _ if name.ends_with(|c: char| c.is_ascii_alphabetic()) => name,
+        _ => return false,
+    };
+    devices.iter().any(|other| other.device == disk)
+}
+
+/// The I/O of a device between two readings of `/proc/diskstats`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct DiskIo {
+    pub read_bytes_per_second:  f64,
+    pub write_bytes_per_second: f64,
+    pub reads_per_second:       f64,
+    pub writes_per_second:      f64,
+    /// The share of the time the device was busy.
+    pub utilization_percent:    f64,
+    /// How long reads took on average, from being queued to being done, 0 without reads.
+    pub read_await_ms:          f64,
+    pub write_await_ms:         f64,
+}
+
+impl DiskStats {
+    /// The I/O since the earlier counters, None if no time has passed.
+    /// Counters that wrapped around, like the 32 bit ones of old kernels, count as no I/O.
+    pub fn io_since(&self, earlier: &DiskStats, elapsed: Duration) -> Option<DiskIo> {
+        let seconds = elapsed.as_secs_f64();
+        if seconds <= 0.0 {
+            return None;
+        }
+
+        let delta = |now: u64, then: u64| now.saturating_sub(then) as f64;
+        let millis =
+            |now: Duration, then: Duration| now.saturating_sub(then).as_secs_f64() * 1000.0;
+        let await_ms = |time: f64, count: f64| if count > 0.0 { time / count } else { 0.0 };
+        let reads = delta(self.reads, earlier.reads);
+        let writes = delta(self.writes, earlier.writes);
+        Some(DiskIo {
+            read_bytes_per_second:  delta(self.read_sectors, earlier.read_sectors)
+                * SECTOR_BYTES as f64
+                / seconds,
+            write_bytes_per_second: delta(self.write_sectors, earlier.write_sectors)
+                * SECTOR_BYTES as f64
+                / seconds,
+            reads_per_second:       reads / seconds,
+            writes_per_second:      writes / seconds,
+            utilization_percent:    (millis(self.io_time, earlier.io_time) / 10.0 / seconds)
+                .min(100.0),
+            read_await_ms:          await_ms(millis(self.read_time, earlier.read_time), reads),
+            write_await_ms:         await_ms(millis(self.write_time, earlier.write_time), writes),
+        })
+    }
+}
+
+/// Samples `disk_read_bytes_per_second`, `disk_write_bytes_per_second`, `disk_reads_per_second`,
+/// `disk_writes_per_second`, `disk_utilization_percent`, `disk_read_await_ms` and `disk_write_await_ms`
+/// of each disk since the previous poll, so the first poll has no samples, and `disk_ios_in_progress`
+/// at the time of the poll, labelled with the device like `device=sda`.
+///
+/// Partitions share the I/O of their disk and VIRTUAL_DEVICES that of other devices, so neither is sampled by default.
+pub struct DiskStatsProbe {
+    proc:            ProcFs,
+    partitions:      bool,
+    ignored_devices: Vec<String>,
+    last:            Option<(Instant, Vec<DiskStats>)>,
+}
+
+impl DiskStatsProbe {
+    pub fn new(proc: ProcFs) -> Self {
+        DiskStatsProbe {
+            proc,
+            partitions: false,
+            ignored_devices: VIRTUAL_DEVICES
+                .iter()
+                .map(|prefix| prefix.to_string())
+                .collect(),
+            last: None,
+        }
+    }
+
+    /// Also sample partitions, like `sda1`.
+    pub fn partitions(mut self) -> Self {
+        self.partitions = true;
+        self
+    }
+
+    /// The prefixes of the names of the devices to leave out, instead of VIRTUAL_DEVICES.
+    pub fn ignored_devices(mut self, prefixes: &[&str]) -> Self {
+        self.ignored_devices = prefixes.iter().map(|prefix| prefix.to_string()).collect();
+        self
+    }
+
+    fn is_sampled(&self, device: &str, devices: &[DiskStats]) -> bool {
+        !self
+            .ignored_devices
+            .iter()
+            .any(|prefix| device.starts_with(prefix.as_str()))
+            && (self.partitions || !is_partition(device, devices))
+    }
+
+    fn samples(&self, earlier: &[DiskStats], now: &[DiskStats], elapsed: Duration) -> Vec<Sample> {
+        let mut samples = Vec::new();
+        for disk in now.iter().filter(|disk| self.is_sampled(&disk.device, now)) {
+            let io = earlier
+                .iter()
+                .find(|earlier| earlier.device == disk.device)
+                .and_then(|earlier| disk.io_since(earlier, elapsed));
+            let io = match io {
+                Some(io) => io,
+                None => continue,
+            };
+
+            samples.extend(
+                vec![
+                    Sample::new("disk_read_bytes_per_second", io.read_bytes_per_second),
+                    Sample::new("disk_write_bytes_per_second", io.write_bytes_per_second),
+                    Sample::new("disk_reads_per_second", io.reads_per_second),
+                    Sample::new("disk_writes_per_second", io.writes_per_second),
+                    Sample::new("disk_utilization_percent", io.utilization_percent),
+                    Sample::new("disk_read_await_ms", io.read_await_ms),
+                    Sample::new("disk_write_await_ms", io.write_await_ms),
+                    Sample::new("disk_ios_in_progress", disk.in_progress as f64),
+                ]
+                .into_iter()
+                .map(|sample| sample.label("device", &disk.device)),
+            );
+        }
+        samples
+    }
+}
+
+impl Probe for DiskStatsProbe {
+    fn sample(&mut self) -> Result<Vec<Sample>> {
+        let now = Instant::now();
+        let stats = parse_diskstats(&self.proc.read(DISKSTATS)?)?;
+        let samples = match &self.last {
+            Some((at, last)) => self.samples(last, &stats, now.duration_since(*at)),
+            None => Vec::new(),
+        };
+        self.last = Some((now, stats));
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures;
+
+    fn devices(samples: &[Sample]) -> Vec<&str> {
+        samples
+            .iter()
+            .map(|sample| sample.labels["device"].as_str())
+            .dedup()
+            .collect()
+    }
+
+    #[test]
+    fn test_diskstats() {
+        let earlier = parse_diskstats(include_str!("../fixtures/proc/diskstats")).unwrap();
+        assert_eq!(earlier.len(), 9);
+        assert_eq!(earlier[3].device, "sda");
+        assert_eq!(earlier[3].write_time, Duration::from_millis(812004));
+        assert!(parse_diskstats("8 0 sda 1 2 3").is_err());
+        assert!(is_partition("nvme0n1p1", &earlier));
+        assert!(is_partition("sda2", &earlier));
+        assert!(!is_partition("nvme0n1", &earlier));
+
+        let devices_named = |names: &[&str]| -> Vec<DiskStats> {
+            names
+                .iter()
+                .map(|name| DiskStats {
+                    device: name.to_string(),
+                    ..DiskStats::default()
+                })
+                .collect()
+        };
+        let many = devices_named(&[
+            "md1",
+            "md10",
+            "md1p1",
+            "nvme0n1",
+            "nvme0n10",
+            "nvme0n1p10",
+            "dm-1",
+            "dm-10",
+            "sdp",
+            "sdp1",
+            "mmcblk0",
+            "mmcblk0p1",
+        ]);
+        let partitions: Vec<&str> = many
+            .iter()
+            .map(|disk| disk.device.as_str())
+            .filter(|device| is_partition(device, &many))
+            .collect();
+        assert_eq!(partitions, vec!["md1p1", "nvme0n1p10", "sdp1", "mmcblk0p1"]);
+
+        let later = parse_diskstats(include_str!("../fixtures/diskstats-later")).unwrap();
+        let io = later[3]
+            .io_since(&earlier[3], Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(
+            io,
+            DiskIo {
+                read_bytes_per_second:  1048576.0,
+                write_bytes_per_second: 2097152.0,
+                reads_per_second:       50.0,
+                writes_per_second:      100.0,
+                utilization_percent:    50.0,
+                read_await_ms:          2.0,
+                write_await_ms:         4.0,
+            }
+        );
+        assert_eq!(later[3].io_since(&earlier[3], Duration::from_secs(0)), None);
+
+        let probe = DiskStatsProbe::new(fixtures());
+        let samples = probe.samples(&earlier, &later, Duration::from_secs(10));
+        assert_eq!(devices(&samples), vec!["sda", "nvme0n1"]);
+        assert_eq!(
+            samples[4],
+            Sample::new("disk_utilization_percent", 50.0).label("device", "sda")
+        );
+        // Idle disks have no await times
+        assert_eq!(
+            samples[13],
+            Sample::new("disk_read_await_ms", 0.0).label("device", "nvme0n1")
+        );
+
+        let probe = DiskStatsProbe::new(fixtures())
+            .partitions()
+            .ignored_devices(&["loop", "ram"]);
+        let samples = probe.samples(&earlier, &later, Duration::from_secs(10));
+        assert_eq!(
+            devices(&samples),
+            vec!["sda", "sda1", "sda2", "nvme0n1", "nvme0n1p1", "dm-0"]
+        );
+
+        let mut probe = DiskStatsProbe::new(fixtures());
+        assert!(probe.sample().unwrap().is_empty());
+        assert_eq!(devices(&probe.sample().unwrap()), vec!["sda", "nvme0n1"]);
+    }
+}
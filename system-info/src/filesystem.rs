@@ -0,0 +1,448 @@
+use std::{
+    collections::BTreeSet,
+    path::Path,
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+use internal_prelude::library_prelude::*;
+use monitoring_service::Sample;
+
+use crate::{Probe, ProcError, ProcFs};
+
+const MOUNTS: &str = "self/mounts";
+
+/// How long to wait for the usage of a filesystem before skipping it, a stale network mount can take forever.
+pub const STATVFS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Filesystem types without space of their own to run out of, or whose usage is someone else's, like overlays and snaps.
+pub const PSEUDO_FILESYSTEMS: [&str; 25] = [
+    "autofs",
+    "binfmt_misc",
+    "bpf",
+    "cgroup",
+    "cgroup2",
+    "configfs",
+    "debugfs",
+    "devpts",
+    "devtmpfs",
+    "efivarfs",
+    "fuse.lxcfs",
+    "fusectl",
+    "hugetlbfs",
+    "mqueue",
+    "nsfs",
+    "overlay",
+    "proc",
+    "pstore",
+    "ramfs",
+    "rpc_pipefs",
+    "securityfs",
+    "selinuxfs",
+    "squashfs",
+    "sysfs",
+    "tracefs",
+];
+
+/// A line of `/proc/self/mounts`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Mount {
+    pub device:      String,
+    pub mount_point: String,
+    pub fs_type:     String,
+}
+
+pub fn parse_mounts(contents: &str) -> Result<Vec<Mount>, ProcError> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 3 {
+                return Err(ProcError::malformed(MOUNTS, line));
+            }
+            Ok(Mount {
+                device:      unescape(fields[0]),
+                mount_point: unescape(fields[1]),
+                fs_type:     fields[2].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Undo the octal escapes of whitespace and backslashes, like `\040` for a space.
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+    while let Some(index) = rest.find('\\') {
+        unescaped.push_str(&rest[..index]);
+        let escaped = rest.get(index + 1..index + 4);
+        match escaped.and_then(|octal| u8::from_str_radix(octal, 8).ok()) {
+            Some(byte) => {
+                unescaped.push(byte as char);
+                rest = &rest[index + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[derive(Error, Debug)]
+pub enum FilesystemError {
+    #[error("No answer within {0:?}, the filesystem may be unreachable.")]
+    TimedOut(Duration),
+    #[error("Still waiting for the answer to an earlier poll.")]
+    StillWaiting,
+}
+
+/// Space and inodes of a filesystem.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct FsUsage {
+    pub total_bytes:     u64,
+    pub free_bytes:      u64,
+    /// Free space usable by unprivileged users, without the blocks reserved for root.
+    pub available_bytes: u64,
+    /// 0 for filesystems without a fixed number of inodes, like btrfs.
+    pub total_inodes:    u64,
+    pub free_inodes:     u64,
+}
+
+impl FsUsage {
+    pub fn statvfs(path: &Path) -> Result<FsUsage> {
+        let stat = nix::sys::statvfs::statvfs(path)?;
+        let block = stat.fragment_size() as u64;
+        Ok(FsUsage {
+            total_bytes:     stat.blocks() as u64 * block,
+            free_bytes:      stat.blocks_free() as u64 * block,
+            available_bytes: stat.blocks_available() as u64 * block,
+            total_inodes:    stat.files() as u64,
+            free_inodes:     stat.files_free() as u64,
+        })
+    }
+
+    /// Used space as df reports it, the share of the space usable by unprivileged users.
+    pub fn used_percent(&self) -> f64 {
+        let used = self.total_bytes.saturating_sub(self.free_bytes);
+        match used + self.available_bytes {
+            0 => 0.0,
+            usable => used as f64 / usable as f64 * 100.0,
+        }
+    }
+
+    pub fn inodes_used_percent(&self) -> Option<f64> {
+        match self.total_inodes {
+            0 => None,
+            total => Some(total.saturating_sub(self.free_inodes) as f64 / total as f64 * 100.0),
+        }
+    }
+}
+
+/// Samples `disk_total_bytes`, `disk_available_bytes` and `disk_used_percent`, and `inodes_total` and
+/// `inodes_used_percent` if the filesystem has a fixed number of inodes, of each mounted filesystem.
+/// The samples are labelled with the mount point and device, like `mount=/home` and `device=/dev/sda2`.
+///
+/// Pseudo filesystems are left out, and devices mounted more than once, like by bind mounts, are only sampled at their first mount point.
+///
+/// Each filesystem gets STATVFS_TIMEOUT to answer, a mount that doesn't is skipped
+/// until the call that hung on it returns.
+pub struct FilesystemProbe {
+    proc:          ProcFs,
+    include:       Vec<regex::Regex>,
+    exclude:       Vec<regex::Regex>,
+    ignored_types: BTreeSet<String>,
+    timeout:       Duration,
+    /// The mount points whose statvfs hasn't returned yet.
+    pending:       Arc<Mutex<BTreeSet<String>>>,
+    statvfs:       fn(&Path) -> Result<FsUsage>,
+}
+
+impl FilesystemProbe {
+    pub fn new(proc: ProcFs) -> Self {
+        FilesystemProbe {
+            proc,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            ignored_types: PSEUDO_FILESYSTEMS
+                .iter()
+                .map(|fs_type| fs_type.to_string())
+                .collect(),
+            timeout: STATVFS_TIMEOUT,
+            pending: Arc::new(Mutex::new(BTreeSet::new())),
+            statvfs: FsUsage::statvfs,
+        }
+    }
+
+    /// Only sample the mount points matching one of the included patterns, where `*` matches any number of characters, like `/srv/*`.
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.include.push(pattern_regex(pattern));
+        self
+    }
+
+    /// Leave out the mount points matching the pattern, even if included.
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.exclude.push(pattern_regex(pattern));
+        self
+    }
+
+    /// The filesystem types to leave out, instead of PSEUDO_FILESYSTEMS.
+    pub fn ignored_types(mut self, fs_types: &[&str]) -> Self {
+        self.ignored_types = fs_types.iter().map(|fs_type| fs_type.to_string()).collect();
+        self
+    }
+
+    /// How long to wait for the usage of a filesystem, instead of STATVFS_TIMEOUT.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The usage of the filesystem, by a helper thread so a hung statvfs only holds up that thread.
+    pub fn usage(&self, mount_point: &str) -> Result<FsUsage> {
+        if !self.pending.lock().insert(mount_point.to_string()) {
+            return Err(FilesystemError::StillWaiting.into());
+        }
+
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let pending = Arc::clone(&self.pending);
+        let statvfs = self.statvfs;
+        let path = mount_point.to_string();
+        let spawned = thread::Builder::new()
+            .name("statvfs".to_string())
+            .spawn(move || {
+                let usage = statvfs(Path::new(&path));
+                pending.lock().remove(&path);
+                let _ = sender.send(usage);
+            });
+        if let Err(err) = spawned {
+            // Without a thread there is no call to wait for
+            self.pending.lock().remove(mount_point);
+            return Err(err.into());
+        }
+        receiver
+            .recv_timeout(self.timeout)
+            .map_err(|_| FilesystemError::TimedOut(self.timeout))?
+    }
+
+    /// The mounts to sample, in the order of the mount table.
+    pub fn mounts(&self) -> Result<Vec<Mount>> {
+        let mut devices = BTreeSet::new();
+        Ok(parse_mounts(&self.proc.read(MOUNTS)?)?
+            .into_iter()
+            .filter(|mount| !self.ignored_types.contains(&mount.fs_type))
+            .filter(|mount| {
+                let matches = |pattern: &regex::Regex| pattern.is_match(&mount.mount_point);
+                (self.include.is_empty() || self.include.iter().any(matches))
+                    && !self.exclude.iter().any(matches)
+            })
+            // Devices without a path, like tmpfs, are a filesystem of their own at every mount
+            .filter(|mount| !mount.device.starts_with('/') || devices.insert(mount.device.clone()))
+            .collect())
+    }
+}
+
+fn pattern_regex(pattern: &str) -> regex::Regex {
+    let regex = format!("^{}$", pattern.split('*').map(regex::escape).join(".*"));
+    regex::Regex::new(&regex).expect("escaped patterns are valid regexes")
+}
+
+impl Probe for FilesystemProbe {
+    fn sample(&mut self) -> Result<Vec<Sample>> {
+        let mut samples = Vec::new();
+        for mount in self.mounts()? {
+            // Unreachable network filesystems and mounts of other users shouldn't hide the usage of the rest
+            let usage = match self.usage(&mount.mount_point) {
+                Ok(usage) => usage,
+                Err(err) => {
+                    log::warn!("Unable to get the usage of {}: {}", mount.mount_point, err);
+                    continue;
+                }
+            };
+
+            let sample = |metric: &str, value: f64| {
+                Sample::new(metric, value)
+                    .label("mount", &mount.mount_point)
+                    .label("device", &mount.device)
+            };
+            samples.push(sample("disk_total_bytes", usage.total_bytes as f64));
+            samples.push(sample("disk_available_bytes", usage.available_bytes as f64));
+            samples.push(sample("disk_used_percent", usage.used_percent()));
+            if let Some(inodes_used_percent) = usage.inodes_used_percent() {
+                samples.push(sample("inodes_total", usage.total_inodes as f64));
+                samples.push(sample("inodes_used_percent", inodes_used_percent));
+            }
+        }
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures;
+    use internal_prelude::application_prelude::anyhow;
+    use monitoring_service::{
+        test_support::{ActionRecorder, MonitorHarness},
+        EventKey, PollingMonitor, PollingSchedule, Severity, ThresholdEvent, VirtualClock,
+    };
+    use std::time::Duration;
+
+    fn mount_points(probe: &FilesystemProbe) -> Vec<String> {
+        probe
+            .mounts()
+            .unwrap()
+            .into_iter()
+            .map(|mount| mount.mount_point)
+            .collect()
+    }
+
+    #[test]
+    fn test_mounts() {
+        let mounts = parse_mounts(include_str!("../fixtures/proc/self/mounts")).unwrap();
+        assert_eq!(mounts.len(), 15);
+        assert_eq!(
+            mounts[12],
+            Mount {
+                device:      "/dev/mapper/vg0-home".to_string(),
+                mount_point: "/home/shared files".to_string(),
+                fs_type:     "ext4".to_string(),
+            }
+        );
+        assert_eq!(unescape(r"a\134b\x"), r"a\b\x");
+        assert!(parse_mounts("/dev/sda2 /").is_err());
+
+        assert_eq!(
+            mount_points(&FilesystemProbe::new(fixtures())),
+            vec![
+                "/run",
+                "/",
+                "/boot/efi",
+                "/var/lib/docker",
+                "/home/shared files",
+                "/mnt/backups"
+            ]
+        );
+        assert_eq!(
+            mount_points(
+                &FilesystemProbe::new(fixtures())
+                    .include("/")
+                    .include("/var/*")
+                    .include("/srv/*")
+                    .exclude("*/docker")
+            ),
+            vec!["/"]
+        );
+        assert_eq!(
+            mount_points(&FilesystemProbe::new(fixtures()).ignored_types(&["tmpfs", "nfs4"])).len(),
+            12
+        );
+    }
+
+    #[test]
+    fn test_usage() {
+        let usage = FsUsage {
+            total_bytes:     1000,
+            free_bytes:      150,
+            available_bytes: 100,
+            total_inodes:    0,
+            free_inodes:     0,
+        };
+        // Like df, the blocks reserved for root don't count as usable
+        assert!((usage.used_percent() - 850.0 / 950.0 * 100.0).abs() < 1e-9);
+        assert_eq!(usage.inodes_used_percent(), None);
+
+        let root = FsUsage::statvfs(Path::new("/")).unwrap();
+        assert!(root.total_bytes >= root.free_bytes);
+        assert!(FsUsage::statvfs(Path::new("/nonexistent")).is_err());
+    }
+
+    #[test]
+    fn test_hung_statvfs() {
+        let mut probe = FilesystemProbe::new(fixtures()).timeout(Duration::from_millis(10));
+        probe.statvfs = |path| {
+            if path == Path::new("/mnt/backups") {
+                thread::sleep(Duration::from_millis(200));
+            }
+            FsUsage::statvfs(Path::new("/"))
+        };
+
+        assert!(probe.usage("/").is_ok());
+        let err = probe.usage("/mnt/backups").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No answer within 10ms, the filesystem may be unreachable."
+        );
+
+        // A mount still hung from the last poll is skipped instead of piling up threads
+        let err = probe.usage("/mnt/backups").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Still waiting for the answer to an earlier poll."
+        );
+        let mount_points: Vec<String> = probe
+            .sample()
+            .unwrap()
+            .into_iter()
+            .filter_map(|sample| sample.labels.get("mount").cloned())
+            .unique()
+            .collect();
+        assert!(!mount_points.contains(&"/mnt/backups".to_string()));
+
+        // Once the hung call returns the mount is tried again
+        thread::sleep(Duration::from_millis(300));
+        assert!(matches!(
+            probe.usage("/mnt/backups").unwrap_err().downcast(),
+            Ok(FilesystemError::TimedOut(_))
+        ));
+    }
+
+    #[test]
+    fn test_threshold_events_per_mount() {
+        let mut probe = FilesystemProbe::new(fixtures())
+            .include("/")
+            .include("/mnt/*");
+        probe.statvfs = |path| match path.to_str() {
+            Some("/") => Ok(FsUsage {
+                total_bytes:     1000,
+                free_bytes:      150,
+                available_bytes: 100,
+                total_inodes:    100,
+                free_inodes:     75,
+            }),
+            _ => Err(anyhow!("Stale file handle")),
+        };
+        let mut metric_monitor = probe.into_metric_monitor();
+        metric_monitor
+            .rule("disk_used_percent", Severity::Warning, "> 80")
+            .unwrap();
+
+        let clock = VirtualClock::new();
+        let recorder = ActionRecorder::new(&clock);
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .schedule_named_polling(
+                "disks",
+                *PollingSchedule::default().interval(Duration::from_secs(60)),
+                metric_monitor.into_polling_func(),
+            )
+            .register_action(
+                EventKey::Polled(ThresholdEvent::new("disk_used_percent", Severity::Warning)),
+                recorder.action("log"),
+            );
+        let mut harness = MonitorHarness::new(monitor, &clock);
+        harness.advance(Duration::from_secs(1));
+
+        // The unreachable backups mount is skipped
+        let invocations = recorder.invocations();
+        assert_eq!(invocations.len(), 1);
+        let event = &invocations[0].event;
+        assert_eq!(event.value("mount"), Some(&"/".into()));
+        assert_eq!(event.value("device"), Some(&"/dev/sda2".into()));
+        assert_eq!(event.value("value"), Some(&(850.0 / 950.0 * 100.0).into()));
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use internal_prelude::library_prelude::*;
+use monitoring_service::{MetricMonitor, Sample};
+
+pub mod cpu;
+pub mod diskstats;
+pub mod filesystem;
+pub mod load;
+pub mod memory;
+pub mod prediction;
+pub mod pressure;
+pub mod process;
+pub mod top;
+
+pub use cpu::CpuProbe;
+pub use diskstats::DiskStatsProbe;
+pub use filesystem::FilesystemProbe;
+pub use load::LoadProbe;
+pub use memory::MemoryProbe;
+pub use prediction::FullPrediction;
+pub use pressure::PressureProbe;
+pub use process::{ProcessMatcher, ProcessProbe};
+pub use top::TopProcesses;
+
+/// A source of numeric samples, like the CPU usage, for the ThresholdRules of a MetricMonitor.
+pub trait Probe: Send {
+    fn sample(&mut self) -> Result<Vec<Sample>>;
+
+    /// A MetricMonitor polling the probe, add rules for the metrics of the probe to it.
+    fn into_metric_monitor(self) -> MetricMonitor
+    where
+        Self: Sized + 'static,
+    {
+        let probe = Mutex::new(self);
+        MetricMonitor::new(move || probe.lock().sample())
+    }
+}
+
+/// Several probes polled as one, the samples of all of them together.
+impl Probe for Vec<Box<dyn Probe>> {
+    fn sample(&mut self) -> Result<Vec<Sample>> {
+        let mut samples = Vec::new();
+        for probe in self.iter_mut() {
+            samples.extend(probe.sample()?);
+        }
+        Ok(samples)
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ProcError {
+    #[error("Unable to read {file}: {source}")]
+    Unreadable {
+        file:   PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Unexpected line in {file}: '{line}'.")]
+    Malformed { file: String, line: String },
+    #[error("{file} has no {field}.")]
+    Missing { file: String, field: String },
+}
+
+impl ProcError {
+    pub(crate) fn malformed(file: &str, line: &str) -> Self {
+        ProcError::Malformed {
+            file: file.to_string(),
+            line: line.to_string(),
+        }
+    }
+
+    pub(crate) fn missing(file: &str, field: &str) -> Self {
+        ProcError::Missing {
+            file:  file.to_string(),
+            field: field.to_string(),
+        }
+    }
+}
+
+/// Where the probes read the files of the proc filesystem from, `/proc` unless pointed elsewhere, like at fixtures.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ProcFs {
+    root: PathBuf,
+}
+
+impl Default for ProcFs {
+    fn default() -> Self {
+        ProcFs::at("/proc")
+    }
+}
+
+impl ProcFs {
+    pub fn at(root: impl Into<PathBuf>) -> Self {
+        ProcFs { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Read a file relative to the root, like `pressure/io`.
+    pub fn read(&self, file: &str) -> Result<String, ProcError> {
+        let path = self.root.join(file);
+        fs::read_to_string(&path).map_err(|source| ProcError::Unreadable { file: path, source })
+    }
+
+    /// The names of the entries of a directory relative to the root, like `1234/fd`.
+    pub fn read_dir(&self, dir: &str) -> Result<Vec<String>, ProcError> {
+        let path = self.root.join(dir);
+        let unreadable = |source| ProcError::Unreadable {
+            file: path.clone(),
+            source,
+        };
+        fs::read_dir(&path)
+            .map_err(unreadable)?
+            .map(|entry| {
+                entry
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .map_err(unreadable)
+            })
+            .collect()
+    }
+
+    /// The ids of the running processes.
+    pub fn pids(&self) -> Result<Vec<u32>, ProcError> {
+        let mut pids: Vec<u32> = self
+            .read_dir("")?
+            .iter()
+            .filter_map(|entry| entry.parse().ok())
+            .collect();
+        pids.sort_unstable();
+        Ok(pids)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use monitoring_service::Severity;
+
+    pub(crate) fn fixtures() -> ProcFs {
+        ProcFs::at(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/proc"))
+    }
+
+    #[test]
+    fn test_probes_together() {
+        let mut probes: Vec<Box<dyn Probe>> = vec![
+            Box::new(LoadProbe::new(fixtures())),
+            Box::new(MemoryProbe::new(fixtures())),
+        ];
+        let samples = probes.sample().unwrap();
+        assert_eq!(samples[0], Sample::new("load1", 0.52));
+        assert!(samples
+            .iter()
+            .any(|sample| sample.metric == "memory_used_percent"));
+
+        let mut monitor = probes.into_metric_monitor();
+        monitor.rule("load1", Severity::Warning, "> 4").unwrap();
+    }
+
+    #[test]
+    fn test_proc_fs_errors() {
+        let missing = ProcFs::at("/nonexistent");
+        assert!(matches!(
+            missing.read("loadavg"),
+            Err(ProcError::Unreadable { .. })
+        ));
+        assert!(matches!(
+            missing.read_dir("1/fd"),
+            Err(ProcError::Unreadable { .. })
+        ));
+        assert!(missing
+            .pids()
+            .unwrap_err()
+            .to_string()
+            .starts_with("Unable to read /nonexistent"));
+    }
+}
@@ -0,0 +1,87 @@
+use internal_prelude::library_prelude::*;
+use monitoring_service::Sample;
+
+use crate::{Probe, ProcError, ProcFs};
+
+const LOADAVG: &str = "loadavg";
+
+/// The contents of `/proc/loadavg`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct LoadAverage {
+    pub load1:     f64,
+    pub load5:     f64,
+    pub load15:    f64,
+    /// Runnable threads at the moment.
+    pub running:   u64,
+    pub processes: u64,
+}
+
+pub fn parse_loadavg(contents: &str) -> Result<LoadAverage, ProcError> {
+    let line = contents.trim();
+    let malformed = || ProcError::malformed(LOADAVG, line);
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 4 {
+        return Err(malformed());
+    }
+
+    let load = |field: &str| field.parse::<f64>().map_err(|_| malformed());
+    let (running, processes) = fields[3].split_once('/').ok_or_else(malformed)?;
+    Ok(LoadAverage {
+        load1:     load(fields[0])?,
+        load5:     load(fields[1])?,
+        load15:    load(fields[2])?,
+        running:   running.parse().map_err(|_| malformed())?,
+        processes: processes.parse().map_err(|_| malformed())?,
+    })
+}
+
+/// Samples `load1`, `load5` and `load15`, and `processes_running` and `processes_total`.
+pub struct LoadProbe {
+    proc: ProcFs,
+}
+
+impl LoadProbe {
+    pub fn new(proc: ProcFs) -> Self {
+        LoadProbe { proc }
+    }
+}
+
+impl Probe for LoadProbe {
+    fn sample(&mut self) -> Result<Vec<Sample>> {
+        let load = parse_loadavg(&self.proc.read(LOADAVG)?)?;
+        Ok(vec![
+            Sample::new("load1", load.load1),
+            Sample::new("load5", load.load5),
+            Sample::new("load15", load.load15),
+            Sample::new("processes_running", load.running as f64),
+            Sample::new("processes_total", load.processes as f64),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures;
+
+    #[test]
+    fn test_loadavg() {
+        let load = parse_loadavg(include_str!("../fixtures/proc/loadavg")).unwrap();
+        assert_eq!(
+            load,
+            LoadAverage {
+                load1:     0.52,
+                load5:     1.14,
+                load15:    1.37,
+                running:   3,
+                processes: 912,
+            }
+        );
+        assert!(parse_loadavg("0.52 1.14").is_err());
+        assert!(parse_loadavg("0.52 1.14 high 3/912 48213").is_err());
+
+        let samples = LoadProbe::new(fixtures()).sample().unwrap();
+        assert_eq!(samples.len(), 5);
+        assert_eq!(samples[4], Sample::new("processes_total", 912.0));
+    }
+}
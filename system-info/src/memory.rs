@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+
+use internal_prelude::library_prelude::*;
+use monitoring_service::Sample;
+
+use crate::{Probe, ProcError, ProcFs};
+
+const MEMINFO: &str = "meminfo";
+
+/// The fields of `/proc/meminfo` by name, sizes in bytes and counts like `HugePages_Total` as they are.
+pub fn parse_meminfo(contents: &str) -> Result<BTreeMap<String, u64>, ProcError> {
+    let mut fields = BTreeMap::new();
+    for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+        let malformed = || ProcError::malformed(MEMINFO, line);
+        let (name, value) = line.split_once(':').ok_or_else(malformed)?;
+        let mut value = value.split_whitespace();
+        let amount: u64 = value
+            .next()
+            .and_then(|amount| amount.parse().ok())
+            .ok_or_else(malformed)?;
+        let amount = match value.next() {
+            Some("kB") => amount * 1024,
+            None => amount,
+            Some(_) => return Err(malformed()),
+        };
+        fields.insert(name.to_string(), amount);
+    }
+    Ok(fields)
+}
+
+/// Samples `memory_total_bytes`, `memory_available_bytes` and `memory_used_percent`,
+/// and `swap_total_bytes` and `swap_used_percent`, which is 0 without swap.
+///
+/// Used memory is what is not available, so page cache that could be reclaimed doesn't count.
+pub struct MemoryProbe {
+    proc: ProcFs,
+}
+
+impl MemoryProbe {
+    pub fn new(proc: ProcFs) -> Self {
+        MemoryProbe { proc }
+    }
+}
+
+impl Probe for MemoryProbe {
+    fn sample(&mut self) -> Result<Vec<Sample>> {
+        let fields = parse_meminfo(&self.proc.read(MEMINFO)?)?;
+        let field = |name: &str| {
+            fields
+                .get(name)
+                .copied()
+                .ok_or_else(|| ProcError::missing(MEMINFO, name))
+        };
+
+        let total = field("MemTotal")?;
+        // Kernels before 3.14 don't estimate the available memory
+        let available = match field("MemAvailable") {
+            Ok(available) => available,
+            Err(_) => field("MemFree")? + field("Buffers")? + field("Cached")?,
+        };
+        let swap_total = field("SwapTotal")?;
+        let swap_used = swap_total.saturating_sub(field("SwapFree")?);
+        let percent = |part: u64, whole: u64| match whole {
+            0 => 0.0,
+            whole => part as f64 / whole as f64 * 100.0,
+        };
+
+        Ok(vec![
+            Sample::new("memory_total_bytes", total as f64),
+            Sample::new("memory_available_bytes", available as f64),
+            Sample::new(
+                "memory_used_percent",
+                percent(total.saturating_sub(available), total),
+            ),
+            Sample::new("swap_total_bytes", swap_total as f64),
+            Sample::new("swap_used_percent", percent(swap_used, swap_total)),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures;
+
+    #[test]
+    fn test_meminfo() {
+        let fields = parse_meminfo(include_str!("../fixtures/proc/meminfo")).unwrap();
+        assert_eq!(fields["MemTotal"], 16318480 * 1024);
+        assert_eq!(fields["HugePages_Total"], 0);
+        assert!(parse_meminfo("MemTotal: 1 MB").is_err());
+        assert!(parse_meminfo("MemTotal 1 kB").is_err());
+
+        let samples = MemoryProbe::new(fixtures()).sample().unwrap();
+        let value = |metric: &str| {
+            samples
+                .iter()
+                .find(|sample| sample.metric == metric)
+                .unwrap()
+                .value
+        };
+        assert_eq!(value("memory_available_bytes"), 9640804.0 * 1024.0);
+        assert!((value("memory_used_percent") - 40.92).abs() < 0.01);
+        assert!((value("swap_used_percent") - 25.0).abs() < 0.01);
+    }
+}
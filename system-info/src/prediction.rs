@@ -0,0 +1,201 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use internal_prelude::library_prelude::*;
+use monitoring_service::Sample;
+
+use crate::{FilesystemProbe, Probe};
+
+/// Predicts when filesystems will be full from the trend of their available space, adding a
+/// `disk_hours_until_full` sample per mount to the samples of the FilesystemProbe, labelled the same way.
+/// A rule like `< 24` on it fires when a filesystem will be full in under a day at its current pace.
+///
+/// The trend is a straight line fitted through the available space within the look-back window.
+/// Sudden jumps in available space, like from cleanups, are taken out of the history instead of
+/// flattening the trend, and filesystems that aren't filling up report the horizon.
+pub struct FullPrediction {
+    probe:       FilesystemProbe,
+    window:      Duration,
+    cleanup:     f64,
+    horizon:     Duration,
+    min_samples: usize,
+    // Available bytes of each series of disk_available_bytes, oldest first
+    history:     HashMap<String, VecDeque<(Instant, f64)>>,
+}
+
+impl FullPrediction {
+    pub fn new(probe: FilesystemProbe) -> Self {
+        FullPrediction {
+            probe,
+            window: Duration::from_secs(6 * 60 * 60),
+            cleanup: 1.0,
+            horizon: Duration::from_secs(365 * 24 * 60 * 60),
+            min_samples: 3,
+            history: HashMap::new(),
+        }
+    }
+
+    /// How far back the trend looks, defaults to 6 hours.
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    /// A rise in available space between polls of more than this percentage of the filesystem size
+    /// counts as a cleanup, defaults to 1%.
+    pub fn cleanup_percent(mut self, percent: f64) -> Self {
+        self.cleanup = percent;
+        self
+    }
+
+    /// The longest prediction, also reported for filesystems that aren't filling up, defaults to a year.
+    pub fn horizon(mut self, horizon: Duration) -> Self {
+        self.horizon = horizon;
+        self
+    }
+
+    /// How many samples in the window it takes to predict, defaults to 3.
+    pub fn min_samples(mut self, min_samples: usize) -> Self {
+        self.min_samples = min_samples.max(2);
+        self
+    }
+
+    pub(crate) fn predict(&mut self, samples: &[Sample], now: Instant) -> Vec<Sample> {
+        let mut predictions = Vec::new();
+        for available in samples
+            .iter()
+            .filter(|sample| sample.metric == "disk_available_bytes")
+        {
+            let total = samples
+                .iter()
+                .find(|sample| {
+                    sample.metric == "disk_total_bytes" && sample.labels == available.labels
+                })
+                .map_or(0.0, |sample| sample.value);
+            let history = self.history.entry(available.series()).or_default();
+
+            while let Some((at, _)) = history.front() {
+                if now.duration_since(*at) <= self.window {
+                    break;
+                }
+                history.pop_front();
+            }
+            if let Some((_, last)) = history.back() {
+                let freed = available.value - last;
+                if freed > total * self.cleanup / 100.0 {
+                    log::info!(
+                        "{} bytes freed on {}, left out of the trend as a cleanup",
+                        freed,
+                        available.series()
+                    );
+                    history.iter_mut().for_each(|(_, value)| *value += freed);
+                }
+            }
+            history.push_back((now, available.value));
+
+            if history.len() < self.min_samples {
+                continue;
+            }
+            let hours = match bytes_per_second(history) {
+                Some(rate) if rate < 0.0 => Duration::from_secs_f64(
+                    (available.value / -rate).min(self.horizon.as_secs_f64()),
+                ),
+                _ => self.horizon,
+            };
+
+            let mut prediction = Sample::new("disk_hours_until_full", hours.as_secs_f64() / 3600.0);
+            prediction.labels = available.labels.clone();
+            predictions.push(prediction);
+        }
+
+        // Forget filesystems that are no longer mounted
+        let series: Vec<String> = samples.iter().map(Sample::series).collect();
+        self.history.retain(|kept, _| series.contains(kept));
+        predictions
+    }
+}
+
+/// The slope of the least squares line through the values, None if they were all taken at once.
+fn bytes_per_second(history: &VecDeque<(Instant, f64)>) -> Option<f64> {
+    let (first, _) = history.front()?;
+    let points: Vec<(f64, f64)> = history
+        .iter()
+        .map(|(at, value)| (at.duration_since(*first).as_secs_f64(), *value))
+        .collect();
+    let count = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / count;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / count;
+
+    let covariance: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    if variance == 0.0 {
+        return None;
+    }
+    Some(covariance / variance)
+}
+
+impl Probe for FullPrediction {
+    fn sample(&mut self) -> Result<Vec<Sample>> {
+        let mut samples = self.probe.sample()?;
+        let predictions = self.predict(&samples, Instant::now());
+        samples.extend(predictions);
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures;
+
+    const GB: f64 = 1_000_000_000.0;
+
+    fn disk(mount: &str, available: f64) -> Vec<Sample> {
+        vec![
+            Sample::new("disk_total_bytes", 100.0 * GB).label("mount", mount),
+            Sample::new("disk_available_bytes", available).label("mount", mount),
+        ]
+    }
+
+    fn hours(predictions: &[Sample]) -> Vec<f64> {
+        predictions
+            .iter()
+            .map(|prediction| (prediction.value * 10.0).round() / 10.0)
+            .collect()
+    }
+
+    #[test]
+    fn test_prediction() {
+        let mut prediction = FullPrediction::new(FilesystemProbe::new(fixtures()))
+            .window(Duration::from_secs(4 * 60 * 60))
+            .horizon(Duration::from_secs(1000 * 60 * 60));
+        let start = Instant::now();
+        let mut predict = |hour: u64, var: f64, tmp: f64| {
+            let mut samples = disk("/var", var * GB);
+            samples.extend(disk("/tmp", tmp * GB));
+            prediction.predict(&samples, start + Duration::from_secs(hour * 60 * 60))
+        };
+
+        // Losing 2GB an hour, not enough samples to tell yet
+        assert!(predict(0, 30.0, 50.0).is_empty());
+        assert!(predict(1, 28.0, 50.0).is_empty());
+        let predictions = predict(2, 26.0, 50.0);
+        assert_eq!(hours(&predictions), vec![13.0, 1000.0]);
+        assert_eq!(predictions[0].series(), "disk_hours_until_full{mount=/var}");
+
+        // The cleanup doesn't turn the trend around
+        assert_eq!(hours(&predict(3, 64.0, 50.0)), vec![45.7, 1000.0]);
+        assert_eq!(hours(&predict(4, 62.0, 50.0)), vec![44.3, 1000.0]);
+
+        // A slower pace once the steep start has left the window
+        for (hour, var) in &[(5, 61.5), (6, 61.0), (7, 60.5)] {
+            predict(*hour, *var, 50.0);
+        }
+        assert_eq!(hours(&predict(8, 60.0, 50.0)), vec![120.0, 1000.0]);
+    }
+}
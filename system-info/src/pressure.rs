@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use internal_prelude::library_prelude::*;
+use monitoring_service::Sample;
+
+use crate::{Probe, ProcError, ProcFs};
+
+/// The resources with pressure stall information, each has a file in `/proc/pressure`.
+pub const RESOURCES: [&str; 3] = ["cpu", "memory", "io"];
+
+/// The share of time tasks were stalled on a resource, averaged over 10s, 60s and 300s.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Pressure {
+    pub avg10:  f64,
+    pub avg60:  f64,
+    pub avg300: f64,
+    /// The total time stalled since boot.
+    pub total:  Duration,
+}
+
+/// The contents of a file in `/proc/pressure`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Pressures {
+    /// At least some tasks were stalled.
+    pub some: Pressure,
+    /// All non-idle tasks were stalled at once, missing for the CPU before Linux 5.13.
+    pub full: Option<Pressure>,
+}
+
+pub fn parse_pressure(resource: &str, contents: &str) -> Result<Pressures, ProcError> {
+    let file = format!("pressure/{}", resource);
+    let mut some = None;
+    let mut full = None;
+    for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+        let malformed = || ProcError::malformed(&file, line);
+        let mut fields = line.split_whitespace();
+        let kind = fields.next().ok_or_else(malformed)?;
+
+        let mut values = fields.map(|field| field.split_once('=').ok_or_else(malformed));
+        let mut value = |name: &str| match values.next() {
+            Some(Ok((field, value))) if field == name => Ok(value),
+            _ => Err(malformed()),
+        };
+        let average = |value: &str| value.parse::<f64>().map_err(|_| malformed());
+        let pressure = Pressure {
+            avg10:  average(value("avg10")?)?,
+            avg60:  average(value("avg60")?)?,
+            avg300: average(value("avg300")?)?,
+            total:  Duration::from_micros(value("total")?.parse().map_err(|_| malformed())?),
+        };
+
+        match kind {
+            "some" => some = Some(pressure),
+            "full" => full = Some(pressure),
+            _ => return Err(malformed()),
+        }
+    }
+
+    Ok(Pressures {
+        some: some.ok_or_else(|| ProcError::missing(&file, "some line"))?,
+        full,
+    })
+}
+
+/// Samples `pressure_some_avg10`, `pressure_some_avg60`, `pressure_some_avg300` and
+/// `pressure_some_total_seconds`, and the same for full, labelled with the resource like `resource=io`.
+///
+/// Needs a kernel with pressure stall information, Linux 4.20 or later with PSI enabled.
+pub struct PressureProbe {
+    proc:      ProcFs,
+    resources: Vec<String>,
+}
+
+impl PressureProbe {
+    /// Probe all of RESOURCES.
+    pub fn new(proc: ProcFs) -> Self {
+        PressureProbe {
+            proc,
+            resources: RESOURCES
+                .iter()
+                .map(|resource| resource.to_string())
+                .collect(),
+        }
+    }
+
+    pub fn resources(mut self, resources: &[&str]) -> Self {
+        self.resources = resources
+            .iter()
+            .map(|resource| resource.to_string())
+            .collect();
+        self
+    }
+}
+
+fn pressure_samples(kind: &str, pressure: &Pressure) -> Vec<Sample> {
+    let metric = |name: &str| format!("pressure_{}_{}", kind, name);
+    vec![
+        Sample::new(&metric("avg10"), pressure.avg10),
+        Sample::new(&metric("avg60"), pressure.avg60),
+        Sample::new(&metric("avg300"), pressure.avg300),
+        Sample::new(&metric("total_seconds"), pressure.total.as_secs_f64()),
+    ]
+}
+
+impl Probe for PressureProbe {
+    fn sample(&mut self) -> Result<Vec<Sample>> {
+        let mut samples = Vec::new();
+        for resource in &self.resources {
+            let contents = self.proc.read(&format!("pressure/{}", resource))?;
+            let pressures = parse_pressure(resource, &contents)?;
+            let mut resource_samples = pressure_samples("some", &pressures.some);
+            if let Some(full) = &pressures.full {
+                resource_samples.extend(pressure_samples("full", full));
+            }
+            samples.extend(
+                resource_samples
+                    .into_iter()
+                    .map(|sample| sample.label("resource", resource)),
+            );
+        }
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures;
+
+    #[test]
+    fn test_pressure() {
+        let io = parse_pressure("io", include_str!("../fixtures/proc/pressure/io")).unwrap();
+        assert_eq!(
+            io.some,
+            Pressure {
+                avg10:  12.5,
+                avg60:  4.31,
+                avg300: 1.02,
+                total:  Duration::from_micros(95031244),
+            }
+        );
+        assert_eq!(io.full.unwrap().avg10, 10.21);
+
+        let old_cpu = "some avg10=2.04 avg60=1.26 avg300=0.68 total=391253861\n";
+        assert_eq!(parse_pressure("cpu", old_cpu).unwrap().full, None);
+        assert!(parse_pressure("cpu", "").is_err());
+        assert!(parse_pressure("cpu", "some avg60=1.26 avg10=2.04 avg300=0.68 total=1").is_err());
+        assert!(parse_pressure("cpu", "most avg10=2.04 avg60=1.26 avg300=0.68 total=1").is_err());
+
+        let samples = PressureProbe::new(fixtures()).sample().unwrap();
+        assert_eq!(samples.len(), 3 * 8);
+        assert_eq!(
+            samples[16],
+            Sample::new("pressure_some_avg10", 12.5).label("resource", "io")
+        );
+        assert_eq!(
+            samples[7],
+            Sample::new("pressure_full_total_seconds", 0.0).label("resource", "cpu")
+        );
+    }
+}
@@ -0,0 +1,555 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use internal_prelude::library_prelude::*;
+use monitoring_service::{MetricMonitor, Sample, Severity};
+use nix::unistd::{sysconf, SysconfVar};
+
+use crate::{Probe, ProcError, ProcFs};
+
+const UPTIME: &str = "uptime";
+
+/// How the processes of a ProcessWatch are found.
+#[derive(Clone, Debug)]
+pub enum ProcessMatcher {
+    /// The name of the executable, as in `/proc/[pid]/comm`, which the kernel cuts to 15 characters
+    /// except for the workers of kernel threads.
+    Name(String),
+    /// A regex matched against the command line, with the arguments separated by spaces.
+    Cmdline(regex::Regex),
+    /// The process whose id is in the file, like `/run/nginx.pid`.
+    Pidfile(PathBuf),
+}
+
+impl ProcessMatcher {
+    pub fn name(name: &str) -> Self {
+        ProcessMatcher::Name(name.to_string())
+    }
+
+    pub fn cmdline(regex: &str) -> Result<Self> {
+        Ok(ProcessMatcher::Cmdline(regex::Regex::new(regex)?))
+    }
+
+    pub fn pidfile(pidfile: impl Into<PathBuf>) -> Self {
+        ProcessMatcher::Pidfile(pidfile.into())
+    }
+
+    fn matches(&self, process: &ProcessInfo, pidfile_pid: Option<u32>) -> bool {
+        match self {
+            ProcessMatcher::Name(name) => {
+                process.comm == *name || process.comm == name.chars().take(15).collect::<String>()
+            }
+            ProcessMatcher::Cmdline(regex) => regex.is_match(&process.cmdline),
+            ProcessMatcher::Pidfile(_) => pidfile_pid == Some(process.pid),
+        }
+    }
+}
+
+/// What is known about a running process from `/proc/[pid]`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ProcessInfo {
+    pub pid:        u32,
+    pub comm:       String,
+    /// Empty for kernel threads.
+    pub cmdline:    String,
+    /// User and system time since the process started, in clock ticks.
+    pub cpu_ticks:  u64,
+    /// Clock ticks after boot the process started at, which tells apart processes that got the same pid.
+    pub start_time: u64,
+    pub rss_pages:  u64,
+    /// None if the fds of the process can't be listed, like those of other users.
+    pub open_fds:   Option<usize>,
+    /// Bytes read from and written to storage since the process started, None if unknown like for other users.
+    pub io_bytes:   Option<u64>,
+}
+
+/// The pid, comm, cpu_ticks, start_time and rss_pages of a `/proc/[pid]/stat` line.
+pub fn parse_stat(contents: &str) -> Result<ProcessInfo, ProcError> {
+    let line = contents.trim();
+    let malformed = || ProcError::malformed("[pid]/stat", line);
+    // The comm is in parentheses and may contain anything, including spaces and parentheses
+    let (pid, rest) = line.split_once(" (").ok_or_else(malformed)?;
+    let (comm, rest) = rest.rsplit_once(") ").ok_or_else(malformed)?;
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    // Field numbers as in proc(5), the state is field 3
+    let field = |number: usize| {
+        fields
+            .get(number - 3)
+            .and_then(|field| field.parse::<u64>().ok())
+            .ok_or_else(malformed)
+    };
+
+    Ok(ProcessInfo {
+        pid:        pid.parse().map_err(|_| malformed())?,
+        comm:       comm.to_string(),
+        cmdline:    String::new(),
+        cpu_ticks:  field(14)? + field(15)?,
+        start_time: field(22)?,
+        rss_pages:  field(24)?,
+        open_fds:   None,
+        io_bytes:   None,
+    })
+}
+
+/// The bytes read from and written to storage of a `/proc/[pid]/io` file, None without the counters.
+pub fn parse_io(contents: &str) -> Option<u64> {
+    let counter = |name: &str| {
+        contents
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| value.trim().parse::<u64>().ok())
+    };
+    Some(counter("read_bytes")? + counter("write_bytes")?)
+}
+
+/// The time since boot of a `/proc/uptime` file.
+pub fn parse_uptime(contents: &str) -> Result<Duration, ProcError> {
+    contents
+        .split_whitespace()
+        .next()
+        .and_then(|seconds| seconds.parse::<f64>().ok())
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| ProcError::malformed(UPTIME, contents.trim()))
+}
+
+/// Every process that is still running by the time it is read.
+pub fn processes(proc: &ProcFs) -> Result<Vec<ProcessInfo>> {
+    let mut processes = process_stats(proc)?;
+    for process in &mut processes {
+        process.cmdline = proc
+            .read(&format!("{}/cmdline", process.pid))
+            .map(|cmdline| cmdline.trim_end_matches('\0').replace('\0', " "))
+            .unwrap_or_default();
+        process.open_fds = proc
+            .read_dir(&format!("{}/fd", process.pid))
+            .ok()
+            .map(|fds| fds.len());
+    }
+    Ok(processes)
+}
+
+/// Every running process with only what its `stat` and `io` files tell, which is much cheaper than
+/// processes for measuring usage as it reads neither the command line nor the open fds.
+pub fn process_stats(proc: &ProcFs) -> Result<Vec<ProcessInfo>> {
+    let mut processes = Vec::new();
+    for pid in proc.pids()? {
+        // Processes may exit while being read, and one odd process shouldn't hide all the others
+        let stat = match proc.read(&format!("{}/stat", pid)) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        let mut process = match parse_stat(&stat) {
+            Ok(process) => process,
+            Err(err) => {
+                log::debug!("Skipping process {}: {}", pid, err);
+                continue;
+            }
+        };
+        process.io_bytes = proc
+            .read(&format!("{}/io", pid))
+            .ok()
+            .and_then(|io| parse_io(&io));
+        processes.push(process);
+    }
+    Ok(processes)
+}
+
+pub(crate) fn page_size() -> u64 {
+    sysconf(SysconfVar::PAGE_SIZE)
+        .ok()
+        .flatten()
+        .map_or(4096, |size| size as u64)
+}
+
+pub(crate) fn ticks_per_second() -> u64 {
+    sysconf(SysconfVar::CLK_TCK)
+        .ok()
+        .flatten()
+        .map_or(100, |ticks| ticks as u64)
+}
+
+/// Processes to watch under a name, which labels their samples like `process=nginx`.
+#[derive(Clone, Debug)]
+pub struct ProcessWatch {
+    pub name:          String,
+    pub matcher:       ProcessMatcher,
+    /// More instances than this are a warning of the liveness monitor, any number if None.
+    pub max_instances: Option<usize>,
+}
+
+impl ProcessWatch {
+    pub fn new(name: &str, matcher: ProcessMatcher) -> Self {
+        ProcessWatch {
+            name: name.to_string(),
+            matcher,
+            max_instances: None,
+        }
+    }
+
+    pub fn max_instances(mut self, max_instances: usize) -> Self {
+        self.max_instances = Some(max_instances);
+        self
+    }
+}
+
+type ProcessKey = (u32, u64);
+
+/// Samples `process_count`, `process_rss_bytes`, `process_open_fds` and `process_restarts`,
+/// the processes started since the previous poll, of each ProcessWatch, and `process_cpu_percent`
+/// since the previous poll, where 100 is one CPU. The samples are the totals over the matching processes.
+///
+/// Use into_liveness_monitor for events about processes that aren't running or run too many times.
+pub struct ProcessProbe {
+    proc:             ProcFs,
+    watches:          Vec<ProcessWatch>,
+    page_size:        u64,
+    ticks_per_second: u64,
+    // The previous poll, with the clock ticks since boot at the time to compare start times with
+    last:             Option<(Instant, u64)>,
+    // Every process of the watches at the previous poll, with its cpu_ticks
+    cpu_ticks:        HashMap<ProcessKey, u64>,
+    seen:             HashMap<String, BTreeSet<ProcessKey>>,
+}
+
+impl ProcessProbe {
+    pub fn new(proc: ProcFs) -> Self {
+        ProcessProbe {
+            proc,
+            watches: Vec::new(),
+            page_size: page_size(),
+            ticks_per_second: ticks_per_second(),
+            last: None,
+            cpu_ticks: HashMap::new(),
+            seen: HashMap::new(),
+        }
+    }
+
+    pub fn watch(self, name: &str, matcher: ProcessMatcher) -> Self {
+        self.add_watch(ProcessWatch::new(name, matcher))
+    }
+
+    pub fn add_watch(mut self, watch: ProcessWatch) -> Self {
+        self.watches.push(watch);
+        self
+    }
+
+    /// A MetricMonitor for the probe with a Severity::Critical rule for watched processes that aren't running,
+    /// and a Severity::Warning rule for each watch with more than its max_instances. Add rules for the resources to it.
+    pub fn into_liveness_monitor(self) -> MetricMonitor {
+        let limits: Vec<(String, usize)> = self
+            .watches
+            .iter()
+            .filter_map(|watch| Some((watch.name.clone(), watch.max_instances?)))
+            .collect();
+        let mut monitor = self.into_metric_monitor();
+        monitor
+            .rule("process_count", Severity::Critical, "< 1")
+            .expect("the liveness rules are valid");
+        for (name, max_instances) in limits {
+            monitor
+                .labelled_rule(
+                    "process_count",
+                    "process",
+                    &name,
+                    Severity::Warning,
+                    &format!("> {}", max_instances),
+                )
+                .expect("the liveness rules are valid");
+        }
+        monitor
+    }
+
+    /// Every process that is still running by the time it is read.
+    pub fn processes(&self) -> Result<Vec<ProcessInfo>> {
+        processes(&self.proc)
+    }
+
+    /// Samples of the processes read at now, uptime after boot.
+    fn samples(
+        &mut self,
+        processes: &[ProcessInfo],
+        now: Instant,
+        uptime: Duration,
+    ) -> Vec<Sample> {
+        let elapsed = self
+            .last
+            .map(|(last, _)| now.duration_since(last).as_secs_f64());
+        let last_poll_ticks = self.last.map_or(0, |(_, ticks)| ticks);
+        let mut cpu_ticks = HashMap::new();
+        let mut samples = Vec::new();
+
+        for watch in &self.watches {
+            let pidfile_pid = match &watch.matcher {
+                ProcessMatcher::Pidfile(pidfile) => fs::read_to_string(pidfile)
+                    .ok()
+                    .and_then(|pid| pid.trim().parse().ok()),
+                _ => None,
+            };
+            let matching: Vec<&ProcessInfo> = processes
+                .iter()
+                .filter(|process| watch.matcher.matches(process, pidfile_pid))
+                .collect();
+            let keys: BTreeSet<ProcessKey> = matching
+                .iter()
+                .map(|process| (process.pid, process.start_time))
+                .collect();
+
+            let sample =
+                |metric: &str, value: f64| Sample::new(metric, value).label("process", &watch.name);
+            samples.push(sample("process_count", matching.len() as f64));
+            samples.push(sample(
+                "process_rss_bytes",
+                matching
+                    .iter()
+                    .map(|process| process.rss_pages * self.page_size)
+                    .sum::<u64>() as f64,
+            ));
+            samples.push(sample(
+                "process_open_fds",
+                matching
+                    .iter()
+                    .filter_map(|process| process.open_fds)
+                    .sum::<usize>() as f64,
+            ));
+
+            if let Some(elapsed) = elapsed.filter(|elapsed| *elapsed > 0.0) {
+                let seen = self.seen.get(&watch.name);
+                let restarts = keys
+                    .iter()
+                    .filter(|key| seen.is_none_or(|seen| !seen.contains(key)))
+                    .count();
+                samples.push(sample("process_restarts", restarts as f64));
+
+                // Processes started since the previous poll used all of their time since then,
+                // older ones new to the watch, like those matching a rewritten pidfile, count from the next poll
+                let ticks: u64 = matching
+                    .iter()
+                    .filter_map(|process| {
+                        match self.cpu_ticks.get(&(process.pid, process.start_time)) {
+                            Some(earlier) => Some(process.cpu_ticks.saturating_sub(*earlier)),
+                            None if process.start_time >= last_poll_ticks => {
+                                Some(process.cpu_ticks)
+                            }
+                            None => None,
+                        }
+                    })
+                    .sum();
+                samples.push(sample(
+                    "process_cpu_percent",
+                    ticks as f64 / self.ticks_per_second as f64 / elapsed * 100.0,
+                ));
+            }
+
+            for process in &matching {
+                cpu_ticks.insert((process.pid, process.start_time), process.cpu_ticks);
+            }
+            self.seen.insert(watch.name.clone(), keys);
+        }
+
+        self.cpu_ticks = cpu_ticks;
+        self.last = Some((
+            now,
+            (uptime.as_secs_f64() * self.ticks_per_second as f64) as u64,
+        ));
+        samples
+    }
+}
+
+impl Probe for ProcessProbe {
+    fn sample(&mut self) -> Result<Vec<Sample>> {
+        let uptime = parse_uptime(&self.proc.read(UPTIME)?)?;
+        let processes = self.processes()?;
+        Ok(self.samples(&processes, Instant::now(), uptime))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures;
+    use monitoring_service::{
+        test_support::{ActionRecorder, MonitorHarness},
+        EventKey, PollingMonitor, PollingSchedule, ThresholdEvent, VirtualClock,
+    };
+
+    fn values(samples: &[Sample], process: &str) -> Vec<(String, f64)> {
+        samples
+            .iter()
+            .filter(|sample| sample.labels["process"] == process)
+            .map(|sample| (sample.metric.clone(), sample.value))
+            .collect()
+    }
+
+    #[test]
+    fn test_stat() {
+        let process = parse_stat("4321 (my (odd) proc) R 1 4321 4321 0 -1 4194560 12 0 0 0 70 30 0 0 20 0 1 0 900 1000 250 0").unwrap();
+        assert_eq!(process.comm, "my (odd) proc");
+        assert_eq!(
+            (process.cpu_ticks, process.start_time, process.rss_pages),
+            (100, 900, 250)
+        );
+        assert!(parse_stat("4321 (short) R 1 2 3").is_err());
+        assert!(parse_stat("no comm").is_err());
+
+        let running = ProcessProbe::new(ProcFs::default()).processes().unwrap();
+        assert!(running
+            .iter()
+            .any(|process| process.pid == std::process::id()));
+    }
+
+    #[test]
+    fn test_processes() {
+        let pidfile = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/run/nginx.pid");
+        let mut probe = ProcessProbe::new(fixtures())
+            .watch("nginx", ProcessMatcher::name("nginx"))
+            .watch("nginx-master", ProcessMatcher::pidfile(pidfile))
+            .watch(
+                "workers",
+                ProcessMatcher::cmdline(r"app\.worker( |$)").unwrap(),
+            )
+            .watch(
+                "kworker",
+                ProcessMatcher::name("kworker/u8:2-events_unbound"),
+            )
+            .watch("redis", ProcessMatcher::name("redis-server"));
+        probe.page_size = 4096;
+        probe.ticks_per_second = 100;
+
+        // The process with a truncated stat file is skipped
+        let mut processes = probe.processes().unwrap();
+        assert_eq!(processes.len(), 7);
+        assert!(processes.iter().all(|process| process.pid != 3001));
+        assert_eq!(
+            processes[4].cmdline,
+            "python3 -m app.worker --queue default"
+        );
+        assert_eq!(processes[4].open_fds, Some(57));
+        // The kernel thread has an empty fd directory, which git doesn't keep
+        assert_eq!(processes[6].open_fds, None);
+        assert_eq!(processes[4].io_bytes, Some(734003200 + 10485760));
+        assert_eq!(processes[0].io_bytes, None);
+        assert_eq!(parse_io("read_bytes: 12\nwrite_bytes: 30\n"), Some(42));
+        assert_eq!(parse_io("rchar: 12\n"), None);
+
+        assert_eq!(
+            parse_uptime("1000.25 3900.50\n").unwrap(),
+            Duration::from_millis(1000250)
+        );
+        assert!(parse_uptime("").is_err());
+
+        let start = Instant::now();
+        let uptime = Duration::from_secs(1000);
+        let first = probe.samples(&processes, start, uptime);
+        assert_eq!(
+            values(&first, "nginx"),
+            vec![
+                ("process_count".to_string(), 3.0),
+                (
+                    "process_rss_bytes".to_string(),
+                    (2312.0 + 5120.0 + 5004.0) * 4096.0
+                ),
+                ("process_open_fds".to_string(), 54.0),
+            ]
+        );
+        assert_eq!(values(&first, "nginx-master")[0].1, 1.0);
+        assert_eq!(values(&first, "workers")[0].1, 1.0);
+        assert_eq!(values(&first, "kworker")[0].1, 1.0);
+        assert_eq!(values(&first, "redis")[0].1, 0.0);
+
+        // A worker is replaced and the other uses a quarter of a CPU
+        processes[2].pid = 815;
+        processes[2].start_time = 100_500;
+        processes[3].cpu_ticks += 250;
+        let ten_seconds = Duration::from_secs(10);
+        let second = probe.samples(&processes, start + ten_seconds, uptime + ten_seconds);
+        let nginx = values(&second, "nginx");
+        assert_eq!(nginx[3], ("process_restarts".to_string(), 1.0));
+        assert_eq!(
+            nginx[4],
+            ("process_cpu_percent".to_string(), (520.0 + 250.0) / 10.0)
+        );
+        assert_eq!(values(&second, "nginx-master")[3].1, 0.0);
+        assert_eq!(values(&second, "redis")[4].1, 0.0);
+
+        let third = probe.samples(
+            &processes,
+            start + ten_seconds * 2,
+            uptime + ten_seconds * 2,
+        );
+        assert_eq!(values(&third, "nginx")[3].1, 0.0);
+        assert_eq!(values(&third, "nginx")[4].1, 0.0);
+
+        // A long running process new to the watch isn't charged the time it used before the previous poll
+        let mut old = processes[3].clone();
+        old.pid = 900;
+        processes.push(old.clone());
+        let fourth = probe.samples(
+            &processes,
+            start + ten_seconds * 3,
+            uptime + ten_seconds * 3,
+        );
+        assert_eq!(values(&fourth, "nginx")[4].1, 0.0);
+
+        processes.last_mut().unwrap().cpu_ticks += 100;
+        let fifth = probe.samples(
+            &processes,
+            start + ten_seconds * 4,
+            uptime + ten_seconds * 4,
+        );
+        assert_eq!(values(&fifth, "nginx")[4].1, 10.0);
+    }
+
+    #[test]
+    fn test_liveness_per_watch() {
+        let probe = ProcessProbe::new(fixtures())
+            .add_watch(ProcessWatch::new("nginx", ProcessMatcher::name("nginx")).max_instances(2))
+            .add_watch(
+                ProcessWatch::new("python", ProcessMatcher::name("python3")).max_instances(2),
+            )
+            .watch("redis", ProcessMatcher::name("redis-server"));
+
+        let clock = VirtualClock::new();
+        let recorder = ActionRecorder::new(&clock);
+        let mut monitor = PollingMonitor::new();
+        monitor
+            .schedule_named_polling(
+                "processes",
+                *PollingSchedule::default().interval(Duration::from_secs(60)),
+                probe.into_liveness_monitor().into_polling_func(),
+            )
+            .register_action(
+                EventKey::Polled(ThresholdEvent::new("process_count", Severity::Warning)),
+                recorder.action("warn"),
+            )
+            .register_action(
+                EventKey::Polled(ThresholdEvent::new("process_count", Severity::Critical)),
+                recorder.action("page"),
+            );
+        let mut harness = MonitorHarness::new(monitor, &clock);
+        harness.advance(Duration::from_secs(1));
+
+        // Three nginx are one too many, two python3 are within their limit
+        let invocations: Vec<(String, serde_json::Value)> = recorder
+            .invocations()
+            .into_iter()
+            .map(|invocation| {
+                (
+                    invocation.action.clone(),
+                    invocation.event.value("process").cloned().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            invocations,
+            vec![
+                ("warn".to_string(), "nginx".into()),
+                ("page".to_string(), "redis".into()),
+            ]
+        );
+    }
+}
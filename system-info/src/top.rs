@@ -0,0 +1,255 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use internal_prelude::library_prelude::*;
+use monitoring_service::Values;
+use serde_json::{json, Value};
+
+use crate::{
+    process::{page_size, process_stats, ticks_per_second, ProcessInfo},
+    ProcFs,
+};
+
+/// What a process used between the two readings of a TopProcesses snapshot.
+#[derive(PartialEq, Clone, Debug)]
+pub struct ProcessUsage {
+    pub pid:                 u32,
+    pub name:                String,
+    /// Where 100 is one CPU.
+    pub cpu_percent:         f64,
+    pub rss_bytes:           u64,
+    /// None if the I/O of the process is unknown, like for processes of other users.
+    pub io_bytes_per_second: Option<f64>,
+}
+
+impl ProcessUsage {
+    fn to_value(&self) -> Value {
+        json!({
+            "pid": self.pid,
+            "name": self.name,
+            "cpu_percent": (self.cpu_percent * 10.0).round() / 10.0,
+            "rss_bytes": self.rss_bytes,
+            "io_bytes_per_second": self.io_bytes_per_second.map(f64::round),
+        })
+    }
+}
+
+/// Captures the processes using the most CPU, memory and I/O, to attach to resource events with MetricMonitor::attach.
+///
+/// Usage is measured between two readings of the `stat` and `io` files of the processes the interval apart,
+/// taken when the snapshot is.
+/// A snapshot has the values `top_by_cpu`, `top_by_rss` and `top_by_io` listing the processes with
+/// their pid, name, cpu_percent, rss_bytes and io_bytes_per_second, and `top_processes`
+/// with all three as text, for notification templates like `{{top_processes}}`.
+pub struct TopProcesses {
+    proc:             ProcFs,
+    count:            usize,
+    interval:         Duration,
+    page_size:        u64,
+    ticks_per_second: u64,
+}
+
+impl TopProcesses {
+    pub fn new(proc: ProcFs) -> Self {
+        TopProcesses {
+            proc,
+            count: 5,
+            interval: Duration::from_millis(250),
+            page_size: page_size(),
+            ticks_per_second: ticks_per_second(),
+        }
+    }
+
+    /// How many processes to list by each resource, defaults to 5.
+    pub fn count(mut self, count: usize) -> Self {
+        self.count = count;
+        self
+    }
+
+    /// How long to measure usage for, defaults to 250ms.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// The usage of every process running at both readings.
+    pub fn usage(&self) -> Result<Vec<ProcessUsage>> {
+        let earlier = process_stats(&self.proc)?;
+        let started = Instant::now();
+        sleep(self.interval);
+        let later = process_stats(&self.proc)?;
+        Ok(self.usage_between(&earlier, &later, started.elapsed()))
+    }
+
+    fn usage_between(
+        &self,
+        earlier: &[ProcessInfo],
+        later: &[ProcessInfo],
+        elapsed: Duration,
+    ) -> Vec<ProcessUsage> {
+        let seconds = elapsed.as_secs_f64();
+        let earlier: HashMap<(u32, u64), &ProcessInfo> = earlier
+            .iter()
+            .map(|process| ((process.pid, process.start_time), process))
+            .collect();
+
+        later
+            .iter()
+            .filter_map(|process| {
+                let earlier = earlier.get(&(process.pid, process.start_time))?;
+                let per_second = |delta: u64| {
+                    if seconds > 0.0 {
+                        delta as f64 / seconds
+                    } else {
+                        0.0
+                    }
+                };
+                Some(ProcessUsage {
+                    pid:                 process.pid,
+                    name:                process.comm.clone(),
+                    cpu_percent:         per_second(
+                        process.cpu_ticks.saturating_sub(earlier.cpu_ticks),
+                    ) / self.ticks_per_second as f64
+                        * 100.0,
+                    rss_bytes:           process.rss_pages * self.page_size,
+                    io_bytes_per_second: process
+                        .io_bytes
+                        .zip(earlier.io_bytes)
+                        .map(|(now, then)| per_second(now.saturating_sub(then))),
+                })
+            })
+            .collect()
+    }
+
+    fn top(
+        &self,
+        usage: &[ProcessUsage],
+        by: impl Fn(&ProcessUsage) -> Option<f64>,
+    ) -> Vec<ProcessUsage> {
+        let mut top: Vec<(f64, &ProcessUsage)> = usage
+            .iter()
+            .filter_map(|process| by(process).map(|value| (value, process)))
+            .filter(|(value, _)| *value > 0.0)
+            .collect();
+        top.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+        top.into_iter()
+            .take(self.count)
+            .map(|(_, process)| process.clone())
+            .collect()
+    }
+
+    /// The values to attach, without any if `/proc` can't be read.
+    pub fn snapshot(&self) -> Values {
+        match self.usage() {
+            Ok(usage) => self.values(&usage),
+            Err(err) => {
+                log::warn!("Unable to capture the top processes: {}", err);
+                Values::new()
+            }
+        }
+    }
+
+    fn values(&self, usage: &[ProcessUsage]) -> Values {
+        let by_cpu = self.top(usage, |process| Some(process.cpu_percent));
+        let by_rss = self.top(usage, |process| Some(process.rss_bytes as f64));
+        let by_io = self.top(usage, |process| process.io_bytes_per_second);
+
+        let line =
+            |resource: &str, top: &[ProcessUsage], amount: &dyn Fn(&ProcessUsage) -> String| {
+                let processes = top
+                    .iter()
+                    .map(|process| format!("{} {} {}", process.pid, process.name, amount(process)))
+                    .join(", ");
+                format!("by {}: {}", resource, processes)
+            };
+        let text = [
+            line("CPU", &by_cpu, &|process| {
+                format!("{:.1}%", process.cpu_percent)
+            }),
+            line("RSS", &by_rss, &|process| {
+                mebibytes(process.rss_bytes as f64)
+            }),
+            line("IO", &by_io, &|process| {
+                format!(
+                    "{}/s",
+                    mebibytes(process.io_bytes_per_second.unwrap_or_default())
+                )
+            }),
+        ]
+        .join("\n");
+
+        let list =
+            |top: &[ProcessUsage]| Value::Array(top.iter().map(ProcessUsage::to_value).collect());
+        let mut values = Values::new();
+        values.insert("top_by_cpu".to_string(), list(&by_cpu));
+        values.insert("top_by_rss".to_string(), list(&by_rss));
+        values.insert("top_by_io".to_string(), list(&by_io));
+        values.insert("top_processes".to_string(), text.into());
+        values
+    }
+}
+
+fn mebibytes(bytes: f64) -> String {
+    format!("{:.1}MiB", bytes / 1024.0 / 1024.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::fixtures;
+
+    #[test]
+    fn test_top_processes() {
+        let mut top = TopProcesses::new(fixtures()).count(2);
+        top.page_size = 4096;
+        top.ticks_per_second = 100;
+
+        let earlier = process_stats(&top.proc).unwrap();
+        let mut later = earlier.clone();
+        // python3 app.worker uses 80% of a CPU and nginx workers read 10MiB/s and 5MiB/s
+        later[4].cpu_ticks += 80;
+        later[2].cpu_ticks += 10;
+        later[2].io_bytes = later[2].io_bytes.map(|io| io + 10 * 1024 * 1024);
+        later[3].io_bytes = later[3].io_bytes.map(|io| io + 5 * 1024 * 1024);
+        // An exited process and a new one are left out
+        later.remove(5);
+        later[0].start_time += 1;
+
+        let usage = top.usage_between(&earlier, &later, Duration::from_secs(1));
+        assert_eq!(usage.len(), 5);
+        assert_eq!(usage[1].io_bytes_per_second, Some(10.0 * 1024.0 * 1024.0));
+        assert_eq!(usage[4].io_bytes_per_second, None);
+
+        let values = top.values(&usage);
+        assert_eq!(
+            values["top_by_cpu"],
+            json!([
+                {"pid": 2001, "name": "python3", "cpu_percent": 80.0, "rss_bytes": 40960 * 4096, "io_bytes_per_second": 0.0},
+                {"pid": 813, "name": "nginx", "cpu_percent": 10.0, "rss_bytes": 5120 * 4096, "io_bytes_per_second": 10485760.0},
+            ])
+        );
+        assert_eq!(
+            values["top_processes"],
+            Value::from(
+                "by CPU: 2001 python3 80.0%, 813 nginx 10.0%\n\
+                 by RSS: 2001 python3 160.0MiB, 813 nginx 20.0MiB\n\
+                 by IO: 813 nginx 10.0MiB/s, 814 nginx 5.0MiB/s"
+            )
+        );
+
+        let snapshot = TopProcesses::new(ProcFs::default())
+            .interval(Duration::from_millis(10))
+            .snapshot();
+        assert!(snapshot["top_processes"]
+            .as_str()
+            .unwrap()
+            .starts_with("by CPU: "));
+        assert!(TopProcesses::new(ProcFs::at("/nonexistent"))
+            .snapshot()
+            .is_empty());
+    }
+}